@@ -4,4 +4,5 @@ mod benchmarks;
 
 criterion_main! {
     benchmarks::compression::compression,
+    benchmarks::uid::uid,
 }
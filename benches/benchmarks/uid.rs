@@ -0,0 +1,62 @@
+use criterion::{black_box, criterion_group, BenchmarkId, Criterion};
+
+use net_sync::uid::UidAllocator;
+
+fn allocator(entities: u32) -> UidAllocator<u32> {
+    let mut allocator = UidAllocator::new();
+    for entity in 0..entities {
+        allocator.allocate(entity, None);
+    }
+    allocator
+}
+
+pub fn criterion_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("UidAllocator");
+
+    for entities in [100, 10_000].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("get_by_val", entities),
+            entities,
+            |b, &entities| {
+                let allocator = allocator(entities);
+                b.iter(|| {
+                    for uid in 1..=entities {
+                        black_box(allocator.get_by_val(&uid));
+                    }
+                });
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("replace_val", entities),
+            entities,
+            |b, &entities| {
+                let mut allocator = allocator(entities);
+                b.iter(|| {
+                    for uid in 1..=entities {
+                        allocator.replace_val(uid, uid + entities);
+                        allocator.replace_val(uid + entities, uid);
+                    }
+                });
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("allocate_deallocate", entities),
+            entities,
+            |b, &entities| {
+                let mut allocator = allocator(entities);
+                b.iter(|| {
+                    for entity in 0..entities {
+                        allocator.deallocate(entity);
+                        black_box(allocator.allocate(entity, None));
+                    }
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(uid, criterion_benchmark);
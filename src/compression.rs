@@ -12,6 +12,14 @@ use crate::error::ErrorKind;
 #[cfg(feature = "lz4-compresion")]
 pub mod lz4;
 
+/// The compression strategy used by the transports when no strategy is specified.
+#[cfg(feature = "lz4-compresion")]
+pub type DefaultCompression = lz4::Lz4;
+
+/// The compression strategy used by the transports when no strategy is specified.
+#[cfg(not(feature = "lz4-compresion"))]
+pub type DefaultCompression = NoCompression;
+
 /// An adapter interface with extension methods for compression purposes in this crate.
 pub trait CompressionStrategy: Clone + Default + Send + Sync {
     /// Compresses the given buffer and returns the compression result.
@@ -21,6 +29,20 @@ pub trait CompressionStrategy: Clone + Default + Send + Sync {
     fn decompress(&self, buffer: &[u8]) -> Result<Vec<u8>, ErrorKind>;
 }
 
+/// A compression strategy that leaves the data as is.
+#[derive(Clone, Debug, Default, PartialOrd, PartialEq)]
+pub struct NoCompression;
+
+impl CompressionStrategy for NoCompression {
+    fn compress(&self, buffer: &[u8]) -> Vec<u8> {
+        buffer.to_vec()
+    }
+
+    fn decompress(&self, buffer: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+        Ok(buffer.to_vec())
+    }
+}
+
 /// A wrapper type over an implementation of CompressionStrategy.
 #[derive(Clone, Debug, PartialOrd, PartialEq)]
 pub struct ModificationCompressor<S: CompressionStrategy> {
@@ -3,6 +3,12 @@
 use std::{
     fmt::{Display, Formatter},
     io,
+    net::SocketAddr,
+};
+
+use crate::{
+    synchronisation::{CommandFrame, ComponentId, EntityId},
+    transport::ClientId,
 };
 
 /// Wrapper for all errors that can occur in `net-sync`.
@@ -12,7 +18,24 @@ pub enum ErrorKind {
     CompressionError(String),
     // An error has occurred related to data serialization.
     SerializationError(String),
+    /// An error has occurred related to framing bytes into packets.
+    FramingError(String),
     IoError(io::Error),
+    /// No client with the given id is registered with the `PostOffice`.
+    ClientNotFound(ClientId),
+    /// The transport has no connection to the given address of a registered client.
+    ConnectionNotFound(SocketAddr),
+    /// The entity with the given id of the server is unknown, or does not have the component that
+    /// is changed.
+    EntityNotFound(EntityId),
+    /// No serde-diff is registered for the components with the given id.
+    ComponentNotRegistered(ComponentId),
+    /// The snapshot of the given command frame, that a world state is relative to, is unknown.
+    BaselineNotFound(CommandFrame),
+    /// A world state of the given command frame arrived after a newer state was applied.
+    OutdatedWorldState(CommandFrame),
+    /// A command of the given command frame was pushed after a command of a newer frame.
+    OutdatedCommand(CommandFrame),
 }
 
 impl Display for ErrorKind {
@@ -23,6 +46,40 @@ impl Display for ErrorKind {
             ErrorKind::SerializationError(e) => {
                 write!(fmt, "Serialization error occurred: {:?}", e)
             }
+            ErrorKind::FramingError(e) => write!(fmt, "Framing error occurred: {:?}", e),
+            ErrorKind::ClientNotFound(client_id) => {
+                write!(fmt, "Client with id {} does not exist", client_id)
+            }
+            ErrorKind::ConnectionNotFound(addr) => {
+                write!(fmt, "No connection to {} exists", addr)
+            }
+            ErrorKind::EntityNotFound(entity_id) => {
+                write!(fmt, "Entity with id {} is not mapped", entity_id)
+            }
+            ErrorKind::ComponentNotRegistered(component_id) => {
+                write!(fmt, "Component with id {} is not registered", component_id)
+            }
+            ErrorKind::BaselineNotFound(command_frame) => {
+                write!(
+                    fmt,
+                    "Snapshot of command frame {} is unknown",
+                    command_frame
+                )
+            }
+            ErrorKind::OutdatedWorldState(command_frame) => {
+                write!(
+                    fmt,
+                    "World state of command frame {} is older than the applied state",
+                    command_frame
+                )
+            }
+            ErrorKind::OutdatedCommand(command_frame) => {
+                write!(
+                    fmt,
+                    "Command of command frame {} is older than the buffered commands",
+                    command_frame
+                )
+            }
         }
     }
 }
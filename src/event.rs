@@ -1,9 +1,21 @@
-use crate::transport::ClientId;
+use crate::{
+    error::ErrorKind,
+    transport::{ClientId, DisconnectReason, RejectReason},
+};
 use std::{collections::VecDeque, net::SocketAddr};
 
 pub enum NetworkEvent {
     Connected(SocketAddr),
-    Disconnected(SocketAddr, ClientId),
+    /// The connection was closed.
+    Disconnected(SocketAddr, ClientId, DisconnectReason),
+    /// The connection of a client was closed before the server accepted it.
+    ConnectionFailed(SocketAddr, DisconnectReason),
+    /// A received packet could not be processed, the connection itself is still usable.
+    Error(SocketAddr, ErrorKind),
+    /// The handshake of a client was refused by the server.
+    Rejected(SocketAddr, RejectReason),
+    /// A client that lost its connection resumed its session under the same id.
+    Reconnected(SocketAddr, ClientId),
 }
 
 pub struct NetworkEventQueue {
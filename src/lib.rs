@@ -2,6 +2,7 @@ pub mod clock;
 pub mod compression;
 pub mod error;
 pub mod event;
+pub mod serialization;
 pub mod synchronisation;
 pub mod tracker;
 pub mod transport;
@@ -44,6 +45,7 @@ pub mod re_exports {
     }
 
     /// A re-export of the [bit-set](https://crates.io/crates/bit-set) create.
+    #[cfg(feature = "bincode-serialization")]
     pub mod bincode {
         pub use bincode::*;
     }
@@ -0,0 +1,75 @@
+//! Module that provides different serializers to be used for serializing data.
+//!
+//! By default, a number of serializers are supplied that can be used by turning on the feature flag.
+//! You might want to create your own serialization strategy by implementing: [SerializationStrategy](./trait.SerializationStrategy.html).
+//!
+//! | Feature | Description |
+//! | :-- | :-- |
+//! | `bincode-serialization` | serialization using [bincode](https://crates.io/crates/bincode) (enabled by default) .|
+//! | `rmp-serialization` | serialization using [MessagePack](https://crates.io/crates/rmp-serde).|
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_diff::SerdeDiff;
+
+use crate::error::ErrorKind;
+
+#[cfg(feature = "bincode-serialization")]
+pub mod bincode;
+#[cfg(feature = "rmp-serialization")]
+pub mod rmp;
+
+#[cfg(not(any(feature = "bincode-serialization", feature = "rmp-serialization")))]
+compile_error!(
+    "Either the `bincode-serialization` or `rmp-serialization` feature must be enabled."
+);
+
+/// The serialization strategy used when no strategy is specified.
+#[cfg(feature = "bincode-serialization")]
+pub type DefaultSerialization = self::bincode::Bincode;
+
+/// The serialization strategy used when no strategy is specified.
+#[cfg(all(not(feature = "bincode-serialization"), feature = "rmp-serialization"))]
+pub type DefaultSerialization = self::rmp::Rmp;
+
+/// An adapter interface with extension methods for serialization purposes in this crate.
+pub trait SerializationStrategy: Clone + Default + Send + Sync + 'static {
+    /// Serializes the given value and returns the serialization result.
+    fn serialize<T: ?Sized + Serialize>(&self, value: &T) -> Result<Vec<u8>, ErrorKind>;
+
+    /// Deserializes the given buffer and returns the deserialized value.
+    fn deserialize<T: DeserializeOwned>(&self, buffer: &[u8]) -> Result<T, ErrorKind>;
+
+    /// Deserializes the serde-diff in the given buffer and applies it to the target.
+    fn apply_diff<T: SerdeDiff>(&self, buffer: &[u8], target: &mut T) -> Result<(), ErrorKind>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::serialization::SerializationStrategy;
+
+    fn round_trip<S: SerializationStrategy>(serialization: S) {
+        let mut value = HashMap::new();
+        value.insert(1u32, (String::from("entity"), vec![1.5f32, 2.5]));
+
+        let serialized = serialization.serialize(&value).unwrap();
+        let deserialized: HashMap<u32, (String, Vec<f32>)> =
+            serialization.deserialize(&serialized).unwrap();
+
+        assert_eq!(deserialized, value);
+        assert!(serialization.deserialize::<u64>(&[]).is_err());
+    }
+
+    #[cfg(feature = "bincode-serialization")]
+    #[test]
+    fn bincode_round_trip() {
+        round_trip(crate::serialization::bincode::Bincode);
+    }
+
+    #[cfg(feature = "rmp-serialization")]
+    #[test]
+    fn rmp_round_trip() {
+        round_trip(crate::serialization::rmp::Rmp);
+    }
+}
@@ -0,0 +1,28 @@
+use ::bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_diff::{Apply, SerdeDiff};
+
+use crate::{error::ErrorKind, serialization::SerializationStrategy};
+
+/// A serialization strategy using bincode.
+#[derive(Clone, Debug, Default)]
+pub struct Bincode;
+
+impl SerializationStrategy for Bincode {
+    fn serialize<T: ?Sized + Serialize>(&self, value: &T) -> Result<Vec<u8>, ErrorKind> {
+        ::bincode::serialize(value).map_err(|e| ErrorKind::SerializationError(e.to_string()))
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, buffer: &[u8]) -> Result<T, ErrorKind> {
+        ::bincode::deserialize(buffer).map_err(|e| ErrorKind::SerializationError(e.to_string()))
+    }
+
+    fn apply_diff<T: SerdeDiff>(&self, buffer: &[u8], target: &mut T) -> Result<(), ErrorKind> {
+        // The same options as `bincode::serialize`.
+        ::bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .deserialize_seed(Apply::deserializable(target), buffer)
+            .map_err(|e| ErrorKind::SerializationError(e.to_string()))
+    }
+}
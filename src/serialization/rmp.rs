@@ -0,0 +1,24 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_diff::{Apply, SerdeDiff};
+
+use crate::{error::ErrorKind, serialization::SerializationStrategy};
+
+/// A serialization strategy using MessagePack.
+#[derive(Clone, Debug, Default)]
+pub struct Rmp;
+
+impl SerializationStrategy for Rmp {
+    fn serialize<T: ?Sized + Serialize>(&self, value: &T) -> Result<Vec<u8>, ErrorKind> {
+        rmp_serde::to_vec(value).map_err(|e| ErrorKind::SerializationError(e.to_string()))
+    }
+
+    fn deserialize<T: DeserializeOwned>(&self, buffer: &[u8]) -> Result<T, ErrorKind> {
+        rmp_serde::from_read_ref(buffer).map_err(|e| ErrorKind::SerializationError(e.to_string()))
+    }
+
+    fn apply_diff<T: SerdeDiff>(&self, buffer: &[u8], target: &mut T) -> Result<(), ErrorKind> {
+        let mut deserializer = rmp_serde::Deserializer::new(buffer);
+        Apply::apply(&mut deserializer, target)
+            .map_err(|e| ErrorKind::SerializationError(e.to_string()))
+    }
+}
@@ -3,9 +3,21 @@
 pub use self::{
     client_command_buffer::{ClientCommandBuffer, ClientCommandBufferEntry},
     command_frame_ticker::CommandFrameTicker,
+    component_diffs::ComponentDiffs,
+    interpolation::{Interpolate, InterpolationBuffer, InterpolationConfig},
+    lag_compensation::{LagCompensationHistory, Rewound, DEFAULT_LAG_COMPENSATION_FRAMES},
     modified_components_buffer::ModifiedComponentsBuffer,
+    network_entity_map::{MappedEntities, NetworkEntityMap},
+    network_world::{apply_world_state, AppliedWorldState, HashMapWorld, NetworkWorld},
+    predicted_spawns::PredictedSpawns,
+    reconciliation::{Reconciler, Rollback},
     resimmulation_buffer::{ResimulationBuffer, ResimulationBufferEntry},
     server_command_buffer::{PushResult, ServerCommandBuffer},
+    snapshot::{SnapshotHistory, WorldSnapshot, DEFAULT_SNAPSHOT_CAPACITY},
+    speed_controller::{SimulationSpeedConfig, SimulationSpeedController},
+    time_sync::{
+        timestamp, ClockSynchronizer, TimeSyncResponse, TimeSyncSample, DEFAULT_TIME_SYNC_SAMPLES,
+    },
 };
 use crate::uid::Uid;
 use serde::{Deserialize, Serialize};
@@ -17,9 +29,19 @@ use std::{
 
 mod client_command_buffer;
 mod command_frame_ticker;
+mod component_diffs;
+mod interpolation;
+mod lag_compensation;
 mod modified_components_buffer;
+mod network_entity_map;
+mod network_world;
+mod predicted_spawns;
+mod reconciliation;
 mod resimmulation_buffer;
 mod server_command_buffer;
+mod snapshot;
+mod speed_controller;
+mod time_sync;
 
 pub type CommandFrame = u32;
 
@@ -33,13 +55,17 @@ pub struct WorldState {
     /// When the server receives the client `command frame` it calculates with its own `command frame` the offset with respect to the server.
     /// With this calculated value the client can see if the simulation should be faster or slower based on the current simulation.
     pub command_frame_offset: i32,
+    /// The command frame of the snapshot this state is relative to.
+    pub baseline: Option<CommandFrame>,
+    /// True if this state contains the full world, entities it does not insert no longer exist.
+    pub is_full: bool,
     /// The removed entity ids.
     pub removed: HashSet<EntityId>,
     /// The inserted entities.
     pub inserted: HashSet<EntityInsert>,
-    /// The changed components and their differences.
+    /// The changed components and their serde-diff differences with the baseline.
     pub changed: HashSet<ComponentChanged>,
-    /// The added components.
+    /// The added components, and the changed components without a registered serde-diff.
     pub component_added: HashSet<ComponentAdded>,
     /// The removed components.
     pub component_removed: HashSet<ComponentRemoved>,
@@ -58,6 +84,8 @@ impl WorldState {
             // The client command_frame offset is different for each client.
             // This offset will be set when the state is sent to a certain client.
             command_frame_offset: 0,
+            baseline: None,
+            is_full: false,
         }
     }
 
@@ -4,9 +4,12 @@ use std::{
         vec_deque::{Iter, IterMut},
         VecDeque,
     },
+    marker::PhantomData,
 };
 
 use crate::{
+    error::ErrorKind,
+    serialization::{DefaultSerialization, SerializationStrategy},
     synchronisation::{CommandFrame, NetworkCommand},
     tracker::ClientChangeTracker,
     uid::Uid,
@@ -44,20 +47,33 @@ impl<ClientToServerCommand: NetworkCommand> ClientCommandBufferEntry<ClientToSer
     }
 }
 
-pub struct ClientCommandBuffer<ClientToServerCommand: NetworkCommand> {
+/// Buffers the commands of the client together with the component values, serialized with
+/// `Serialization`, before and after the command was applied.
+pub struct ClientCommandBuffer<
+    ClientToServerCommand: NetworkCommand,
+    Serialization: SerializationStrategy = DefaultSerialization,
+> {
     commands: VecDeque<ClientCommandBufferEntry<ClientToServerCommand>>,
     max_command_frame_capacity: u32,
     last_seen_command_frame: CommandFrame,
     oldest_seen_command_frame: CommandFrame,
+    errors: Vec<(Uid, ErrorKind)>,
+    serialization: PhantomData<Serialization>,
 }
 
-impl<ClientToServerCommand: NetworkCommand> ClientCommandBuffer<ClientToServerCommand> {
-    pub fn with_capacity(capacity: u32) -> ClientCommandBuffer<ClientToServerCommand> {
+impl<ClientToServerCommand: NetworkCommand, Serialization: SerializationStrategy>
+    ClientCommandBuffer<ClientToServerCommand, Serialization>
+{
+    pub fn with_capacity(
+        capacity: u32,
+    ) -> ClientCommandBuffer<ClientToServerCommand, Serialization> {
         ClientCommandBuffer {
             commands: VecDeque::new(),
             max_command_frame_capacity: capacity,
             last_seen_command_frame: 0,
             oldest_seen_command_frame: 0,
+            errors: Vec::new(),
+            serialization: PhantomData,
         }
     }
 
@@ -86,7 +102,11 @@ impl<ClientToServerCommand: NetworkCommand> ClientCommandBuffer<ClientToServerCo
         entity_id: Uid,
         component_type: TypeId,
     ) {
-        assert!(command_frame >= self.last_seen_command_frame);
+        if command_frame < self.last_seen_command_frame {
+            self.errors
+                .push((entity_id, ErrorKind::OutdatedCommand(command_frame)));
+            return;
+        }
 
         self.last_seen_command_frame = command_frame;
 
@@ -106,7 +126,7 @@ impl<ClientToServerCommand: NetworkCommand> ClientCommandBuffer<ClientToServerCo
 
                 self.clear_old(removed_command.command_frame);
 
-                if let Some(oldest) = self.commands.get(self.commands.len() - 1) {
+                if let Some(oldest) = self.commands.back() {
                     self.oldest_seen_command_frame = oldest.command_frame;
                 }
             }
@@ -124,7 +144,7 @@ impl<ClientToServerCommand: NetworkCommand> ClientCommandBuffer<ClientToServerCo
 
     fn clear_old(&mut self, command_frame: CommandFrame) {
         // pop all commands with the same synchronisation frame as the removed synchronisation.
-        while let Some(command) = self.commands.get(self.commands.len() - 1) {
+        while let Some(command) = self.commands.back() {
             if command_frame == command.command_frame {
                 self.commands
                     .pop_back()
@@ -153,9 +173,18 @@ impl<ClientToServerCommand: NetworkCommand> ClientCommandBuffer<ClientToServerCo
             iter_down_to_frame: self.last_seen_command_frame - frames_in_history,
         }
     }
+
+    /// Drains the entities whose modifications could not be tracked, with the reason.
+    pub fn drain_errors(&mut self) -> std::vec::Drain<'_, (Uid, ErrorKind)> {
+        self.errors.drain(..)
+    }
 }
 
-impl<C: NetworkCommand> ClientChangeTracker<C> for ClientCommandBuffer<C> {
+impl<C: NetworkCommand, Serialization: SerializationStrategy> ClientChangeTracker<C>
+    for ClientCommandBuffer<C, Serialization>
+{
+    type Serialization = Serialization;
+
     fn push(
         &mut self,
         command: C,
@@ -174,6 +203,10 @@ impl<C: NetworkCommand> ClientChangeTracker<C> for ClientCommandBuffer<C> {
             component_type,
         );
     }
+
+    fn push_error(&mut self, entity_id: Uid, error: ErrorKind) {
+        self.errors.push((entity_id, error));
+    }
 }
 
 pub struct CommandIterMut<'a, ClientToServerCommand: NetworkCommand> {
@@ -202,8 +235,10 @@ impl<'a, ClientToServerCommand: NetworkCommand> Iterator
 #[cfg(test)]
 mod test {
     use crate::{
-        synchronisation::{client_command_buffer::ClientCommandBuffer, ClientCommandBufferEntry},
-        transport::{NetworkCommand, NetworkMessage},
+        error::ErrorKind,
+        synchronisation::{
+            client_command_buffer::ClientCommandBuffer, NetworkCommand, NetworkMessage,
+        },
     };
     use std::any::TypeId;
 
@@ -318,6 +353,28 @@ mod test {
         assert_eq!(collected_frames, vec![2, 2]);
     }
 
+    #[test]
+    fn outdated_command_is_rejected_with_error() {
+        let mut buffer = ClientCommandBuffer::<u32>::with_capacity(3);
+        push_command(&mut buffer, 1, 2);
+        push_command(&mut buffer, 2, 1);
+
+        assert_eq!(buffer.commands.len(), 1);
+        assert!(matches!(
+            buffer.drain_errors().next(),
+            Some((1, ErrorKind::OutdatedCommand(1)))
+        ));
+    }
+
+    #[test]
+    fn clear_old_on_empty_buffer_does_nothing() {
+        let mut buffer = ClientCommandBuffer::<u32>::with_capacity(3);
+
+        buffer.clear_old(1);
+
+        assert!(buffer.commands.is_empty());
+    }
+
     fn push_command(buffer: &mut ClientCommandBuffer<u32>, command: u32, command_frame: u32) {
         buffer.push(
             command,
@@ -40,22 +40,42 @@ impl CommandFrameTicker {
     }
 
     pub fn try_tick(&mut self) -> bool {
-        let can_tick = self.can_tick();
+        self.try_tick_at(Instant::now())
+    }
+
+    /// Ticks if a frame duration passed between the last execution and `now`.
+    pub fn try_tick_at(&mut self, now: Instant) -> bool {
+        let can_tick = self.can_tick_at(now);
 
         if can_tick {
-            self.advance();
+            self.advance_at(now);
         }
 
         can_tick
     }
 
     pub fn can_tick(&self) -> bool {
-        self.last_execution.elapsed() >= Duration::from_millis(self.simulation_speed as u64)
+        self.can_tick_at(Instant::now())
+    }
+
+    /// Returns true if a frame duration passed between the last execution and `now`.
+    pub fn can_tick_at(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_execution) >= self.frame_duration()
+    }
+
+    /// Returns the duration of a command frame, the simulation speed is in milliseconds.
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f32(self.simulation_speed.max(0.) / 1000.)
     }
 
     pub fn advance(&mut self) {
+        self.advance_at(Instant::now())
+    }
+
+    /// Advances to the next command frame, that is executed at `now`.
+    pub fn advance_at(&mut self, now: Instant) {
         self.command_frame += 1;
-        self.last_execution = Instant::now()
+        self.last_execution = now
     }
 
     pub fn adjust_simulation(&mut self, new: f32) {
@@ -85,6 +105,15 @@ mod test {
         assert_eq!(ticker.simulation_speed, 10.5);
     }
 
+    #[test]
+    fn frame_duration_keeps_fractional_milliseconds() {
+        let mut ticker = CommandFrameTicker::new(10.);
+        ticker.adjust_simulation(10.5);
+
+        assert!(ticker.frame_duration() > Duration::from_millis(10));
+        assert!(ticker.frame_duration() < Duration::from_millis(11));
+    }
+
     #[test]
     fn can_tick_returns_true() {
         let mut ticker = CommandFrameTicker::new(100.);
@@ -0,0 +1,166 @@
+use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_diff::{Config, FieldPathMode, SerdeDiff};
+
+use crate::{error::ErrorKind, serialization::SerializationStrategy, synchronisation::ComponentId};
+
+/// Computes and applies the serde-diff of the serialized components of a single type.
+trait ComponentDiff: Send + Sync {
+    fn diff(&self, old: &[u8], new: &[u8]) -> Result<Option<Vec<u8>>, ErrorKind>;
+
+    fn apply(&self, data: &[u8], diff: &[u8]) -> Result<Vec<u8>, ErrorKind>;
+}
+
+struct SerdeComponentDiff<T, S> {
+    serialization: S,
+    component: PhantomData<fn() -> T>,
+}
+
+impl<T, S> ComponentDiff for SerdeComponentDiff<T, S>
+where
+    T: SerdeDiff + Serialize + DeserializeOwned,
+    S: SerializationStrategy,
+{
+    fn diff(&self, old: &[u8], new: &[u8]) -> Result<Option<Vec<u8>>, ErrorKind> {
+        let old = self.serialization.deserialize::<T>(old)?;
+        let new = self.serialization.deserialize::<T>(new)?;
+
+        let diff = Config::new()
+            .with_field_path_mode(FieldPathMode::Index)
+            .serializable_diff(&old, &new);
+        let serialized = self.serialization.serialize(&diff)?;
+
+        if diff.has_changes() {
+            Ok(Some(serialized))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn apply(&self, data: &[u8], diff: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+        let mut component = self.serialization.deserialize::<T>(data)?;
+        self.serialization.apply_diff(diff, &mut component)?;
+        self.serialization.serialize(&component)
+    }
+}
+
+/// The component types whose changes are sent as serde-diff differences instead of whole
+/// components.
+///
+/// The server and the client register the same types under the same component ids. Changed
+/// components of a type that is not registered are sent whole, as added components.
+#[derive(Clone, Default)]
+pub struct ComponentDiffs {
+    diffs: HashMap<ComponentId, Arc<dyn ComponentDiff>>,
+}
+
+impl ComponentDiffs {
+    pub fn new() -> ComponentDiffs {
+        ComponentDiffs::default()
+    }
+
+    /// Registers the type of the components with the given id, the components are serialized
+    /// with the given serialization strategy.
+    pub fn register<T, S>(&mut self, component_id: ComponentId, serialization: S)
+    where
+        T: SerdeDiff + Serialize + DeserializeOwned + 'static,
+        S: SerializationStrategy,
+    {
+        self.diffs.insert(
+            component_id,
+            Arc::new(SerdeComponentDiff::<T, S> {
+                serialization,
+                component: PhantomData,
+            }),
+        );
+    }
+
+    /// Returns true if the type of the components with the given id is registered.
+    pub fn is_registered(&self, component_id: ComponentId) -> bool {
+        self.diffs.contains_key(&component_id)
+    }
+
+    /// Returns the serialized serde-diff that turns the `old` component into the `new` one, or
+    /// `None` if the components are equal.
+    pub fn diff(
+        &self,
+        component_id: ComponentId,
+        old: &[u8],
+        new: &[u8],
+    ) -> Result<Option<Vec<u8>>, ErrorKind> {
+        self.get(component_id)?.diff(old, new)
+    }
+
+    /// Applies a serialized serde-diff to the component, and returns the changed component.
+    pub fn apply(
+        &self,
+        component_id: ComponentId,
+        data: &[u8],
+        diff: &[u8],
+    ) -> Result<Vec<u8>, ErrorKind> {
+        self.get(component_id)?.apply(data, diff)
+    }
+
+    fn get(&self, component_id: ComponentId) -> Result<&Arc<dyn ComponentDiff>, ErrorKind> {
+        self.diffs
+            .get(&component_id)
+            .ok_or(ErrorKind::ComponentNotRegistered(component_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_diff::SerdeDiff;
+
+    use crate::{
+        error::ErrorKind,
+        serialization::{DefaultSerialization, SerializationStrategy},
+        synchronisation::ComponentDiffs,
+    };
+
+    #[derive(SerdeDiff, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Inventory {
+        gold: u32,
+        items: Vec<u32>,
+    }
+
+    #[test]
+    fn diff_of_changed_field_is_smaller_than_component() {
+        let serialization = DefaultSerialization::default();
+        let mut diffs = ComponentDiffs::new();
+        diffs.register::<Inventory, _>(1, serialization.clone());
+
+        let old = Inventory {
+            gold: 10,
+            items: (0..100).collect(),
+        };
+        let mut new = old.clone();
+        new.gold = 20;
+
+        let old = serialization.serialize(&old).unwrap();
+        let new_serialized = serialization.serialize(&new).unwrap();
+
+        let diff = diffs.diff(1, &old, &new_serialized).unwrap().unwrap();
+        assert!(diff.len() < new_serialized.len());
+        assert_eq!(diffs.diff(1, &old, &old).unwrap(), None);
+
+        let applied = diffs.apply(1, &old, &diff).unwrap();
+        assert_eq!(
+            serialization.deserialize::<Inventory>(&applied).unwrap(),
+            new
+        );
+    }
+
+    #[test]
+    fn unregistered_component_can_not_be_diffed() {
+        let diffs = ComponentDiffs::new();
+
+        assert!(!diffs.is_registered(1));
+        assert!(matches!(
+            diffs.diff(1, &[], &[]),
+            Err(ErrorKind::ComponentNotRegistered(1))
+        ));
+    }
+}
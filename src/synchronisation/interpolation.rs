@@ -0,0 +1,401 @@
+use std::collections::VecDeque;
+
+use log::error;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    serialization::{DefaultSerialization, SerializationStrategy},
+    synchronisation::{CommandFrame, ComponentId, EntityId, WorldSnapshot},
+};
+
+/// A component value that can be blended between two snapshots.
+pub trait Interpolate {
+    /// Returns the value at `t` between `self` (0.0) and `other` (1.0).
+    ///
+    /// `t` is larger than 1.0 when the value is extrapolated.
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t as f64
+    }
+}
+
+/// Configuration of the `InterpolationBuffer`.
+#[derive(Clone, Debug)]
+pub struct InterpolationConfig {
+    /// The number of snapshots that are kept.
+    pub capacity: usize,
+    /// The number of command frames the render time stays behind the newest snapshot.
+    pub delay: f32,
+    /// The number of command frames the render time may run ahead of the newest snapshot when
+    /// snapshots are late. Components are extrapolated in this period.
+    pub max_extrapolation: f32,
+    /// The number of command frames the render time may drift from `delay` frames behind the
+    /// newest snapshot before it is steered back.
+    pub drift_tolerance: f32,
+    /// The fraction of the drift that is corrected on every advance, between 0.0 and 1.0.
+    pub correction: f32,
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        InterpolationConfig {
+            capacity: 32,
+            delay: 2.,
+            max_extrapolation: 2.,
+            drift_tolerance: 1.,
+            correction: 0.1,
+        }
+    }
+}
+
+/// Buffers the decoded world states the client received, and samples components at a render
+/// time that lags a configurable delay behind the newest snapshot.
+///
+/// States arrive at the server tick rate, by rendering a bit in the past there are
+/// usually two snapshots to interpolate the components between.
+/// If snapshots are late, components are extrapolated up to `max_extrapolation` frames.
+pub struct InterpolationBuffer<Serialization: SerializationStrategy = DefaultSerialization> {
+    snapshots: VecDeque<WorldSnapshot>,
+    config: InterpolationConfig,
+    render_time: Option<f32>,
+    serialization: Serialization,
+}
+
+impl InterpolationBuffer {
+    pub fn new() -> InterpolationBuffer {
+        InterpolationBuffer::with_config(InterpolationConfig::default(), Default::default())
+    }
+}
+
+impl<Serialization: SerializationStrategy> InterpolationBuffer<Serialization> {
+    /// Returns a new `InterpolationBuffer` that deserializes components with the given
+    /// serialization strategy.
+    pub fn with_config(
+        config: InterpolationConfig,
+        serialization: Serialization,
+    ) -> InterpolationBuffer<Serialization> {
+        InterpolationBuffer {
+            snapshots: VecDeque::with_capacity(config.capacity),
+            config,
+            render_time: None,
+            serialization,
+        }
+    }
+
+    pub fn config(&self) -> &InterpolationConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: InterpolationConfig) {
+        self.config = config;
+    }
+
+    /// Adds a decoded snapshot, for example the one returned by `SnapshotHistory::apply`.
+    ///
+    /// Snapshots may arrive out of order, a snapshot older than the oldest buffered snapshot is
+    /// ignored when the buffer is full.
+    pub fn push(&mut self, snapshot: WorldSnapshot) {
+        let command_frame = snapshot.command_frame();
+
+        match self
+            .snapshots
+            .binary_search_by_key(&command_frame, |snapshot| snapshot.command_frame())
+        {
+            Ok(index) => self.snapshots[index] = snapshot,
+            Err(0) if self.snapshots.len() >= self.config.capacity => return,
+            Err(index) => self.snapshots.insert(index, snapshot),
+        }
+
+        while self.snapshots.len() > self.config.capacity {
+            self.snapshots.pop_front();
+        }
+
+        if self.render_time.is_none() {
+            self.render_time = Some(command_frame as f32 - self.config.delay);
+        }
+    }
+
+    /// Advances the render time with the given number of command frames.
+    ///
+    /// When the render time drifted more than `drift_tolerance` frames from `delay` frames behind
+    /// the newest snapshot, it runs faster or slower to catch up, but it never runs backwards.
+    /// The render time is kept between the oldest snapshot and the extrapolation limit.
+    pub fn advance(&mut self, frames: f32) {
+        let (oldest, newest) = match (self.snapshots.front(), self.snapshots.back()) {
+            (Some(oldest), Some(newest)) => (oldest.command_frame(), newest.command_frame()),
+            _ => return,
+        };
+
+        if let Some(render_time) = self.render_time.as_mut() {
+            let mut step = frames;
+            let drift = (newest as f32 - self.config.delay) - (*render_time + frames);
+
+            if drift.abs() > self.config.drift_tolerance {
+                step = (step + drift * self.config.correction).max(0.);
+            }
+
+            *render_time = (*render_time + step)
+                .min(newest as f32 + self.config.max_extrapolation)
+                .max(oldest as f32);
+        }
+    }
+
+    /// Returns the command frame, including the fraction, that is currently rendered.
+    pub fn render_time(&self) -> Option<f32> {
+        self.render_time
+    }
+
+    /// Returns true if the render time passed the newest snapshot.
+    pub fn is_extrapolating(&self) -> bool {
+        match (self.render_time, self.newest_command_frame()) {
+            (Some(render_time), Some(newest)) => render_time > newest as f32,
+            _ => false,
+        }
+    }
+
+    /// Returns the command frame of the newest snapshot.
+    pub fn newest_command_frame(&self) -> Option<CommandFrame> {
+        self.snapshots
+            .back()
+            .map(|snapshot| snapshot.command_frame())
+    }
+
+    /// Returns the component of the given entity at the current render time.
+    pub fn sample<T: Interpolate + DeserializeOwned>(
+        &self,
+        entity_id: EntityId,
+        component_id: ComponentId,
+    ) -> Option<T> {
+        self.sample_at(entity_id, component_id, self.render_time?)
+    }
+
+    /// Returns the component of the given entity at the given render time.
+    ///
+    /// The component is interpolated between the snapshots around `render_time`, or extrapolated
+    /// from the two newest snapshots when `render_time` passed the newest snapshot.
+    /// If only one snapshot contains the component, its value is returned as is.
+    pub fn sample_at<T: Interpolate + DeserializeOwned>(
+        &self,
+        entity_id: EntityId,
+        component_id: ComponentId,
+        render_time: f32,
+    ) -> Option<T> {
+        let containing = self
+            .snapshots
+            .iter()
+            .filter(|snapshot| snapshot.component(entity_id, component_id).is_some())
+            .collect::<Vec<&WorldSnapshot>>();
+
+        let after = containing
+            .iter()
+            .position(|snapshot| snapshot.command_frame() as f32 > render_time)
+            .unwrap_or(containing.len());
+
+        let (from, to) = match after {
+            0 => return self.deserialize(containing.first()?, entity_id, component_id),
+            1 if containing.len() == 1 => {
+                return self.deserialize(containing[0], entity_id, component_id)
+            }
+            after if after == containing.len() => (containing[after - 2], containing[after - 1]),
+            after => (containing[after - 1], containing[after]),
+        };
+
+        let from_value = self.deserialize::<T>(from, entity_id, component_id)?;
+        let to_value = self.deserialize::<T>(to, entity_id, component_id)?;
+
+        let render_time = render_time
+            .min(to.command_frame() as f32 + self.config.max_extrapolation)
+            .max(from.command_frame() as f32);
+        let t = (render_time - from.command_frame() as f32)
+            / (to.command_frame() - from.command_frame()) as f32;
+
+        Some(from_value.interpolate(&to_value, t))
+    }
+
+    fn deserialize<T: DeserializeOwned>(
+        &self,
+        snapshot: &WorldSnapshot,
+        entity_id: EntityId,
+        component_id: ComponentId,
+    ) -> Option<T> {
+        let data = snapshot.component(entity_id, component_id)?;
+
+        match self.serialization.deserialize(data) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                error!(
+                    "Error occurred when deserializing component for interpolation. Reason: {:?}",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Removes all snapshots and resets the render time.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.render_time = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+impl Default for InterpolationBuffer {
+    fn default() -> Self {
+        InterpolationBuffer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        serialization::{DefaultSerialization, SerializationStrategy},
+        synchronisation::{
+            ComponentData, Interpolate, InterpolationBuffer, InterpolationConfig, WorldSnapshot,
+        },
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    impl Interpolate for Position {
+        fn interpolate(&self, other: &Self, t: f32) -> Self {
+            Position {
+                x: self.x.interpolate(&other.x, t),
+                y: self.y.interpolate(&other.y, t),
+            }
+        }
+    }
+
+    fn snapshot(command_frame: u32, x: f32) -> WorldSnapshot {
+        let data = DefaultSerialization::default()
+            .serialize(&Position { x, y: 0. })
+            .unwrap();
+
+        let mut snapshot = WorldSnapshot::new(command_frame);
+        snapshot.insert_entity(1, vec![ComponentData::new(1, data)]);
+        snapshot
+    }
+
+    fn buffer() -> InterpolationBuffer {
+        InterpolationBuffer::with_config(
+            InterpolationConfig {
+                capacity: 4,
+                delay: 2.,
+                max_extrapolation: 1.,
+                drift_tolerance: 1.,
+                correction: 0.5,
+            },
+            DefaultSerialization::default(),
+        )
+    }
+
+    #[test]
+    fn interpolates_between_snapshots_behind_newest() {
+        let mut buffer = buffer();
+        buffer.push(snapshot(10, 0.));
+        buffer.push(snapshot(12, 20.));
+        buffer.push(snapshot(11, 10.));
+
+        // The render time starts `delay` frames behind the first snapshot.
+        assert_eq!(buffer.render_time(), Some(8.));
+        buffer.advance(2.5);
+        assert_eq!(buffer.render_time(), Some(10.5));
+
+        assert_eq!(
+            buffer.sample::<Position>(1, 1),
+            Some(Position { x: 5., y: 0. })
+        );
+        assert_eq!(
+            buffer.sample_at::<Position>(1, 1, 11.75),
+            Some(Position { x: 17.5, y: 0. })
+        );
+        assert!(!buffer.is_extrapolating());
+    }
+
+    #[test]
+    fn extrapolates_late_snapshots_up_to_limit() {
+        let mut buffer = buffer();
+        buffer.push(snapshot(10, 0.));
+        buffer.push(snapshot(11, 10.));
+
+        buffer.advance(10.);
+
+        // The render time is limited to one frame after the newest snapshot.
+        assert_eq!(buffer.render_time(), Some(12.));
+        assert!(buffer.is_extrapolating());
+        assert_eq!(
+            buffer.sample::<Position>(1, 1),
+            Some(Position { x: 20., y: 0. })
+        );
+    }
+
+    #[test]
+    fn render_time_is_steered_towards_delay_behind_newest() {
+        let mut buffer = buffer();
+        buffer.push(snapshot(10, 0.));
+        assert_eq!(buffer.render_time(), Some(8.));
+
+        // Snapshots arrive faster than the render time advances, it speeds up to catch up.
+        buffer.push(snapshot(20, 10.));
+        buffer.advance(1.);
+        assert_eq!(buffer.render_time(), Some(13.5));
+        buffer.advance(1.);
+        assert_eq!(buffer.render_time(), Some(16.25));
+
+        // Within the tolerance the render time advances as is.
+        buffer.advance(1.);
+        buffer.advance(0.5);
+        buffer.advance(1.);
+        assert_eq!(buffer.render_time(), Some(18.75));
+
+        // Snapshots stop arriving, the render time slows down instead of running ahead.
+        buffer.advance(1.);
+        assert_eq!(buffer.render_time(), Some(18.875));
+        buffer.advance(1.);
+        assert_eq!(buffer.render_time(), Some(18.9375));
+        assert!(buffer.render_time().unwrap() < 20.);
+        assert!(!buffer.is_extrapolating());
+    }
+
+    #[test]
+    fn drops_oldest_snapshots_over_capacity() {
+        let mut buffer = buffer();
+
+        for frame in 1..=6 {
+            buffer.push(snapshot(frame, frame as f32));
+        }
+
+        assert_eq!(buffer.len(), 4);
+
+        // Too old to be buffered.
+        buffer.push(snapshot(1, 1.));
+        assert_eq!(buffer.len(), 4);
+
+        // The render time can not go back further than the oldest snapshot.
+        buffer.advance(0.);
+        assert_eq!(buffer.render_time(), Some(3.));
+        assert_eq!(buffer.sample::<f32>(2, 1), None);
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    any::TypeId,
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+};
+
+use crate::{
+    serialization::{DefaultSerialization, SerializationStrategy},
+    synchronisation::{modified_components_buffer::EntryIdentifier, CommandFrame},
+    tracker::ServerChangeTracker,
+    uid::Uid,
+};
+
+/// The number of command frames a `LagCompensationHistory` keeps by default.
+pub const DEFAULT_LAG_COMPENSATION_FRAMES: u32 = 64;
+
+/// Keeps the components, serialized with `Serialization`, as they were before they got modified
+/// during the last command frames, so that the server can rewind them to the world a client saw.
+///
+/// Like the `ModifiedComponentsBuffer`, the unchanged data of a component is stored under the
+/// frame it is modified in. The value of a component at the end of frame `n` is therefore the
+/// first value stored after frame `n`. A component that is not modified after frame `n` still
+/// has the value of frame `n`, and does not need to be rewound.
+pub struct LagCompensationHistory<Serialization: SerializationStrategy = DefaultSerialization> {
+    entries: BTreeMap<CommandFrame, HashMap<EntryIdentifier, Vec<u8>>>,
+    oldest_frame: CommandFrame,
+    capacity: u32,
+    interpolation_delay: u32,
+    serialization: PhantomData<Serialization>,
+}
+
+impl<Serialization: SerializationStrategy> LagCompensationHistory<Serialization> {
+    pub fn new() -> LagCompensationHistory<Serialization> {
+        LagCompensationHistory::with_capacity(DEFAULT_LAG_COMPENSATION_FRAMES, 0)
+    }
+
+    /// Returns a new `LagCompensationHistory` that keeps `capacity` command frames, and rewinds
+    /// `interpolation_delay` frames further than the command frame of a command.
+    ///
+    /// The interpolation delay should match the delay the clients render with.
+    pub fn with_capacity(
+        capacity: u32,
+        interpolation_delay: u32,
+    ) -> LagCompensationHistory<Serialization> {
+        LagCompensationHistory {
+            entries: BTreeMap::new(),
+            oldest_frame: 0,
+            capacity,
+            interpolation_delay,
+            serialization: PhantomData,
+        }
+    }
+
+    /// Stores the unchanged data of a component that is modified during the given frame.
+    ///
+    /// Only the first modification of a component in a frame is stored.
+    pub fn push(
+        &mut self,
+        frame: CommandFrame,
+        unchanged_serialized: Vec<u8>,
+        entity_identifier: Uid,
+        component_type: TypeId,
+    ) {
+        self.entries
+            .entry(frame)
+            .or_default()
+            .entry((entity_identifier, component_type))
+            .or_insert(unchanged_serialized);
+    }
+
+    /// Stores the entries of a frame drained from a `ModifiedComponentsBuffer`.
+    pub fn insert_frame(
+        &mut self,
+        frame: CommandFrame,
+        entries: HashMap<EntryIdentifier, Vec<u8>>,
+    ) {
+        for ((entity_identifier, component_type), unchanged_serialized) in entries {
+            self.push(
+                frame,
+                unchanged_serialized,
+                entity_identifier,
+                component_type,
+            );
+        }
+    }
+
+    /// Drops the frames that are more than `capacity` frames older than `current_frame`.
+    pub fn advance(&mut self, current_frame: CommandFrame) {
+        self.oldest_frame = self
+            .oldest_frame
+            .max(current_frame.saturating_sub(self.capacity));
+        self.entries = self.entries.split_off(&self.oldest_frame);
+    }
+
+    /// Returns the frame a command has to be evaluated against.
+    ///
+    /// The command frame of a client is `command_frame_offset` frames ahead of the server, and
+    /// the client rendered the world `interpolation_delay` frames in the past. A client can not
+    /// have seen a frame after `current_frame`, the frame the server is simulating.
+    pub fn target_frame(
+        &self,
+        client_command_frame: CommandFrame,
+        command_frame_offset: i32,
+        current_frame: CommandFrame,
+    ) -> CommandFrame {
+        let server_frame = (client_command_frame as i64 - command_frame_offset as i64)
+            .max(0)
+            .min(current_frame as i64);
+        (server_frame as CommandFrame).saturating_sub(self.interpolation_delay)
+    }
+
+    /// Returns the serialized component as it was at the end of the given frame.
+    ///
+    /// Returns `None` if the component was not modified after that frame, or if the frame is
+    /// older than the history.
+    pub fn component_at(
+        &self,
+        frame: CommandFrame,
+        entity_identifier: Uid,
+        component_type: TypeId,
+    ) -> Option<&[u8]> {
+        let after = frame.saturating_add(1);
+
+        if after < self.oldest_frame {
+            return None;
+        }
+
+        self.entries
+            .range(after..)
+            .find_map(|(_, entries)| entries.get(&(entity_identifier, component_type)))
+            .map(|data| data.as_slice())
+    }
+
+    /// Rewinds the given components to the end of `frame`.
+    ///
+    /// `replace` is called for every component that was modified after `frame`, it receives the
+    /// historic data which it should write to the world, and returns the present data.
+    /// The returned `Rewound` restores the present data when the command is processed.
+    pub fn rewind(
+        &self,
+        frame: CommandFrame,
+        components: impl IntoIterator<Item = (Uid, TypeId)>,
+        mut replace: impl FnMut(Uid, TypeId, &[u8]) -> Option<Vec<u8>>,
+    ) -> Rewound {
+        let mut present = Vec::new();
+
+        for (entity_identifier, component_type) in components {
+            if let Some(historic) = self.component_at(frame, entity_identifier, component_type) {
+                if let Some(data) = replace(entity_identifier, component_type, historic) {
+                    present.push(((entity_identifier, component_type), data));
+                }
+            }
+        }
+
+        Rewound { frame, present }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<Serialization: SerializationStrategy> ServerChangeTracker
+    for LagCompensationHistory<Serialization>
+{
+    type Serialization = Serialization;
+
+    fn push(
+        &mut self,
+        command_frame: CommandFrame,
+        entity_identifier: Uid,
+        unchanged_serialized: Vec<u8>,
+        component_type: TypeId,
+    ) {
+        self.push(
+            command_frame,
+            unchanged_serialized,
+            entity_identifier,
+            component_type,
+        );
+    }
+}
+
+impl<Serialization: SerializationStrategy> Default for LagCompensationHistory<Serialization> {
+    fn default() -> Self {
+        LagCompensationHistory::new()
+    }
+}
+
+/// The present data of the components that were rewound by a `LagCompensationHistory`.
+#[must_use = "rewound components have to be restored"]
+pub struct Rewound {
+    frame: CommandFrame,
+    present: Vec<(EntryIdentifier, Vec<u8>)>,
+}
+
+impl Rewound {
+    /// Returns the frame the components were rewound to.
+    pub fn frame(&self) -> CommandFrame {
+        self.frame
+    }
+
+    /// Returns the number of components that were rewound.
+    pub fn len(&self) -> usize {
+        self.present.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.present.is_empty()
+    }
+
+    /// Calls `write` with the present data of every rewound component.
+    pub fn restore(self, mut write: impl FnMut(Uid, TypeId, &[u8])) {
+        for ((entity_identifier, component_type), data) in self.present {
+            write(entity_identifier, component_type, &data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{any::TypeId, collections::HashMap};
+
+    use crate::synchronisation::{CommandFrame, LagCompensationHistory};
+
+    struct Position;
+
+    fn history() -> LagCompensationHistory {
+        let mut history = LagCompensationHistory::with_capacity(10, 2);
+
+        // Entity 1 had position 1 up to frame 4, 2 up to frame 7 and is 3 now.
+        history.push(5, vec![1], 1, TypeId::of::<Position>());
+        history.push(8, vec![2], 1, TypeId::of::<Position>());
+        history.push(8, vec![9], 1, TypeId::of::<Position>());
+        history
+    }
+
+    #[test]
+    fn component_at_returns_first_unchanged_data_after_frame() {
+        let history = history();
+        let position = TypeId::of::<Position>();
+
+        assert_eq!(history.component_at(4, 1, position), Some(&[1][..]));
+        assert_eq!(history.component_at(5, 1, position), Some(&[2][..]));
+        assert_eq!(history.component_at(7, 1, position), Some(&[2][..]));
+        assert_eq!(history.component_at(8, 1, position), None);
+        assert_eq!(history.component_at(4, 2, position), None);
+    }
+
+    #[test]
+    fn rewind_and_restore_components() {
+        let history = history();
+        let position = TypeId::of::<Position>();
+
+        let mut world = HashMap::new();
+        world.insert((1, position), vec![3]);
+
+        // A command of frame 10 from a client that is 4 frames ahead, rendering 2 frames behind.
+        let frame = history.target_frame(10, 4, 8);
+        assert_eq!(frame, 4);
+
+        let rewound = history.rewind(frame, vec![(1, position)], |entity, component, data| {
+            world.insert((entity, component), data.to_vec())
+        });
+        assert_eq!(rewound.len(), 1);
+        assert_eq!(world[&(1, position)], vec![1]);
+
+        rewound.restore(|entity, component, data| {
+            world.insert((entity, component), data.to_vec());
+        });
+        assert_eq!(world[&(1, position)], vec![3]);
+    }
+
+    #[test]
+    fn target_frame_is_clamped_to_current_frame() {
+        let history = history();
+
+        // A client that claims to be far ahead is evaluated against the present.
+        assert_eq!(history.target_frame(1_000, 0, 10), 8);
+        assert_eq!(history.target_frame(CommandFrame::MAX, -5, 10), 8);
+        assert_eq!(
+            history.component_at(CommandFrame::MAX, 1, TypeId::of::<Position>()),
+            None
+        );
+    }
+
+    #[test]
+    fn advance_drops_frames_out_of_capacity() {
+        let mut history = history();
+        history.advance(16);
+
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.component_at(4, 1, TypeId::of::<Position>()), None);
+        assert_eq!(
+            history.component_at(7, 1, TypeId::of::<Position>()),
+            Some(&[2][..])
+        );
+    }
+}
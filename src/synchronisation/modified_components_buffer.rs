@@ -1,20 +1,33 @@
 use std::{
     any::TypeId,
     collections::{hash_map::Drain, HashMap},
+    marker::PhantomData,
 };
 
-use crate::{synchronisation::CommandFrame, tracker::ServerChangeTracker, uid::Uid};
+use crate::{
+    error::ErrorKind,
+    serialization::{DefaultSerialization, SerializationStrategy},
+    synchronisation::CommandFrame,
+    tracker::ServerChangeTracker,
+    uid::Uid,
+};
 
-type EntryIdentifier = (Uid, TypeId);
+pub(crate) type EntryIdentifier = (Uid, TypeId);
 
-pub struct ModifiedComponentsBuffer {
+/// Buffers the unchanged components, serialized with `Serialization`, that were modified during a
+/// command frame.
+pub struct ModifiedComponentsBuffer<Serialization: SerializationStrategy = DefaultSerialization> {
     pub entries: HashMap<CommandFrame, HashMap<EntryIdentifier, Vec<u8>>>,
+    errors: Vec<(Uid, ErrorKind)>,
+    serialization: PhantomData<Serialization>,
 }
 
-impl ModifiedComponentsBuffer {
-    pub fn new() -> ModifiedComponentsBuffer {
+impl<Serialization: SerializationStrategy> ModifiedComponentsBuffer<Serialization> {
+    pub fn new() -> ModifiedComponentsBuffer<Serialization> {
         ModifiedComponentsBuffer {
             entries: HashMap::new(),
+            errors: Vec::new(),
+            serialization: PhantomData,
         }
     }
 
@@ -25,22 +38,28 @@ impl ModifiedComponentsBuffer {
         entity_identifier: Uid,
         component_type: TypeId,
     ) {
-        let entry = self.entries.entry(frame).or_insert_with(|| HashMap::new());
-
-        if !entry.contains_key(&(entity_identifier, component_type)) {
-            let mut hashmap = HashMap::new();
-            hashmap.insert((entity_identifier, component_type), unchanged_serialized);
-
-            self.entries.insert(frame, hashmap);
-        }
+        self.entries
+            .entry(frame)
+            .or_default()
+            .entry((entity_identifier, component_type))
+            .or_insert(unchanged_serialized);
     }
 
     pub fn drain_entries(&mut self) -> Drain<CommandFrame, HashMap<EntryIdentifier, Vec<u8>>> {
         self.entries.drain()
     }
+
+    /// Drains the entities whose modifications could not be tracked, with the reason.
+    pub fn drain_errors(&mut self) -> std::vec::Drain<'_, (Uid, ErrorKind)> {
+        self.errors.drain(..)
+    }
 }
 
-impl ServerChangeTracker for ModifiedComponentsBuffer {
+impl<Serialization: SerializationStrategy> ServerChangeTracker
+    for ModifiedComponentsBuffer<Serialization>
+{
+    type Serialization = Serialization;
+
     fn push(
         &mut self,
         command_frame: CommandFrame,
@@ -55,4 +74,8 @@ impl ServerChangeTracker for ModifiedComponentsBuffer {
             component_type,
         );
     }
+
+    fn push_error(&mut self, entity_identifier: Uid, error: ErrorKind) {
+        self.errors.push((entity_identifier, error));
+    }
 }
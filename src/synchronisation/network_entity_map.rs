@@ -0,0 +1,271 @@
+use std::{collections::HashSet, hash::Hash};
+
+use crate::{
+    synchronisation::{EntityId, EntityInsert, PredictedSpawns, WorldState},
+    uid::{UidAllocator, UidRange},
+};
+
+/// The entities a `NetworkEntityMap` spawned, confirmed and despawned while applying a world
+/// state, with their server ids.
+pub struct MappedEntities<Entity> {
+    /// The entities that were spawned for the inserted entities.
+    pub spawned: Vec<(EntityId, Entity)>,
+    /// The predicted entities the server inserted, they are already spawned.
+    pub confirmed: Vec<(EntityId, Entity)>,
+    /// The entities of the removed entities, they should be despawned.
+    pub despawned: Vec<(EntityId, Entity)>,
+}
+
+/// Maps the `EntityId`s of the server to the local entities of the client.
+///
+/// The map is kept in sync by applying the world states of the server. Predicted entities get
+/// an id the server reserved for this client, so they keep their id when the server confirms them.
+pub struct NetworkEntityMap<Entity: Hash + Eq + Clone> {
+    allocator: UidAllocator<Entity>,
+    predicted: PredictedSpawns,
+}
+
+impl<Entity: Hash + Eq + Clone> NetworkEntityMap<Entity> {
+    pub fn new() -> NetworkEntityMap<Entity> {
+        NetworkEntityMap {
+            allocator: UidAllocator::new(),
+            predicted: PredictedSpawns::new(),
+        }
+    }
+
+    /// Returns the local entity of the given server id.
+    pub fn entity(&self, entity_id: EntityId) -> Option<&Entity> {
+        self.allocator.try_get_by_val(&entity_id)
+    }
+
+    /// Returns the server id of the given local entity.
+    pub fn entity_id(&self, entity: &Entity) -> Option<EntityId> {
+        self.allocator.try_get(entity)
+    }
+
+    /// Returns true if the given server id refers to an entity that was removed since.
+    pub fn is_stale(&self, entity_id: EntityId) -> bool {
+        self.allocator.is_stale(entity_id)
+    }
+
+    /// Returns an iterator over the server ids and their local entities, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &Entity)> {
+        self.allocator
+            .iter()
+            .map(|(entity, entity_id)| (entity_id, entity))
+    }
+
+    pub fn len(&self) -> usize {
+        self.allocator.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocator.is_empty()
+    }
+
+    /// Maps the given server id to the local entity.
+    pub fn insert(&mut self, entity_id: EntityId, entity: Entity) {
+        self.allocator.allocate(entity, Some(entity_id));
+    }
+
+    /// Removes the local entity of the given server id, and returns it.
+    pub fn remove(&mut self, entity_id: EntityId) -> Option<Entity> {
+        let entity = self.allocator.try_get_by_val(&entity_id)?.clone();
+        self.allocator.deallocate(entity.clone());
+        Some(entity)
+    }
+
+    /// Adds the ids of a `ServerToClientMessage::UidsReserved` for predicted entities.
+    pub fn add_reserved(&mut self, range: UidRange) {
+        self.predicted.add_reserved(range);
+    }
+
+    /// Returns the predicted spawns, with the reserved ids that are left.
+    pub fn predicted(&self) -> &PredictedSpawns {
+        &self.predicted
+    }
+
+    /// Maps the local entity the client predicted to a reserved id, and returns that id.
+    ///
+    /// Returns `None` if the reserved ids ran out, the entity is not predicted then.
+    pub fn insert_predicted(&mut self, entity: Entity) -> Option<EntityId> {
+        let entity_id = self.predicted.spawn()?;
+        self.insert(entity_id, entity);
+        Some(entity_id)
+    }
+
+    /// Removes the predicted entity of a `ServerToClientMessage::SpawnRejected`, and returns it so
+    /// that it can be despawned.
+    pub fn reject(&mut self, entity_id: EntityId) -> Option<Entity> {
+        if self.predicted.reject(entity_id) {
+            self.remove(entity_id)
+        } else {
+            None
+        }
+    }
+
+    /// Updates the map with the removed and inserted entities of the world state.
+    ///
+    /// Removed entities are unmapped first, because the server can reuse their ids for inserted
+    /// entities. `spawn` is called for every inserted entity that is not mapped yet. An inserted
+    /// entity that is already mapped, for example because a full world state is sent after a
+    /// reconnect, keeps its local entity. A full world state also removes the mapped entities it
+    /// does not insert, except predicted entities the server did not confirm yet.
+    pub fn apply(
+        &mut self,
+        world_state: &WorldState,
+        mut spawn: impl FnMut(&EntityInsert) -> Entity,
+    ) -> MappedEntities<Entity> {
+        let mut mapped = MappedEntities {
+            spawned: Vec::new(),
+            confirmed: Vec::new(),
+            despawned: Vec::new(),
+        };
+
+        for entity_id in world_state.removed.iter() {
+            // A removed prediction was confirmed and removed by the same state.
+            self.predicted.reject(*entity_id);
+
+            if let Some(entity) = self.remove(*entity_id) {
+                mapped.despawned.push((*entity_id, entity));
+            }
+        }
+
+        if world_state.is_full {
+            let inserted = world_state
+                .inserted
+                .iter()
+                .map(|insert| insert.entity_id())
+                .collect::<HashSet<_>>();
+            let stale = self
+                .iter()
+                .map(|(entity_id, _)| entity_id)
+                .filter(|entity_id| {
+                    !inserted.contains(entity_id) && !self.predicted.is_pending(*entity_id)
+                })
+                .collect::<Vec<_>>();
+
+            for entity_id in stale {
+                if let Some(entity) = self.remove(entity_id) {
+                    mapped.despawned.push((entity_id, entity));
+                }
+            }
+        }
+
+        for entity_id in self.predicted.confirm(world_state) {
+            if let Some(entity) = self.entity(entity_id) {
+                mapped.confirmed.push((entity_id, entity.clone()));
+            }
+        }
+
+        for insert in world_state.inserted.iter() {
+            if self.entity(insert.entity_id()).is_none() {
+                let entity = spawn(insert);
+                self.insert(insert.entity_id(), entity.clone());
+                mapped.spawned.push((insert.entity_id(), entity));
+            }
+        }
+
+        mapped
+    }
+}
+
+impl<Entity: Hash + Eq + Clone> Default for NetworkEntityMap<Entity> {
+    fn default() -> Self {
+        NetworkEntityMap::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        synchronisation::{NetworkEntityMap, WorldState},
+        uid::UidRange,
+    };
+
+    #[test]
+    fn applying_world_state_spawns_and_despawns() {
+        let mut map = NetworkEntityMap::new();
+        let mut next_entity = 100;
+        let mut spawn = |_: &_| {
+            next_entity += 1;
+            next_entity
+        };
+
+        let mut world_state = WorldState::new(1);
+        world_state.insert_entity(1, Vec::new());
+        world_state.insert_entity(2, Vec::new());
+        let mapped = map.apply(&world_state, &mut spawn);
+
+        assert_eq!(mapped.spawned.len(), 2);
+        let entity = *map.entity(1).unwrap();
+        assert_eq!(map.entity_id(&entity), Some(1));
+
+        // Inserting a mapped entity again keeps its local entity.
+        let mapped = map.apply(&world_state, &mut spawn);
+        assert!(mapped.spawned.is_empty());
+        assert_eq!(map.entity(1), Some(&entity));
+
+        let mut world_state = WorldState::new(2);
+        world_state.removed.insert(1);
+        let mapped = map.apply(&world_state, &mut spawn);
+
+        assert_eq!(mapped.despawned, vec![(1, entity)]);
+        assert_eq!(map.entity(1), None);
+        assert!(map.is_stale(1));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn full_state_after_reconnect_despawns_removed_entities() {
+        let mut map = NetworkEntityMap::new();
+        let mut next_entity = 100;
+        let mut spawn = |_: &_| {
+            next_entity += 1;
+            next_entity
+        };
+        map.add_reserved(UidRange { start: 10, end: 11 });
+        let predicted = map.insert_predicted(50).unwrap();
+
+        let mut world_state = WorldState::new(1);
+        world_state.insert_entity(1, Vec::new());
+        world_state.insert_entity(2, Vec::new());
+        map.apply(&world_state, &mut spawn);
+        let removed = *map.entity(2).unwrap();
+
+        // Entity 2 was removed while the client was disconnected.
+        let mut full = WorldState::new(5);
+        full.is_full = true;
+        full.insert_entity(1, Vec::new());
+        let mapped = map.apply(&full, &mut spawn);
+
+        assert_eq!(mapped.despawned, vec![(2, removed)]);
+        assert!(mapped.spawned.is_empty());
+        assert_eq!(map.entity(2), None);
+        assert!(map.entity(1).is_some());
+        assert_eq!(map.entity(predicted), Some(&50));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn predicted_entity_is_confirmed_or_rejected() {
+        let mut map = NetworkEntityMap::new();
+        map.add_reserved(UidRange { start: 5, end: 7 });
+
+        let confirmed = map.insert_predicted("projectile").unwrap();
+        let rejected = map.insert_predicted("missile").unwrap();
+        assert_eq!(map.insert_predicted("rocket"), None);
+
+        let mut world_state = WorldState::new(1);
+        world_state.insert_entity(confirmed, Vec::new());
+        let mapped = map.apply(&world_state, |_| panic!("Nothing should be spawned."));
+
+        assert_eq!(mapped.confirmed, vec![(confirmed, "projectile")]);
+        assert!(mapped.spawned.is_empty());
+        assert_eq!(map.entity(confirmed), Some(&"projectile"));
+
+        assert_eq!(map.reject(rejected), Some("missile"));
+        assert_eq!(map.reject(confirmed), None);
+        assert_eq!(map.entity_id(&"missile"), None);
+    }
+}
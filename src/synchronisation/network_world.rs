@@ -0,0 +1,497 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{
+    error::ErrorKind,
+    synchronisation::{
+        ComponentData, ComponentDiffs, ComponentId, EntityId, MappedEntities, NetworkEntityMap,
+        SnapshotHistory, WorldState,
+    },
+};
+
+/// The world of an entity component system, to which the `WorldState`s of the server are applied
+/// with `apply_world_state`.
+///
+/// Components are identified by their `ComponentId` and passed as serialized data, the world
+/// deserializes them into its own component types.
+pub trait NetworkWorld {
+    /// The local entity handle of the world.
+    type Entity: Hash + Eq + Clone;
+
+    /// Spawns a new entity without components.
+    fn spawn(&mut self) -> Self::Entity;
+
+    /// Despawns the entity and its components.
+    fn despawn(&mut self, entity: &Self::Entity);
+
+    /// Inserts the component, an existing component with the same id is replaced.
+    fn insert_component(
+        &mut self,
+        entity: &Self::Entity,
+        component: &ComponentData,
+    ) -> Result<(), ErrorKind>;
+
+    /// Removes the component with the given id.
+    fn remove_component(&mut self, entity: &Self::Entity, component_id: ComponentId);
+
+    /// Returns the ids of the components of the entity.
+    fn component_ids(&self, entity: &Self::Entity) -> Vec<ComponentId>;
+
+    /// Updates an existing component with the data of a `ComponentChanged`.
+    ///
+    /// The data is the serialized serde-diff of the component with the last applied state, it is
+    /// applied with `SerializationStrategy::apply_diff` or `ComponentDiffs::apply`.
+    fn patch_component(
+        &mut self,
+        entity: &Self::Entity,
+        component: &ComponentData,
+    ) -> Result<(), ErrorKind>;
+}
+
+/// The outcome of `apply_world_state`.
+pub struct AppliedWorldState<Entity> {
+    /// The entities that were spawned, confirmed and despawned.
+    pub entities: MappedEntities<Entity>,
+    /// The entities whose components could not be applied, the rest of the state is applied.
+    pub errors: Vec<(EntityId, ErrorKind)>,
+}
+
+/// Applies the world state of the server to the world, and keeps the map of server ids to local
+/// entities in sync.
+///
+/// The state is relative to the baseline the server saw acknowledged, which is usually older than
+/// the state the world reflects. It is therefore decoded with `SnapshotHistory::apply` first, and
+/// the world is updated with the differences of the decoded snapshot with the last applied one.
+/// A state that is older than the last applied one is not applied, and returns
+/// `ErrorKind::OutdatedWorldState`.
+///
+/// Inserted entities are spawned and get their components first, then components are added and
+/// changed, and removals are applied last. A full state despawns the entities it does not insert.
+pub fn apply_world_state<W: NetworkWorld>(
+    world: &mut W,
+    entity_map: &mut NetworkEntityMap<W::Entity>,
+    history: &mut SnapshotHistory,
+    world_state: &WorldState,
+) -> Result<AppliedWorldState<W::Entity>, ErrorKind> {
+    let previous = history.newest().cloned();
+
+    if let Some(previous) = previous.as_ref() {
+        if world_state.command_frame <= previous.command_frame() {
+            return Err(ErrorKind::OutdatedWorldState(world_state.command_frame));
+        }
+    }
+
+    let snapshot = history.apply(world_state)?.clone();
+    let baseline = if world_state.is_full {
+        None
+    } else {
+        previous.as_ref()
+    };
+    let local_state = snapshot.delta(baseline, history.diffs());
+
+    Ok(apply_local_state(world, entity_map, &local_state))
+}
+
+/// Applies a world state that is relative to the state the world reflects.
+///
+/// Changes of entities that are removed by the same state are skipped.
+fn apply_local_state<W: NetworkWorld>(
+    world: &mut W,
+    entity_map: &mut NetworkEntityMap<W::Entity>,
+    world_state: &WorldState,
+) -> AppliedWorldState<W::Entity> {
+    let entities = entity_map.apply(world_state, |_| world.spawn());
+    let mut errors = Vec::new();
+
+    let mut apply = |entity_id: EntityId,
+                     f: &mut dyn FnMut(&W::Entity) -> Result<(), ErrorKind>| {
+        let result = match entity_map.entity(entity_id) {
+            Some(entity) => f(entity),
+            None if world_state.removed.contains(&entity_id) => Ok(()),
+            None => Err(ErrorKind::EntityNotFound(entity_id)),
+        };
+
+        if let Err(e) = result {
+            errors.push((entity_id, e));
+        }
+    };
+
+    // Mapped entities that are inserted again, for example by a full state, get the components
+    // of the server, and lose the components the server does not have.
+    for insert in world_state.inserted.iter() {
+        apply(insert.entity_id(), &mut |entity| {
+            for component_id in world.component_ids(entity) {
+                if !insert
+                    .components()
+                    .iter()
+                    .any(|component| component.component_id() == component_id)
+                {
+                    world.remove_component(entity, component_id);
+                }
+            }
+
+            insert
+                .components()
+                .iter()
+                .try_for_each(|component| world.insert_component(entity, component))
+        });
+    }
+
+    for added in world_state.component_added.iter() {
+        apply(added.entity_id(), &mut |entity| {
+            world.insert_component(entity, added.component_data())
+        });
+    }
+
+    for changed in world_state.changed.iter() {
+        apply(changed.entity_id(), &mut |entity| {
+            world.patch_component(entity, changed.component_data())
+        });
+    }
+
+    for removed in world_state.component_removed.iter() {
+        apply(removed.entity_id(), &mut |entity| {
+            world.remove_component(entity, *removed.component_id());
+            Ok(())
+        });
+    }
+
+    for (_, entity) in entities.despawned.iter() {
+        world.despawn(entity);
+    }
+
+    AppliedWorldState { entities, errors }
+}
+
+/// A world that stores the serialized components of its entities in hash maps.
+///
+/// It is the reference implementation of `NetworkWorld`, and can be used in tests. Changed
+/// components are patched with the types registered in its `ComponentDiffs`.
+#[derive(Default)]
+pub struct HashMapWorld {
+    next_entity: u32,
+    entities: HashMap<u32, HashMap<ComponentId, Vec<u8>>>,
+    diffs: ComponentDiffs,
+}
+
+impl HashMapWorld {
+    pub fn new() -> HashMapWorld {
+        HashMapWorld::default()
+    }
+
+    /// Returns a new `HashMapWorld` that patches changed components with the given diffs.
+    pub fn with_diffs(diffs: ComponentDiffs) -> HashMapWorld {
+        HashMapWorld {
+            diffs,
+            ..HashMapWorld::default()
+        }
+    }
+
+    pub fn contains(&self, entity: u32) -> bool {
+        self.entities.contains_key(&entity)
+    }
+
+    /// Returns the serialized data of a component of the given entity.
+    pub fn component(&self, entity: u32, component_id: ComponentId) -> Option<&[u8]> {
+        self.entities
+            .get(&entity)
+            .and_then(|components| components.get(&component_id))
+            .map(|data| data.as_slice())
+    }
+
+    /// Returns the number of entities in this world.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+impl NetworkWorld for HashMapWorld {
+    type Entity = u32;
+
+    fn spawn(&mut self) -> u32 {
+        self.next_entity += 1;
+        self.entities.insert(self.next_entity, HashMap::new());
+        self.next_entity
+    }
+
+    fn despawn(&mut self, entity: &u32) {
+        self.entities.remove(entity);
+    }
+
+    fn insert_component(
+        &mut self,
+        entity: &u32,
+        component: &ComponentData,
+    ) -> Result<(), ErrorKind> {
+        if let Some(components) = self.entities.get_mut(entity) {
+            components.insert(component.component_id(), component.data().clone());
+        }
+        Ok(())
+    }
+
+    fn remove_component(&mut self, entity: &u32, component_id: ComponentId) {
+        if let Some(components) = self.entities.get_mut(entity) {
+            components.remove(&component_id);
+        }
+    }
+
+    fn component_ids(&self, entity: &u32) -> Vec<ComponentId> {
+        self.entities
+            .get(entity)
+            .map(|components| components.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// A missing component is reported as `EntityNotFound` of the local entity.
+    fn patch_component(
+        &mut self,
+        entity: &u32,
+        component: &ComponentData,
+    ) -> Result<(), ErrorKind> {
+        let data = self
+            .entities
+            .get_mut(entity)
+            .and_then(|components| components.get_mut(&component.component_id()))
+            .ok_or(ErrorKind::EntityNotFound(*entity))?;
+
+        *data = self
+            .diffs
+            .apply(component.component_id(), data, component.data())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::ErrorKind,
+        serialization::{DefaultSerialization, SerializationStrategy},
+        synchronisation::{
+            apply_world_state, ComponentData, ComponentDiffs, HashMapWorld, NetworkEntityMap,
+            SnapshotHistory, WorldSnapshot, WorldState,
+        },
+    };
+
+    fn component(component_id: u32, value: u32) -> ComponentData {
+        ComponentData::new(
+            component_id,
+            DefaultSerialization::default().serialize(&value).unwrap(),
+        )
+    }
+
+    fn diffs() -> ComponentDiffs {
+        let mut diffs = ComponentDiffs::new();
+        for component_id in 1..=2 {
+            diffs.register::<u32, _>(component_id, DefaultSerialization::default());
+        }
+        diffs
+    }
+
+    #[test]
+    fn world_follows_server_snapshots() {
+        let mut world = HashMapWorld::new();
+        let mut entity_map = NetworkEntityMap::new();
+        let mut history = SnapshotHistory::new();
+        let diffs = ComponentDiffs::new();
+
+        let mut first = WorldSnapshot::new(1);
+        first.insert_entity(1, vec![ComponentData::new(1, vec![1])]);
+        first.insert_entity(2, vec![ComponentData::new(1, vec![2])]);
+
+        let applied = apply_world_state(
+            &mut world,
+            &mut entity_map,
+            &mut history,
+            &first.delta(None, &diffs),
+        )
+        .unwrap();
+        assert_eq!(applied.entities.spawned.len(), 2);
+        assert!(applied.errors.is_empty());
+
+        let mut second = first.clone();
+        second.set_component(1, ComponentData::new(1, vec![3]));
+        second.set_component(1, ComponentData::new(2, vec![4]));
+        second.remove_component(2, 1);
+        second.remove_entity(2);
+        second.insert_entity(3, vec![ComponentData::new(1, vec![5])]);
+
+        let removed = *entity_map.entity(2).unwrap();
+        let mut delta = second.delta(Some(&first), &diffs);
+        delta.command_frame = 2;
+        let applied = apply_world_state(&mut world, &mut entity_map, &mut history, &delta).unwrap();
+        assert!(applied.errors.is_empty());
+        assert_eq!(applied.entities.despawned, vec![(2, removed)]);
+
+        let entity = *entity_map.entity(1).unwrap();
+        assert_eq!(world.component(entity, 1), Some(&[3][..]));
+        assert_eq!(world.component(entity, 2), Some(&[4][..]));
+        assert!(!world.contains(removed));
+
+        let inserted = *entity_map.entity(3).unwrap();
+        assert_eq!(world.component(inserted, 1), Some(&[5][..]));
+        assert_eq!(world.len(), 2);
+    }
+
+    #[test]
+    fn states_that_can_not_be_decoded_are_not_applied() {
+        let mut world = HashMapWorld::new();
+        let mut entity_map = NetworkEntityMap::new();
+        let mut history = SnapshotHistory::new();
+
+        let mut first = WorldSnapshot::new(2);
+        first.insert_entity(7, vec![ComponentData::new(1, vec![1])]);
+        let state = first.delta(None, history.diffs());
+        apply_world_state(&mut world, &mut entity_map, &mut history, &state).unwrap();
+
+        // The client never received the baseline of this state.
+        let mut state = WorldState::new(3);
+        state.baseline = Some(1);
+        state.add_component(7, ComponentData::new(2, vec![2]));
+        assert!(matches!(
+            apply_world_state(&mut world, &mut entity_map, &mut history, &state),
+            Err(ErrorKind::BaselineNotFound(1))
+        ));
+
+        // A state that arrives after a newer one was applied.
+        let mut state = WorldState::new(1);
+        state.baseline = Some(2);
+        state.add_component(7, ComponentData::new(2, vec![2]));
+        assert!(matches!(
+            apply_world_state(&mut world, &mut entity_map, &mut history, &state),
+            Err(ErrorKind::OutdatedWorldState(1))
+        ));
+
+        let entity = *entity_map.entity(7).unwrap();
+        assert_eq!(world.component(entity, 2), None);
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn changed_components_are_patched_with_diffs() {
+        let mut world = HashMapWorld::with_diffs(diffs());
+        let mut entity_map = NetworkEntityMap::new();
+        let mut history = SnapshotHistory::new();
+        *history.diffs_mut() = diffs();
+
+        let mut first = WorldSnapshot::new(1);
+        first.insert_entity(1, vec![component(1, 10)]);
+        let mut second = first.clone();
+        second.set_component(1, component(1, 20));
+
+        let state = first.delta(None, &diffs());
+        apply_world_state(&mut world, &mut entity_map, &mut history, &state).unwrap();
+        let mut delta = second.delta(Some(&first), &diffs());
+        delta.command_frame = 2;
+        assert_eq!(delta.changed.len(), 1);
+
+        let applied = apply_world_state(&mut world, &mut entity_map, &mut history, &delta).unwrap();
+        assert!(applied.errors.is_empty());
+
+        let entity = *entity_map.entity(1).unwrap();
+        assert_eq!(
+            world.component(entity, 1),
+            Some(component(1, 20).data().as_slice())
+        );
+    }
+
+    #[test]
+    fn state_relative_to_older_baseline_is_applied_to_newer_world() {
+        let mut world = HashMapWorld::with_diffs(diffs());
+        let mut entity_map = NetworkEntityMap::new();
+        let mut history = SnapshotHistory::new();
+        *history.diffs_mut() = diffs();
+
+        let mut first = WorldSnapshot::new(1);
+        first.insert_entity(1, vec![component(1, 1), component(2, 1)]);
+        let mut second = first.clone();
+        second.set_component(1, component(1, 5));
+        let mut third = first.clone();
+        third.set_component(1, component(2, 7));
+
+        let mut states = vec![first.delta(None, &diffs())];
+        states.push(second.delta(Some(&first), &diffs()));
+        // The server did not see the acknowledgement of the second state yet.
+        states.push(third.delta(Some(&first), &diffs()));
+
+        for (command_frame, mut state) in (1..).zip(states) {
+            state.command_frame = command_frame;
+            let applied =
+                apply_world_state(&mut world, &mut entity_map, &mut history, &state).unwrap();
+            assert!(applied.errors.is_empty());
+        }
+
+        let entity = *entity_map.entity(1).unwrap();
+        assert_eq!(
+            world.component(entity, 1),
+            Some(component(1, 1).data().as_slice())
+        );
+        assert_eq!(
+            world.component(entity, 2),
+            Some(component(2, 7).data().as_slice())
+        );
+    }
+
+    #[test]
+    fn full_state_after_reconnect_replaces_world() {
+        let mut world = HashMapWorld::new();
+        let mut entity_map = NetworkEntityMap::new();
+        let mut history = SnapshotHistory::new();
+        let diffs = ComponentDiffs::new();
+
+        let mut before = WorldSnapshot::new(1);
+        before.insert_entity(
+            1,
+            vec![
+                ComponentData::new(1, vec![1]),
+                ComponentData::new(2, vec![2]),
+            ],
+        );
+        before.insert_entity(2, vec![ComponentData::new(1, vec![3])]);
+        let state = before.delta(None, &diffs);
+        apply_world_state(&mut world, &mut entity_map, &mut history, &state).unwrap();
+        let removed = *entity_map.entity(2).unwrap();
+
+        // While the client was disconnected, entity 2 and a component of entity 1 were removed.
+        let mut after = WorldSnapshot::new(9);
+        after.insert_entity(1, vec![ComponentData::new(1, vec![4])]);
+        let state = after.delta(None, &diffs);
+        let applied = apply_world_state(&mut world, &mut entity_map, &mut history, &state).unwrap();
+
+        assert!(applied.errors.is_empty());
+        assert_eq!(applied.entities.despawned, vec![(2, removed)]);
+        assert!(!world.contains(removed));
+
+        let entity = *entity_map.entity(1).unwrap();
+        assert_eq!(world.component(entity, 1), Some(&[4][..]));
+        assert_eq!(world.component(entity, 2), None);
+        assert_eq!(world.len(), 1);
+    }
+
+    #[test]
+    fn changes_of_removed_entities_are_skipped() {
+        let mut world = HashMapWorld::new();
+        let mut entity_map = NetworkEntityMap::new();
+        let mut history = SnapshotHistory::new();
+
+        let mut inserted = WorldState::new(1);
+        inserted.insert_entity(7, vec![ComponentData::new(1, vec![1])]);
+        apply_world_state(&mut world, &mut entity_map, &mut history, &inserted).unwrap();
+
+        let mut removed = WorldState::new(2);
+        removed.baseline = Some(1);
+        removed.removed.insert(7);
+        removed.add_component(7, ComponentData::new(2, vec![2]));
+        removed.change(7, ComponentData::new(1, vec![3]));
+        removed.remove_component(7, 1);
+
+        let applied =
+            apply_world_state(&mut world, &mut entity_map, &mut history, &removed).unwrap();
+
+        assert!(applied.errors.is_empty());
+        assert_eq!(applied.entities.despawned.len(), 1);
+        assert!(world.is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    synchronisation::{EntityId, WorldState},
+    uid::{Uid, UidRange},
+};
+
+/// Hands out the identifiers the server reserved for this client to the entities the client
+/// predicts, and tracks which of them the server has not confirmed yet.
+///
+/// The server confirms a predicted entity by inserting it in a world state, or rejects it with a
+/// `ServerToClientMessage::SpawnRejected`.
+pub struct PredictedSpawns {
+    reserved: VecDeque<UidRange>,
+    pending: HashSet<Uid>,
+}
+
+impl PredictedSpawns {
+    pub fn new() -> PredictedSpawns {
+        PredictedSpawns {
+            reserved: VecDeque::new(),
+            pending: HashSet::new(),
+        }
+    }
+
+    /// Adds the identifiers of a `ServerToClientMessage::UidsReserved`.
+    pub fn add_reserved(&mut self, range: UidRange) {
+        if !range.is_empty() {
+            self.reserved.push_back(range);
+        }
+    }
+
+    /// Returns the number of reserved identifiers that were not handed out yet.
+    pub fn remaining(&self) -> u32 {
+        self.reserved.iter().map(|range| range.len()).sum()
+    }
+
+    /// Returns the identifier for a new predicted entity, or `None` if the reserved identifiers
+    /// ran out.
+    pub fn spawn(&mut self) -> Option<Uid> {
+        let range = self.reserved.front_mut()?;
+        let uid = range.start;
+
+        range.start += 1;
+        if range.is_empty() {
+            self.reserved.pop_front();
+        }
+
+        self.pending.insert(uid);
+        Some(uid)
+    }
+
+    /// Returns true if the entity with the given identifier is predicted and not confirmed yet.
+    pub fn is_pending(&self, uid: Uid) -> bool {
+        self.pending.contains(&uid)
+    }
+
+    /// Confirms the predicted entities that are inserted by the given world state, and returns
+    /// their identifiers.
+    pub fn confirm(&mut self, world_state: &WorldState) -> Vec<EntityId> {
+        let confirmed = world_state
+            .inserted
+            .iter()
+            .map(|insert| insert.entity_id())
+            .filter(|entity_id| self.pending.contains(entity_id))
+            .collect::<Vec<_>>();
+
+        for entity_id in confirmed.iter() {
+            self.pending.remove(entity_id);
+        }
+
+        confirmed
+    }
+
+    /// Forgets the rejected prediction, returns true if the entity was predicted and should be
+    /// despawned.
+    pub fn reject(&mut self, uid: Uid) -> bool {
+        self.pending.remove(&uid)
+    }
+}
+
+impl Default for PredictedSpawns {
+    fn default() -> Self {
+        PredictedSpawns::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        synchronisation::{PredictedSpawns, WorldState},
+        uid::UidRange,
+    };
+
+    #[test]
+    fn spawns_from_reserved_ranges_until_they_run_out() {
+        let mut spawns = PredictedSpawns::new();
+        spawns.add_reserved(UidRange { start: 4, end: 6 });
+        spawns.add_reserved(UidRange { start: 10, end: 11 });
+        assert_eq!(spawns.remaining(), 3);
+
+        assert_eq!(spawns.spawn(), Some(4));
+        assert_eq!(spawns.spawn(), Some(5));
+        assert_eq!(spawns.spawn(), Some(10));
+        assert_eq!(spawns.spawn(), None);
+        assert_eq!(spawns.remaining(), 0);
+    }
+
+    #[test]
+    fn inserted_predictions_are_confirmed_and_rejected_are_forgotten() {
+        let mut spawns = PredictedSpawns::new();
+        spawns.add_reserved(UidRange { start: 1, end: 3 });
+        let confirmed = spawns.spawn().unwrap();
+        let rejected = spawns.spawn().unwrap();
+
+        let mut world_state = WorldState::new(1);
+        world_state.insert_entity(confirmed, Vec::new());
+        world_state.insert_entity(20, Vec::new());
+
+        assert_eq!(spawns.confirm(&world_state), vec![confirmed]);
+        assert!(!spawns.is_pending(confirmed));
+
+        assert!(spawns.reject(rejected));
+        assert!(!spawns.reject(rejected));
+        assert!(!spawns.is_pending(rejected));
+    }
+}
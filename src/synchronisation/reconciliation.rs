@@ -0,0 +1,227 @@
+use std::{any::TypeId, collections::HashMap};
+
+use crate::{
+    serialization::SerializationStrategy,
+    synchronisation::{
+        ClientCommandBuffer, ClientCommandBufferEntry, CommandFrame, ComponentId, EntityId,
+        NetworkCommand, ResimulationBuffer, WorldSnapshot,
+    },
+};
+
+/// A predicted component that differs from the authoritative state of the server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rollback {
+    pub entity_id: EntityId,
+    pub component_type: TypeId,
+    /// The component as predicted by the client.
+    pub predicted_data: Vec<u8>,
+    /// The component as simulated by the server, it replaces the predicted component.
+    pub authoritative_data: Vec<u8>,
+}
+
+/// Compares authoritative server snapshots with the state the client predicted, and schedules
+/// the commands that have to be re-applied when the prediction was wrong.
+///
+/// The client records the component data after each command in the `ClientCommandBuffer`.
+/// When a snapshot of frame `n` arrives, the recorded data of frame `n` is compared with the
+/// component data in the snapshot. Every mismatching component is rolled back to the
+/// authoritative data, after which the commands after frame `n` are resimulated.
+///
+/// The component data is compared byte for byte, so the client and server need to use the same
+/// serialization strategy.
+pub struct Reconciler {
+    component_ids: HashMap<TypeId, ComponentId>,
+    last_reconciled: Option<CommandFrame>,
+}
+
+impl Reconciler {
+    pub fn new() -> Reconciler {
+        Reconciler {
+            component_ids: HashMap::new(),
+            last_reconciled: None,
+        }
+    }
+
+    /// Registers the id under which the server replicates component `T`.
+    ///
+    /// Predictions of components that are not registered are never reconciled.
+    pub fn register<T: 'static>(&mut self, component_id: ComponentId) {
+        self.component_ids.insert(TypeId::of::<T>(), component_id);
+    }
+
+    /// Returns the command frame of the last reconciled snapshot.
+    pub fn last_reconciled(&self) -> Option<CommandFrame> {
+        self.last_reconciled
+    }
+
+    /// Reconciles the predictions for the command frame of the given authoritative snapshot.
+    ///
+    /// The command frame of the snapshot has to be in the command frames of the client.
+    /// The returned rollbacks have to be applied to the world before the commands in the
+    /// `ResimulationBuffer` are resimulated.
+    /// Snapshots older than the last reconciled snapshot are ignored.
+    pub fn reconcile<
+        ClientToServerCommand: NetworkCommand,
+        Serialization: SerializationStrategy,
+    >(
+        &mut self,
+        snapshot: &WorldSnapshot,
+        command_buffer: &ClientCommandBuffer<ClientToServerCommand, Serialization>,
+        resimulation_buffer: &mut ResimulationBuffer<ClientToServerCommand>,
+    ) -> Vec<Rollback> {
+        let command_frame = snapshot.command_frame();
+
+        if matches!(self.last_reconciled, Some(last) if command_frame <= last) {
+            return Vec::new();
+        }
+        self.last_reconciled = Some(command_frame);
+
+        let mut predicted = HashMap::new();
+
+        // The buffer iterates from new to old, the first entry is the final prediction of a frame.
+        for entry in command_buffer
+            .iter()
+            .filter(|entry| entry.command_frame == command_frame)
+        {
+            predicted
+                .entry((entry.entity_id, entry.component_type))
+                .or_insert(entry);
+        }
+
+        let mut rollbacks = Vec::new();
+
+        for ((entity_id, component_type), entry) in predicted {
+            let component_id = match self.component_ids.get(&component_type) {
+                Some(component_id) => *component_id,
+                None => continue,
+            };
+
+            let authoritative = match snapshot.component(entity_id, component_id) {
+                Some(authoritative) => authoritative,
+                None => continue,
+            };
+
+            if authoritative != entry.changed_data.as_slice() {
+                rollbacks.push(Rollback {
+                    entity_id,
+                    component_type,
+                    predicted_data: entry.changed_data.clone(),
+                    authoritative_data: authoritative.to_vec(),
+                });
+            }
+        }
+
+        if !rollbacks.is_empty() {
+            let mut to_resimulate = command_buffer
+                .iter()
+                .filter(|entry| entry.command_frame > command_frame)
+                .cloned()
+                .collect::<Vec<ClientCommandBufferEntry<ClientToServerCommand>>>();
+            to_resimulate.reverse();
+            // A command has an entry for every component it changed, it is resimulated once.
+            to_resimulate.dedup_by(|entry, previous| {
+                entry.command_frame == previous.command_frame && entry.command == previous.command
+            });
+
+            if let Some(last) = to_resimulate.last() {
+                let end_command_frame = last.command_frame;
+                resimulation_buffer.push(command_frame + 1, end_command_frame, to_resimulate);
+            }
+        }
+
+        rollbacks
+    }
+
+    /// Drains the `ResimulationBuffer` and calls `resimulate` once for every command, from the
+    /// oldest to the newest command.
+    pub fn resimulate<ClientToServerCommand: NetworkCommand>(
+        &self,
+        resimulation_buffer: &mut ResimulationBuffer<ClientToServerCommand>,
+        mut resimulate: impl FnMut(&ClientCommandBufferEntry<ClientToServerCommand>),
+    ) {
+        for entry in resimulation_buffer.drain() {
+            for command in entry.to_resimmulate.iter() {
+                resimulate(command);
+            }
+        }
+    }
+}
+
+impl Default for Reconciler {
+    fn default() -> Self {
+        Reconciler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+
+    use crate::synchronisation::{
+        ClientCommandBuffer, ComponentData, Reconciler, ResimulationBuffer, WorldSnapshot,
+    };
+
+    struct Position;
+    struct Health;
+
+    fn command_buffer() -> ClientCommandBuffer<u32> {
+        let mut buffer = ClientCommandBuffer::<u32>::with_capacity(10);
+        buffer.push(1, 1, vec![0], vec![1], 1, TypeId::of::<Position>());
+        buffer.push(2, 2, vec![1], vec![2], 1, TypeId::of::<Position>());
+        buffer.push(3, 2, vec![2], vec![3], 1, TypeId::of::<Position>());
+        buffer.push(4, 3, vec![3], vec![4], 1, TypeId::of::<Position>());
+        buffer.push(4, 3, vec![8], vec![7], 1, TypeId::of::<Health>());
+        buffer.push(5, 4, vec![4], vec![5], 1, TypeId::of::<Position>());
+        buffer.push(5, 4, vec![7], vec![6], 1, TypeId::of::<Health>());
+        buffer
+    }
+
+    fn reconciler() -> Reconciler {
+        let mut reconciler = Reconciler::new();
+        reconciler.register::<Position>(1);
+        reconciler.register::<Health>(2);
+        reconciler
+    }
+
+    #[test]
+    fn correct_prediction_does_not_resimulate() {
+        let mut snapshot = WorldSnapshot::new(2);
+        snapshot.insert_entity(1, vec![ComponentData::new(1, vec![3])]);
+
+        let mut resimulation_buffer = ResimulationBuffer::new();
+        let rollbacks =
+            reconciler().reconcile(&snapshot, &command_buffer(), &mut resimulation_buffer);
+
+        assert!(rollbacks.is_empty());
+        assert_eq!(resimulation_buffer.iter().count(), 0);
+    }
+
+    #[test]
+    fn misprediction_rolls_back_and_resimulates_later_commands() {
+        let mut snapshot = WorldSnapshot::new(2);
+        snapshot.insert_entity(1, vec![ComponentData::new(1, vec![9])]);
+
+        let mut reconciler = reconciler();
+        let mut resimulation_buffer = ResimulationBuffer::new();
+        let rollbacks =
+            reconciler.reconcile(&snapshot, &command_buffer(), &mut resimulation_buffer);
+
+        assert_eq!(rollbacks.len(), 1);
+        assert_eq!(rollbacks[0].entity_id, 1);
+        assert_eq!(rollbacks[0].predicted_data, vec![3]);
+        assert_eq!(rollbacks[0].authoritative_data, vec![9]);
+
+        let mut resimulated = Vec::new();
+        reconciler.resimulate(&mut resimulation_buffer, |entry| {
+            resimulated.push((entry.command_frame, entry.command))
+        });
+
+        assert_eq!(resimulated, vec![(3, 4), (4, 5)]);
+        assert_eq!(resimulation_buffer.iter().count(), 0);
+
+        // A snapshot that is not newer than the last reconciled snapshot is ignored.
+        assert!(reconciler
+            .reconcile(&snapshot, &command_buffer(), &mut resimulation_buffer)
+            .is_empty());
+    }
+}
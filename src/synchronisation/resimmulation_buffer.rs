@@ -1,9 +1,10 @@
-use std::collections::{vec_deque::Iter, VecDeque};
+use std::collections::{
+    vec_deque::{Drain, Iter},
+    VecDeque,
+};
 
 use crate::synchronisation::{ClientCommandBufferEntry, CommandFrame, NetworkCommand};
 
-// TODO: make resim buffer empty when iterated
-
 pub struct ResimulationBufferEntry<ClientToServerCommand: NetworkCommand> {
     pub to_resimmulate: Vec<ClientCommandBufferEntry<ClientToServerCommand>>,
     pub start_command_frame: CommandFrame,
@@ -37,4 +38,11 @@ impl<ClientToServerCommand: NetworkCommand> ResimulationBuffer<ClientToServerCom
     pub fn iter(&self) -> Iter<ResimulationBufferEntry<ClientToServerCommand>> {
         self.entries.iter()
     }
+
+    /// Removes all entries and returns them from the oldest to the newest entry.
+    pub fn drain(
+        &mut self,
+    ) -> std::iter::Rev<Drain<'_, ResimulationBufferEntry<ClientToServerCommand>>> {
+        self.entries.drain(..).rev()
+    }
 }
@@ -0,0 +1,490 @@
+use std::collections::{HashMap, VecDeque};
+
+use log::error;
+
+use crate::{
+    error::ErrorKind,
+    synchronisation::{
+        CommandFrame, ComponentAdded, ComponentChanged, ComponentData, ComponentDiffs, ComponentId,
+        ComponentRemoved, EntityId, EntityInsert, WorldState,
+    },
+};
+
+/// The number of snapshots a `SnapshotHistory` keeps by default.
+pub const DEFAULT_SNAPSHOT_CAPACITY: usize = 64;
+
+/// The replicated state of all entities at a certain command frame.
+///
+/// The server sends a snapshot as a `WorldState` that only contains the differences with a
+/// snapshot the client already has, the baseline. Changed components are encoded as the serde-diff
+/// of the types registered in the `ComponentDiffs`.
+/// The client applies that `WorldState` to its copy of the baseline to get the same snapshot.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorldSnapshot {
+    command_frame: CommandFrame,
+    entities: HashMap<EntityId, HashMap<ComponentId, Vec<u8>>>,
+}
+
+impl WorldSnapshot {
+    /// Returns a new empty `WorldSnapshot` with the given command frame.
+    pub fn new(command_frame: CommandFrame) -> WorldSnapshot {
+        WorldSnapshot {
+            command_frame,
+            entities: HashMap::new(),
+        }
+    }
+
+    /// Returns the command frame on which this snapshot was taken.
+    pub fn command_frame(&self) -> CommandFrame {
+        self.command_frame
+    }
+
+    /// Inserts an entity with the given components, an existing entity is replaced.
+    pub fn insert_entity(&mut self, entity_id: EntityId, components: Vec<ComponentData>) {
+        self.entities.insert(
+            entity_id,
+            components
+                .into_iter()
+                .map(|component| (component.0, component.1))
+                .collect(),
+        );
+    }
+
+    /// Removes an entity and all its components.
+    pub fn remove_entity(&mut self, entity_id: EntityId) {
+        self.entities.remove(&entity_id);
+    }
+
+    /// Adds or replaces a component of the given entity, the entity is inserted if it does not
+    /// exist.
+    pub fn set_component(&mut self, entity_id: EntityId, component: ComponentData) {
+        self.entities
+            .entry(entity_id)
+            .or_default()
+            .insert(component.0, component.1);
+    }
+
+    /// Removes a component of the given entity.
+    pub fn remove_component(&mut self, entity_id: EntityId, component_id: ComponentId) {
+        if let Some(components) = self.entities.get_mut(&entity_id) {
+            components.remove(&component_id);
+        }
+    }
+
+    /// Returns true if the snapshot contains the given entity.
+    pub fn contains_entity(&self, entity_id: EntityId) -> bool {
+        self.entities.contains_key(&entity_id)
+    }
+
+    /// Returns the serialized data of a component of the given entity.
+    pub fn component(&self, entity_id: EntityId, component_id: ComponentId) -> Option<&[u8]> {
+        self.entities
+            .get(&entity_id)
+            .and_then(|components| components.get(&component_id))
+            .map(|data| data.as_slice())
+    }
+
+    /// Returns the ids of the entities in this snapshot.
+    pub fn entity_ids(&self) -> impl Iterator<Item = &EntityId> {
+        self.entities.keys()
+    }
+
+    /// Returns the number of entities in this snapshot.
+    pub fn entity_count(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Returns the `WorldState` that turns `baseline` into this snapshot.
+    ///
+    /// Without a baseline, the returned state inserts all entities of this snapshot. A changed
+    /// component whose type is not registered in `diffs`, or that can not be diffed, is sent whole
+    /// as an added component.
+    pub fn delta(&self, baseline: Option<&WorldSnapshot>, diffs: &ComponentDiffs) -> WorldState {
+        let mut state = WorldState::new(self.command_frame);
+        state.baseline = baseline.map(|baseline| baseline.command_frame);
+        state.is_full = baseline.is_none();
+
+        let empty = HashMap::new();
+        let baseline_entities = baseline.map_or(&empty, |baseline| &baseline.entities);
+
+        for (entity_id, components) in self.entities.iter() {
+            let baseline_components = match baseline_entities.get(entity_id) {
+                Some(baseline_components) => baseline_components,
+                None => {
+                    state
+                        .inserted
+                        .insert(EntityInsert(*entity_id, sorted_components(components)));
+                    continue;
+                }
+            };
+
+            for (component_id, data) in components.iter() {
+                let baseline_data = match baseline_components.get(component_id) {
+                    Some(baseline_data) if baseline_data == data => continue,
+                    Some(baseline_data) if diffs.is_registered(*component_id) => baseline_data,
+                    _ => {
+                        state.component_added.insert(ComponentAdded(
+                            *entity_id,
+                            ComponentData(*component_id, data.clone()),
+                        ));
+                        continue;
+                    }
+                };
+
+                match diffs.diff(*component_id, baseline_data, data) {
+                    Ok(Some(diff)) => {
+                        state.changed.insert(ComponentChanged(
+                            *entity_id,
+                            ComponentData(*component_id, diff),
+                        ));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        error!(
+                            "Error occurred when diffing component {} of entity {}. Reason: {:?}",
+                            component_id, entity_id, e
+                        );
+                        state.component_added.insert(ComponentAdded(
+                            *entity_id,
+                            ComponentData(*component_id, data.clone()),
+                        ));
+                    }
+                }
+            }
+
+            for component_id in baseline_components.keys() {
+                if !components.contains_key(component_id) {
+                    state
+                        .component_removed
+                        .insert(ComponentRemoved(*entity_id, *component_id));
+                }
+            }
+        }
+
+        for entity_id in baseline_entities.keys() {
+            if !self.entities.contains_key(entity_id) {
+                state.removed.insert(*entity_id);
+            }
+        }
+
+        state
+    }
+
+    /// Applies the changes of a `WorldState` to this snapshot and takes over its command frame.
+    ///
+    /// The differences of changed components are applied with the types registered in `diffs`.
+    /// If one can not be applied an error is returned, and the snapshot should be discarded
+    /// because it is applied partially. A full state replaces all entities. Changes of entities that
+    /// are removed by the same state are skipped.
+    pub fn apply(&mut self, state: &WorldState, diffs: &ComponentDiffs) -> Result<(), ErrorKind> {
+        if state.is_full {
+            self.entities.clear();
+        }
+
+        for entity_id in state.removed.iter() {
+            self.remove_entity(*entity_id);
+        }
+
+        for insert in state.inserted.iter() {
+            self.insert_entity(insert.0, insert.1.clone());
+        }
+
+        let is_removed = |snapshot: &WorldSnapshot, entity_id: &EntityId| {
+            state.removed.contains(entity_id) && !snapshot.contains_entity(*entity_id)
+        };
+
+        for removed in state.component_removed.iter() {
+            self.remove_component(removed.0, removed.1);
+        }
+
+        for added in state.component_added.iter() {
+            if !is_removed(self, &added.0) {
+                self.set_component(added.0, added.1.clone());
+            }
+        }
+
+        for changed in state.changed.iter() {
+            if is_removed(self, &changed.0) {
+                continue;
+            }
+
+            let ComponentData(component_id, diff) = &changed.1;
+
+            let data = self
+                .component(changed.0, *component_id)
+                .ok_or(ErrorKind::EntityNotFound(changed.0))?;
+            let data = diffs.apply(*component_id, data, diff)?;
+
+            self.set_component(changed.0, ComponentData(*component_id, data));
+        }
+
+        self.command_frame = state.command_frame;
+        Ok(())
+    }
+}
+
+/// Returns the components ordered by id, so that equal entities serialize to equal bytes.
+fn sorted_components(components: &HashMap<ComponentId, Vec<u8>>) -> Vec<ComponentData> {
+    let mut components = components
+        .iter()
+        .map(|(component_id, data)| ComponentData(*component_id, data.clone()))
+        .collect::<Vec<ComponentData>>();
+    components.sort_by_key(|component| component.0);
+    components
+}
+
+/// A bounded history of snapshots ordered by command frame.
+///
+/// The server uses it to look up the baseline a client acknowledged, the client uses it to look up
+/// the baseline a received `WorldState` is relative to.
+pub struct SnapshotHistory {
+    snapshots: VecDeque<WorldSnapshot>,
+    capacity: usize,
+    diffs: ComponentDiffs,
+}
+
+impl SnapshotHistory {
+    pub fn new() -> SnapshotHistory {
+        SnapshotHistory::with_capacity(DEFAULT_SNAPSHOT_CAPACITY)
+    }
+
+    /// Returns a new `SnapshotHistory` that keeps at most `capacity` snapshots, and at least the
+    /// newest one.
+    pub fn with_capacity(capacity: usize) -> SnapshotHistory {
+        let capacity = capacity.max(1);
+
+        SnapshotHistory {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+            diffs: ComponentDiffs::new(),
+        }
+    }
+
+    /// Returns the component types whose changes are encoded as serde-diff differences.
+    pub fn diffs(&self) -> &ComponentDiffs {
+        &self.diffs
+    }
+
+    /// Returns the component types whose changes are encoded as serde-diff differences, to
+    /// register types.
+    pub fn diffs_mut(&mut self) -> &mut ComponentDiffs {
+        &mut self.diffs
+    }
+
+    /// Adds a snapshot, the oldest snapshot is dropped when the history is full.
+    ///
+    /// A snapshot with the same command frame as the newest snapshot replaces it.
+    pub fn push(&mut self, snapshot: WorldSnapshot) {
+        if let Some(newest) = self.snapshots.back() {
+            if newest.command_frame == snapshot.command_frame {
+                self.snapshots.pop_back();
+            }
+        }
+
+        self.snapshots.push_back(snapshot);
+
+        while self.snapshots.len() > self.capacity {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Returns the snapshot of the given command frame if it is still in the history.
+    pub fn get(&self, command_frame: CommandFrame) -> Option<&WorldSnapshot> {
+        self.snapshots
+            .iter()
+            .find(|snapshot| snapshot.command_frame == command_frame)
+    }
+
+    /// Returns the most recent snapshot.
+    pub fn newest(&self) -> Option<&WorldSnapshot> {
+        self.snapshots.back()
+    }
+
+    /// Returns the `WorldState` that turns the snapshot of `baseline` into `snapshot`.
+    ///
+    /// A full state is returned if there is no baseline, or if it is no longer in the history.
+    pub fn delta(&self, snapshot: &WorldSnapshot, baseline: Option<CommandFrame>) -> WorldState {
+        snapshot.delta(
+            baseline.and_then(|baseline| self.get(baseline)),
+            &self.diffs,
+        )
+    }
+
+    /// Applies a received `WorldState` to its baseline and adds the resulting snapshot.
+    ///
+    /// Returns an error if the baseline of the state is no longer in the history, or if the state
+    /// can not be applied to it. The history is unchanged then.
+    pub fn apply(&mut self, state: &WorldState) -> Result<&WorldSnapshot, ErrorKind> {
+        let mut snapshot = match state.baseline {
+            Some(baseline) => self
+                .get(baseline)
+                .ok_or(ErrorKind::BaselineNotFound(baseline))?
+                .clone(),
+            None => WorldSnapshot::default(),
+        };
+
+        snapshot.apply(state, &self.diffs)?;
+        self.push(snapshot);
+        // The history keeps at least the newest snapshot.
+        Ok(&self.snapshots[self.snapshots.len() - 1])
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl Default for SnapshotHistory {
+    fn default() -> Self {
+        SnapshotHistory::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::ErrorKind,
+        serialization::{DefaultSerialization, SerializationStrategy},
+        synchronisation::{ComponentData, ComponentDiffs, SnapshotHistory, WorldSnapshot},
+    };
+
+    fn component(component_id: u32, value: u32) -> ComponentData {
+        ComponentData::new(
+            component_id,
+            DefaultSerialization::default().serialize(&value).unwrap(),
+        )
+    }
+
+    fn diffs() -> ComponentDiffs {
+        let mut diffs = ComponentDiffs::new();
+        for component_id in 1..=3 {
+            diffs.register::<u32, _>(component_id, DefaultSerialization::default());
+        }
+        diffs
+    }
+
+    fn snapshot(command_frame: u32) -> WorldSnapshot {
+        let mut snapshot = WorldSnapshot::new(command_frame);
+        snapshot.insert_entity(1, vec![component(1, 1), component(2, 2)]);
+        snapshot.insert_entity(2, vec![component(1, 3)]);
+        snapshot.insert_entity(3, vec![component(1, 4)]);
+        snapshot
+    }
+
+    #[test]
+    fn delta_contains_only_differences() {
+        let baseline = snapshot(1);
+
+        let mut current = baseline.clone();
+        current.command_frame = 2;
+        current.set_component(1, component(1, 9));
+        current.remove_component(1, 2);
+        current.set_component(2, component(3, 5));
+        current.remove_entity(3);
+        current.insert_entity(4, vec![component(1, 6)]);
+
+        let state = current.delta(Some(&baseline), &diffs());
+        assert_eq!(state.baseline, Some(1));
+        assert_eq!(state.changed.len(), 1);
+        assert_eq!(state.component_removed.len(), 1);
+        assert_eq!(state.component_added.len(), 1);
+        assert_eq!(state.removed.len(), 1);
+        assert_eq!(state.inserted.len(), 1);
+
+        let mut applied = baseline.clone();
+        applied.apply(&state, &diffs()).unwrap();
+        assert_eq!(applied, current);
+    }
+
+    #[test]
+    fn unregistered_changed_component_is_sent_whole() {
+        let baseline = snapshot(1);
+
+        let mut current = baseline.clone();
+        current.command_frame = 2;
+        current.set_component(1, component(1, 9));
+
+        let state = current.delta(Some(&baseline), &ComponentDiffs::new());
+        assert!(state.changed.is_empty());
+        assert_eq!(state.component_added.len(), 1);
+
+        let mut applied = baseline.clone();
+        applied.apply(&state, &ComponentDiffs::new()).unwrap();
+        assert_eq!(applied, current);
+
+        // A diff can not be applied without the registered type.
+        let state = current.delta(Some(&baseline), &diffs());
+        assert!(matches!(
+            baseline.clone().apply(&state, &ComponentDiffs::new()),
+            Err(ErrorKind::ComponentNotRegistered(1))
+        ));
+    }
+
+    #[test]
+    fn unchanged_snapshot_results_in_empty_state() {
+        let baseline = snapshot(1);
+        let mut current = baseline.clone();
+        current.command_frame = 2;
+
+        assert!(current.delta(Some(&baseline), &diffs()).is_empty());
+        assert_eq!(current.delta(None, &diffs()).inserted.len(), 3);
+    }
+
+    #[test]
+    fn history_falls_back_to_full_state() {
+        let mut history = SnapshotHistory::with_capacity(2);
+        history.push(snapshot(1));
+        history.push(snapshot(2));
+        history.push(snapshot(3));
+
+        assert!(history.get(1).is_none());
+        assert_eq!(history.len(), 2);
+
+        let full = history.delta(&snapshot(4), Some(1));
+        assert_eq!(full.baseline, None);
+        assert_eq!(full.inserted.len(), 3);
+
+        let delta = history.delta(&snapshot(4), Some(2));
+        assert_eq!(delta.baseline, Some(2));
+        assert!(delta.is_empty());
+    }
+
+    #[test]
+    fn client_history_reconstructs_server_snapshots() {
+        let mut server = SnapshotHistory::new();
+        let mut client = SnapshotHistory::new();
+        *server.diffs_mut() = diffs();
+        *client.diffs_mut() = diffs();
+
+        let first = snapshot(1);
+        server.push(first.clone());
+        let applied = client.apply(&server.delta(&first, None)).unwrap();
+        assert_eq!(*applied, first);
+
+        let mut second = first.clone();
+        second.command_frame = 2;
+        second.set_component(2, component(1, 7));
+        server.push(second.clone());
+
+        let state = server.delta(&second, Some(1));
+        assert_eq!(state.changed.len(), 1);
+        let applied = client.apply(&state).unwrap();
+        assert_eq!(*applied, second);
+
+        // A state relative to an unknown baseline can not be applied.
+        let mut state = server.delta(&second, Some(1));
+        state.baseline = Some(10);
+        assert!(matches!(
+            client.apply(&state),
+            Err(ErrorKind::BaselineNotFound(10))
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::Instant;
+
+use crate::synchronisation::CommandFrameTicker;
+
+/// Configuration of the `SimulationSpeedController`.
+#[derive(Clone, Debug)]
+pub struct SimulationSpeedConfig {
+    /// The number of command frames the client should be ahead of the server.
+    pub target_lead: f32,
+    /// The simulation runs at the default speed while the lead is within this number of frames
+    /// from the target.
+    pub tolerance: f32,
+    /// The speed adjustment per frame of error.
+    pub proportional_gain: f32,
+    /// The speed adjustment per frame of error per second.
+    pub integral_gain: f32,
+    /// The weight of a new offset in the smoothed offset, between 0.0 and 1.0.
+    pub smoothing: f32,
+    /// The maximum adjustment as a fraction of the default simulation speed.
+    pub max_adjustment: f32,
+}
+
+impl Default for SimulationSpeedConfig {
+    fn default() -> Self {
+        SimulationSpeedConfig {
+            target_lead: 2.,
+            tolerance: 0.5,
+            proportional_gain: 0.02,
+            integral_gain: 0.01,
+            smoothing: 0.2,
+            max_adjustment: 0.1,
+        }
+    }
+}
+
+/// Adjusts the simulation speed of the client so that its command frame stays a few frames
+/// ahead of the server.
+///
+/// The server reports the offset of the client in `WorldState::command_frame_offset`.
+/// The offsets are smoothed and fed to a PI controller. A client that is not enough ahead of the
+/// server shortens its frame duration, a client that is too far ahead lengthens it.
+pub struct SimulationSpeedController {
+    config: SimulationSpeedConfig,
+    smoothed_offset: Option<f32>,
+    integral: f32,
+    last_update: Option<Instant>,
+}
+
+impl SimulationSpeedController {
+    pub fn new() -> SimulationSpeedController {
+        SimulationSpeedController::with_config(SimulationSpeedConfig::default())
+    }
+
+    pub fn with_config(config: SimulationSpeedConfig) -> SimulationSpeedController {
+        SimulationSpeedController {
+            config,
+            smoothed_offset: None,
+            integral: 0.,
+            last_update: None,
+        }
+    }
+
+    pub fn config(&self) -> &SimulationSpeedConfig {
+        &self.config
+    }
+
+    /// Returns the smoothed command frame offset.
+    pub fn smoothed_offset(&self) -> Option<f32> {
+        self.smoothed_offset
+    }
+
+    /// Processes a command frame offset received at `now` and adjusts the simulation speed of
+    /// the ticker.
+    pub fn update(
+        &mut self,
+        command_frame_offset: i32,
+        now: Instant,
+        ticker: &mut CommandFrameTicker,
+    ) -> f32 {
+        let speed =
+            self.simulation_speed(command_frame_offset, now, ticker.default_simulation_speed());
+        ticker.adjust_simulation(speed);
+        speed
+    }
+
+    /// Processes a command frame offset received at `now` and returns the simulation speed, the
+    /// duration of a frame in milliseconds, relative to `default_simulation_speed`.
+    pub fn simulation_speed(
+        &mut self,
+        command_frame_offset: i32,
+        now: Instant,
+        default_simulation_speed: f32,
+    ) -> f32 {
+        let offset = command_frame_offset as f32;
+        let smoothed = match self.smoothed_offset {
+            Some(smoothed) => smoothed + (offset - smoothed) * self.config.smoothing,
+            None => offset,
+        };
+        self.smoothed_offset = Some(smoothed);
+
+        let elapsed = self
+            .last_update
+            .map_or(0., |last| now.duration_since(last).as_secs_f32());
+        self.last_update = Some(now);
+
+        // A positive error means the client is too far ahead and should slow down.
+        let error = smoothed - self.config.target_lead;
+
+        if error.abs() <= self.config.tolerance {
+            self.integral = 0.;
+            return default_simulation_speed;
+        }
+
+        self.integral += error * elapsed;
+
+        // Limit the integral so that it can not wind up beyond the maximum adjustment.
+        if self.config.integral_gain > 0. {
+            let limit = self.config.max_adjustment / self.config.integral_gain;
+            self.integral = self.integral.max(-limit).min(limit);
+        }
+
+        let adjustment = (self.config.proportional_gain * error
+            + self.config.integral_gain * self.integral)
+            .max(-self.config.max_adjustment)
+            .min(self.config.max_adjustment);
+
+        default_simulation_speed * (1. + adjustment)
+    }
+
+    /// Forgets the received offsets, for example after a reconnect.
+    pub fn reset(&mut self) {
+        self.smoothed_offset = None;
+        self.integral = 0.;
+        self.last_update = None;
+    }
+}
+
+impl Default for SimulationSpeedController {
+    fn default() -> Self {
+        SimulationSpeedController::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::synchronisation::{
+        CommandFrameTicker, SimulationSpeedConfig, SimulationSpeedController,
+    };
+
+    /// Returns the instants of a clock that ticks every 100 milliseconds.
+    fn fake_clock() -> impl Iterator<Item = Instant> {
+        let start = Instant::now();
+        (0..).map(move |tick| start + Duration::from_millis(100 * tick))
+    }
+
+    #[test]
+    fn client_behind_server_speeds_up() {
+        let mut controller = SimulationSpeedController::new();
+        let mut clock = fake_clock();
+
+        let first = controller.simulation_speed(-2, clock.next().unwrap(), 10.);
+        assert!(first < 10.);
+
+        // The integral makes the adjustment grow while the error persists.
+        let mut speed = first;
+        for _ in 0..10 {
+            speed = controller.simulation_speed(-2, clock.next().unwrap(), 10.);
+        }
+        assert!(speed < first);
+    }
+
+    #[test]
+    fn client_too_far_ahead_slows_down_within_bounds() {
+        let mut controller = SimulationSpeedController::new();
+        let mut clock = fake_clock();
+
+        let mut speed = 0.;
+        for _ in 0..100 {
+            speed = controller.simulation_speed(50, clock.next().unwrap(), 10.);
+        }
+
+        assert!(speed > 10.);
+        assert!(speed <= 11. + f32::EPSILON);
+    }
+
+    #[test]
+    fn returns_to_default_speed_when_in_range() {
+        let mut controller = SimulationSpeedController::with_config(SimulationSpeedConfig {
+            smoothing: 1.,
+            ..SimulationSpeedConfig::default()
+        });
+        let mut ticker = CommandFrameTicker::new(10.);
+        let mut clock = fake_clock();
+
+        controller.update(-3, clock.next().unwrap(), &mut ticker);
+        assert!(ticker.simulation_speed() < 10.);
+
+        controller.update(2, clock.next().unwrap(), &mut ticker);
+        assert_eq!(ticker.simulation_speed(), 10.);
+    }
+
+    #[test]
+    fn slowed_down_ticker_waits_longer_before_ticking() {
+        let mut controller = SimulationSpeedController::with_config(SimulationSpeedConfig {
+            max_adjustment: 0.5,
+            ..SimulationSpeedConfig::default()
+        });
+        let mut ticker = CommandFrameTicker::new(200.);
+        let start = Instant::now();
+
+        controller.update(100, start, &mut ticker);
+        assert!(ticker.frame_duration() > Duration::from_millis(299));
+
+        ticker.advance_at(start);
+        // The default frame duration passed, the adjusted one did not.
+        assert!(!ticker.try_tick_at(start + Duration::from_millis(220)));
+
+        assert!(ticker.try_tick_at(start + Duration::from_millis(320)));
+        assert_eq!(ticker.command_frame(), 2);
+    }
+}
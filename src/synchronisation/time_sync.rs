@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::synchronisation::{CommandFrame, CommandFrameTicker};
+
+/// The number of time sync samples a `ClockSynchronizer` keeps by default.
+pub const DEFAULT_TIME_SYNC_SAMPLES: usize = 8;
+
+/// Returns the current time in microseconds since the unix epoch.
+///
+/// The timestamps of the time sync messages use this clock.
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// The reply of the server to a `ClientToServerMessage::TimeSync` request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSyncResponse {
+    /// The timestamp at which the client sent the request, copied from the request.
+    pub client_sent: u64,
+    /// The timestamp at which the server received the request.
+    pub server_received: u64,
+    /// The timestamp at which the server sent this reply.
+    pub server_sent: u64,
+    /// The command frame of the server when it sent this reply.
+    pub command_frame: CommandFrame,
+}
+
+impl TimeSyncResponse {
+    /// Returns the time sync sample for this reply, received by the client at `client_received`.
+    pub fn sample(&self, client_received: u64) -> TimeSyncSample {
+        let client_sent = self.client_sent as i64;
+        let server_received = self.server_received as i64;
+        let server_sent = self.server_sent as i64;
+        let client_received = client_received as i64;
+
+        let round_trip_time = (client_received - client_sent) - (server_sent - server_received);
+        let clock_offset = ((server_received - client_sent) + (server_sent - client_received)) / 2;
+
+        TimeSyncSample {
+            round_trip_time: Duration::from_micros(round_trip_time.max(0) as u64),
+            clock_offset,
+            command_frame: self.command_frame,
+            server_sent: self.server_sent,
+        }
+    }
+}
+
+/// A measurement of a single time sync round trip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSyncSample {
+    /// The round trip time without the processing time of the server.
+    pub round_trip_time: Duration,
+    /// The number of microseconds the server clock is ahead of the client clock.
+    pub clock_offset: i64,
+    /// The command frame of the server when it sent the reply.
+    pub command_frame: CommandFrame,
+    /// The server timestamp at which the server was at `command_frame`.
+    pub server_sent: u64,
+}
+
+/// Estimates the round trip time, the clock offset and the current command frame of the server
+/// from the time sync replies of the server.
+///
+/// The sample with the lowest round trip time is used, it is the least affected by queueing
+/// delays.
+pub struct ClockSynchronizer {
+    samples: VecDeque<TimeSyncSample>,
+    capacity: usize,
+}
+
+impl ClockSynchronizer {
+    pub fn new() -> ClockSynchronizer {
+        ClockSynchronizer::with_capacity(DEFAULT_TIME_SYNC_SAMPLES)
+    }
+
+    /// Returns a new `ClockSynchronizer` that keeps the last `capacity` samples.
+    pub fn with_capacity(capacity: usize) -> ClockSynchronizer {
+        ClockSynchronizer {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Processes a reply of the server received at the `client_received` timestamp.
+    pub fn receive(&mut self, response: &TimeSyncResponse, client_received: u64) {
+        self.samples.push_back(response.sample(client_received));
+
+        while self.samples.len() > self.capacity {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Returns the sample with the lowest round trip time.
+    pub fn best_sample(&self) -> Option<&TimeSyncSample> {
+        self.samples
+            .iter()
+            .min_by_key(|sample| sample.round_trip_time)
+    }
+
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        self.best_sample().map(|sample| sample.round_trip_time)
+    }
+
+    /// Returns the number of microseconds the server clock is ahead of the client clock.
+    pub fn clock_offset(&self) -> Option<i64> {
+        self.best_sample().map(|sample| sample.clock_offset)
+    }
+
+    /// Returns the estimated command frame of the server at the client timestamp `now`.
+    ///
+    /// `frame_duration` is the duration of a command frame on the server.
+    pub fn estimated_server_frame(
+        &self,
+        now: u64,
+        frame_duration: Duration,
+    ) -> Option<CommandFrame> {
+        let sample = self.best_sample()?;
+
+        let server_now = now as i64 + sample.clock_offset;
+        let elapsed = (server_now - sample.server_sent as i64).max(0) as u128;
+        let frames = elapsed / frame_duration.as_micros().max(1);
+
+        Some(sample.command_frame + frames as CommandFrame)
+    }
+
+    /// Sets the command frame of the ticker so that the commands of the client arrive at the
+    /// server `lead` frames before the server simulates them.
+    ///
+    /// Returns the command frame that was set, or `None` if no sample was received yet.
+    pub fn initialize_ticker(
+        &self,
+        ticker: &mut CommandFrameTicker,
+        now: u64,
+        lead: CommandFrame,
+    ) -> Option<CommandFrame> {
+        let frame_duration =
+            Duration::from_micros((ticker.default_simulation_speed() * 1000.) as u64);
+        let server_frame = self.estimated_server_frame(now, frame_duration)?;
+
+        // The commands need half a round trip to reach the server.
+        let one_way = self.round_trip_time()?.as_micros() / 2;
+        let travel_frames = one_way.div_ceil(frame_duration.as_micros().max(1));
+
+        let command_frame = server_frame + travel_frames as CommandFrame + lead;
+        ticker.set_command_frame(command_frame);
+        Some(command_frame)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+impl Default for ClockSynchronizer {
+    fn default() -> Self {
+        ClockSynchronizer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::synchronisation::{ClockSynchronizer, CommandFrameTicker, TimeSyncResponse};
+
+    /// The server clock is 1 second ahead, the network takes 20ms each way and the server needs
+    /// 2ms to reply.
+    fn response(client_sent: u64) -> TimeSyncResponse {
+        TimeSyncResponse {
+            client_sent,
+            server_received: client_sent + 1_020_000,
+            server_sent: client_sent + 1_022_000,
+            command_frame: 100,
+        }
+    }
+
+    #[test]
+    fn sample_calculates_round_trip_time_and_offset() {
+        let sample = response(5_000_000).sample(5_042_000);
+
+        assert_eq!(sample.round_trip_time, Duration::from_millis(40));
+        assert_eq!(sample.clock_offset, 1_000_000);
+    }
+
+    #[test]
+    fn synchronizer_uses_sample_with_lowest_round_trip_time() {
+        let mut synchronizer = ClockSynchronizer::new();
+        synchronizer.receive(&response(0), 42_000);
+        // This reply was delayed 100ms by the network.
+        synchronizer.receive(&response(0), 142_000);
+
+        assert_eq!(
+            synchronizer.round_trip_time(),
+            Some(Duration::from_millis(40))
+        );
+        assert_eq!(synchronizer.clock_offset(), Some(1_000_000));
+    }
+
+    #[test]
+    fn synchronizer_initializes_ticker_ahead_of_server() {
+        let mut synchronizer = ClockSynchronizer::new();
+        synchronizer.receive(&response(0), 42_000);
+
+        // 100ms after the reply was sent the server is 10 frames further.
+        assert_eq!(
+            synchronizer.estimated_server_frame(122_000, Duration::from_millis(10)),
+            Some(110)
+        );
+
+        // Commands need 20ms, two frames, to arrive at the server.
+        let mut ticker = CommandFrameTicker::new(10.);
+        assert_eq!(
+            synchronizer.initialize_ticker(&mut ticker, 122_000, 2),
+            Some(114)
+        );
+        assert_eq!(ticker.command_frame(), 114);
+    }
+}
@@ -2,6 +2,7 @@
 
 use std::{any::TypeId, fmt::Debug};
 
+use log::error;
 use serde::Serialize;
 use serde_diff::SerdeDiff;
 
@@ -10,6 +11,8 @@ pub use server_tracker::ServerModificationTracker;
 pub use track::TrackResource;
 
 use crate::{
+    error::ErrorKind,
+    serialization::SerializationStrategy,
     synchronisation::{CommandFrame, NetworkCommand},
     uid::Uid,
 };
@@ -46,6 +49,9 @@ pub trait TrackableMarker: Clone + SerdeDiff + Serialize + Debug + Send + Sync +
 
 // server push/client push/ voor tracker
 pub trait ServerChangeTracker {
+    /// The strategy used to serialize the tracked components.
+    type Serialization: SerializationStrategy;
+
     fn push(
         &mut self,
         command_frame: CommandFrame,
@@ -53,9 +59,21 @@ pub trait ServerChangeTracker {
         unchanged_serialized: Vec<u8>,
         component_type: TypeId,
     );
+
+    /// Called when the modification of a component could not be serialized, the modification is
+    /// not tracked.
+    fn push_error(&mut self, entity_id: Uid, error: ErrorKind) {
+        error!(
+            "Error occurred when tracking modification of entity {}. Reason: {:?}",
+            entity_id, error
+        );
+    }
 }
 
 pub trait ClientChangeTracker<C: NetworkCommand> {
+    /// The strategy used to serialize the tracked components.
+    type Serialization: SerializationStrategy;
+
     fn push(
         &mut self,
         command: C,
@@ -65,4 +83,13 @@ pub trait ClientChangeTracker<C: NetworkCommand> {
         changed_serialized: Vec<u8>,
         component_type: TypeId,
     );
+
+    /// Called when the modification of a component could not be serialized, the modification is
+    /// not tracked.
+    fn push_error(&mut self, entity_id: Uid, error: ErrorKind) {
+        error!(
+            "Error occurred when tracking modification of entity {}. Reason: {:?}",
+            entity_id, error
+        );
+    }
 }
@@ -7,6 +7,7 @@ use serde_diff::{Config, Diff, FieldPathMode};
 
 use crate::{
     error::ErrorKind,
+    serialization::SerializationStrategy,
     synchronisation::{CommandFrame, NetworkCommand},
     tracker::{ClientChangeTracker, TrackableMarker},
     uid::Uid,
@@ -26,6 +27,7 @@ where
     unchanged: Component,
     borrow: &'borrow mut Component,
     tracker: &'notifier mut Tracker,
+    serialization: Tracker::Serialization,
     identifier: Uid,
     command_frame: CommandFrame,
     command: Command,
@@ -40,8 +42,7 @@ where
     /// Constructs a new tracker.
     ///
     /// * `borrow`: mutable reference to the object which modifications are tracked.
-    /// * `serialization`: an instance of a type that implements [SerializationStrategy](../track/serialization/trait.SerializationStrategy.html) strategy.
-    ///     This serializer is needed to monitor the changes and the serialized mutations are sent along with the event.
+    /// * `tracker`: the tracker that is notified of modifications, its [SerializationStrategy](../serialization/trait.SerializationStrategy.html) is used to monitor the changes and to serialize the component values.
     pub fn new(
         borrow: &'borrow mut C,
         tracker: &'notifier mut T,
@@ -53,6 +54,7 @@ where
             unchanged: (borrow.deref()).clone(),
             borrow,
             tracker,
+            serialization: Default::default(),
             identifier,
             command_frame,
             command,
@@ -64,12 +66,11 @@ where
     }
 
     pub fn serialize_unchanged(&self) -> Result<Vec<u8>, ErrorKind> {
-        bincode::serialize(&self.unchanged)
-            .map_err(|e| ErrorKind::SerializationError(e.to_string()))
+        self.serialization.serialize(&self.unchanged)
     }
 
     pub fn serialize_changed(&self) -> Result<Vec<u8>, ErrorKind> {
-        bincode::serialize(&self.borrow).map_err(|e| ErrorKind::SerializationError(e.to_string()))
+        self.serialization.serialize(&self.borrow)
     }
 
     fn configure_diff(&self) -> Diff<'_, '_, C> {
@@ -114,37 +115,33 @@ where
 {
     /// Checks to see if any field values have changed.
     /// If this is the case, the changed fields will be packed into an event and an event will be sent.
+    /// Serialization errors are passed to the tracker instead of being raised.
     fn drop(&mut self) {
         let diff = self.configure_diff();
 
-        match bincode::serialize(&diff) {
-            Ok(_data) => {
-                if diff.has_changes() {
-                    let unchanged_serialized = self
-                        .serialize_unchanged()
-                        .expect("Error while serializing unchanged component.");
-                    let changed_serialized = self
-                        .serialize_changed()
-                        .expect("Error while serializing unchanged component.");
-
-                    self.tracker.push(
-                        self.command.clone(),
-                        self.command_frame,
-                        self.identifier,
-                        unchanged_serialized,
-                        changed_serialized,
-                        TypeId::of::<C>(),
-                    );
-                } else {
-                    println!("not different")
-                }
-            }
-            Err(e) => {
-                panic!(
-                    "Could not serialize modification information because: {:?}",
-                    e
-                );
-            }
-        };
+        if let Err(e) = self.serialization.serialize(&diff) {
+            self.tracker.push_error(self.identifier, e);
+            return;
+        }
+
+        if !diff.has_changes() {
+            return;
+        }
+
+        let serialized = self
+            .serialize_unchanged()
+            .and_then(|unchanged| Ok((unchanged, self.serialize_changed()?)));
+
+        match serialized {
+            Ok((unchanged_serialized, changed_serialized)) => self.tracker.push(
+                self.command.clone(),
+                self.command_frame,
+                self.identifier,
+                unchanged_serialized,
+                changed_serialized,
+                TypeId::of::<C>(),
+            ),
+            Err(e) => self.tracker.push_error(self.identifier, e),
+        }
     }
 }
@@ -7,6 +7,7 @@ use serde_diff::{Config, Diff, FieldPathMode};
 
 use crate::{
     error::ErrorKind,
+    serialization::SerializationStrategy,
     synchronisation::CommandFrame,
     tracker::{ServerChangeTracker, TrackableMarker},
     uid::Uid,
@@ -25,6 +26,7 @@ where
     unchanged: Component,
     borrow: &'borrow mut Component,
     tracker: &'notifier mut Tracker,
+    serialization: Tracker::Serialization,
     identifier: Uid,
     command_frame: CommandFrame,
 }
@@ -37,8 +39,7 @@ where
     /// Constructs a new tracker.
     ///
     /// * `borrow`: mutable reference to the object which modifications are tracked.
-    /// * `serialization`: an instance of a type that implements [SerializationStrategy](../track/serialization/trait.SerializationStrategy.html) strategy.
-    ///     This serializer is needed to monitor the changes and the serialized mutations are sent along with the event.
+    /// * `tracker`: the tracker that is notified of modifications, its [SerializationStrategy](../serialization/trait.SerializationStrategy.html) is used to monitor the changes and to serialize the component values.
     pub fn new(
         borrow: &'borrow mut C,
         tracker: &'notifier mut T,
@@ -49,6 +50,7 @@ where
             unchanged: (borrow.deref()).clone(),
             borrow,
             tracker,
+            serialization: Default::default(),
             identifier,
             command_frame,
         }
@@ -59,8 +61,7 @@ where
     }
 
     pub fn serialize_unchanged(&self) -> Result<Vec<u8>, ErrorKind> {
-        bincode::serialize(&self.unchanged)
-            .map_err(|e| ErrorKind::SerializationError(e.to_string()))
+        self.serialization.serialize(&self.unchanged)
     }
 
     fn configure_diff(&self) -> Diff<'_, '_, C> {
@@ -101,27 +102,80 @@ where
 {
     /// Checks to see if any field values have changed.
     /// If this is the case, the changed fields will be packed into an event and an event will be sent.
+    /// Serialization errors are passed to the tracker instead of being raised.
     fn drop(&mut self) {
         let diff = self.configure_diff();
 
-        match bincode::serialize(&diff) {
-            Ok(_data) => {
-                if diff.has_changes() {
-                    self.tracker.push(
-                        self.command_frame,
-                        self.identifier,
-                        self.serialize_unchanged()
-                            .expect("Error while serializing unchanged component."),
-                        TypeId::of::<C>(),
-                    );
-                }
-            }
-            Err(e) => {
-                panic!(
-                    "Could not serialize modification information because: {:?}",
-                    e
-                );
-            }
-        };
+        if let Err(e) = self.serialization.serialize(&diff) {
+            self.tracker.push_error(self.identifier, e);
+            return;
+        }
+
+        if !diff.has_changes() {
+            return;
+        }
+
+        match self.serialize_unchanged() {
+            Ok(unchanged_serialized) => self.tracker.push(
+                self.command_frame,
+                self.identifier,
+                unchanged_serialized,
+                TypeId::of::<C>(),
+            ),
+            Err(e) => self.tracker.push_error(self.identifier, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_diff::SerdeDiff;
+
+    use crate::{
+        error::ErrorKind,
+        serialization::SerializationStrategy,
+        synchronisation::ModifiedComponentsBuffer,
+        tracker::{ServerModificationTracker, TrackableMarker},
+    };
+
+    #[derive(Clone, Default)]
+    struct FailingSerialization;
+
+    impl SerializationStrategy for FailingSerialization {
+        fn serialize<T: ?Sized + Serialize>(&self, _value: &T) -> Result<Vec<u8>, ErrorKind> {
+            Err(ErrorKind::SerializationError("Always fails.".to_string()))
+        }
+
+        fn deserialize<T: DeserializeOwned>(&self, _buffer: &[u8]) -> Result<T, ErrorKind> {
+            Err(ErrorKind::SerializationError("Always fails.".to_string()))
+        }
+
+        fn apply_diff<T: SerdeDiff>(
+            &self,
+            _buffer: &[u8],
+            _target: &mut T,
+        ) -> Result<(), ErrorKind> {
+            Err(ErrorKind::SerializationError("Always fails.".to_string()))
+        }
+    }
+
+    impl TrackableMarker for u32 {}
+
+    #[test]
+    fn serialization_error_is_passed_to_tracker() {
+        let mut position = 1u32;
+        let mut buffer = ModifiedComponentsBuffer::<FailingSerialization>::new();
+
+        {
+            let mut tracked = ServerModificationTracker::new(&mut position, &mut buffer, 3, 1);
+            *tracked = 2;
+        }
+
+        assert!(buffer.entries.is_empty());
+        assert!(matches!(
+            buffer.drain_errors().next(),
+            Some((3, ErrorKind::SerializationError(_)))
+        ));
     }
 }
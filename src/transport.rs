@@ -1,14 +1,224 @@
 //! This module provides code for transporting data from one endpoint to another.
+//!
+//! The transport backend is abstracted by the [ServerTransport](./trait.ServerTransport.html) and
+//! [ClientTransport](./trait.ClientTransport.html) traits.
+//! Systems that are generic over these traits work with any backend.
+//!
+//! | Backend | Description |
+//! | :-- | :-- |
+//! | [tcp](./tcp/index.html) | Reliable and ordered transport over TCP streams. |
+//! | [udp](./udp/index.html) | Reliable and unreliable-sequenced transport over UDP datagrams. |
+//! | [memory](./memory/index.html) | In-process transport over channels, for tests and listen servers. |
+//!
+//! The backends compress their packets with the [Packer](./struct.Packer.html) of the
+//! `CompressionStrategy` they are created with.
+//!
+//! Before a client is registered with the `PostOffice`, it introduces itself with a
+//! [Hello](./struct.Hello.html). The server checks the protocol version and game id of the hello
+//! against its [HandshakeConfig](./struct.HandshakeConfig.html), after which the
+//! [Authenticator](./trait.Authenticator.html) of the `PostOffice` decides whether the client is
+//! accepted.
+//!
+//! Both sides send heartbeats when they have nothing else to send, and disconnect a remote from
+//! which nothing was received within the timeout of their
+//! [HeartbeatConfig](./struct.HeartbeatConfig.html).
+//!
+//! Either side can close the connection with a reason, the remote receives the
+//! [DisconnectReason](./enum.DisconnectReason.html) after the messages that were sent before it.
+//!
+//! Any backend can be wrapped by the transports in [conditioner](./conditioner/index.html) to
+//! simulate latency, jitter, loss, duplication and reordering.
 
 pub use self::{
+    acknowledgement::{SnapshotAcknowledgements, SnapshotAcknowledger, ACKNOWLEDGEMENT_BITS},
+    authentication::{
+        AcceptAll, Authentication, Authenticator, SharedSecretAuthenticator, UserData,
+    },
     client::{Client, ClientId},
+    control::{
+        ControlPacket, DisconnectReason, HandshakeConfig, HandshakeReply, Hello, RejectReason,
+        SessionToken, PROTOCOL_VERSION,
+    },
+    framing::{FrameReader, FrameWriter, DEFAULT_MAX_FRAME_SIZE, FRAME_HEADER_SIZE},
+    heartbeat::HeartbeatConfig,
     message::*,
+    packer::{Packer, Payload, DEFAULT_COMPRESSION_THRESHOLD, PACKET_FLAG_SIZE},
     postbox::PostBox,
     postoffice::PostOffice,
 };
 
+use crate::{
+    event::NetworkEventQueue,
+    synchronisation::{CommandFrame, NetworkCommand, NetworkMessage},
+};
+
+mod acknowledgement;
+mod authentication;
 mod client;
+pub mod conditioner;
+mod control;
+mod framing;
+mod heartbeat;
+pub mod memory;
 mod message;
+mod packer;
 mod postbox;
 mod postoffice;
 pub mod tcp;
+pub mod udp;
+
+/// The server side of a transport backend.
+///
+/// A server transport moves messages between the network and the client postboxes of the
+/// `PostOffice` and reports connection changes as `NetworkEvent`s.
+pub trait ServerTransport {
+    /// Accepts new connections, they are registered as clients in the `PostOffice` once their
+    /// handshake is accepted.
+    fn accept<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    );
+
+    /// Receives incoming messages and adds them to the inbox of the sending client.
+    ///
+    /// The `command_frame` is the current server command frame, received commands are buffered
+    /// relative to it.
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        command_frame: CommandFrame,
+        network_events: &mut NetworkEventQueue,
+    );
+
+    /// Sends the outgoing messages of all clients.
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    );
+
+    /// Sends the outgoing messages of the client followed by a disconnect with the given reason,
+    /// then closes the connection and removes the client from the `PostOffice`.
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        client_id: ClientId,
+        reason: DisconnectReason,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    );
+
+    /// Disconnects all clients with `DisconnectReason::ServerShutdown`.
+    fn shutdown<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let client_ids = postoffice
+            .clients()
+            .map(|(client_id, _)| *client_id)
+            .collect::<Vec<_>>();
+
+        for client_id in client_ids {
+            self.disconnect(
+                client_id,
+                DisconnectReason::ServerShutdown,
+                postoffice,
+                network_events,
+            );
+        }
+    }
+}
+
+/// The client side of a transport backend.
+///
+/// A client transport moves messages between the network and the client `PostBox` and reports
+/// connection changes as `NetworkEvent`s.
+pub trait ClientTransport {
+    /// Receives incoming messages from the server and adds them to the inbox of the postbox.
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            self::ServerToClientMessage<ServerToClientMessage>,
+            self::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    );
+
+    /// Sends the outgoing messages of the postbox to the server.
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            self::ServerToClientMessage<ServerToClientMessage>,
+            self::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    );
+
+    /// Sends the outgoing messages of the postbox followed by a disconnect with the given reason,
+    /// then closes the connection.
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        reason: DisconnectReason,
+        postbox: &mut PostBox<
+            self::ServerToClientMessage<ServerToClientMessage>,
+            self::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    );
+
+    /// Returns true if the transport is connected with the server.
+    fn is_connected(&self) -> bool;
+}
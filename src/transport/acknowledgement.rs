@@ -0,0 +1,315 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::ErrorKind,
+    synchronisation::{CommandFrame, SnapshotHistory, WorldSnapshot, WorldState},
+    transport::ClientToServerMessage,
+};
+
+/// The number of frames before the latest frame that are acknowledged by the bitfield.
+pub const ACKNOWLEDGEMENT_BITS: u32 = 32;
+
+/// The number of frames before the latest acknowledged or sent frame for which bookkeeping is
+/// kept.
+const ACKNOWLEDGEMENT_WINDOW: u32 = 1024;
+
+/// Returns the bitfield that acknowledges the frames before `latest`.
+///
+/// Bit `n` is set if frame `latest - n - 1` is acknowledged.
+fn acknowledgement_bits(latest: CommandFrame, frames: &BTreeSet<CommandFrame>) -> u32 {
+    let mut bits = 0;
+
+    for n in 0..ACKNOWLEDGEMENT_BITS.min(latest) {
+        if frames.contains(&(latest - n - 1)) {
+            bits |= 1 << n;
+        }
+    }
+
+    bits
+}
+
+/// Keeps track of the world states a client received and creates the acknowledgement for them.
+///
+/// The client applies each received `ServerToClientMessage::StateUpdate` with `apply`, or with
+/// `apply_world_state` followed by `receive`, and sends `message` to the server every tick.
+/// A state is only acknowledged once it is applied, so the server never encodes against a
+/// baseline the client does not have.
+pub struct SnapshotAcknowledger {
+    received: BTreeSet<CommandFrame>,
+}
+
+impl SnapshotAcknowledger {
+    pub fn new() -> SnapshotAcknowledger {
+        SnapshotAcknowledger {
+            received: BTreeSet::new(),
+        }
+    }
+
+    /// Marks the world state of the given command frame as received and applied.
+    pub fn receive(&mut self, command_frame: CommandFrame) {
+        self.received.insert(command_frame);
+
+        let latest = *self.received.iter().next_back().unwrap_or(&command_frame);
+        self.received = self
+            .received
+            .split_off(&latest.saturating_sub(ACKNOWLEDGEMENT_BITS));
+    }
+
+    /// Applies a received world state to the history, and marks it as received if it applied.
+    pub fn apply<'a>(
+        &mut self,
+        history: &'a mut SnapshotHistory,
+        state: &WorldState,
+    ) -> Result<&'a WorldSnapshot, ErrorKind> {
+        let snapshot = history.apply(state)?;
+        self.receive(state.command_frame);
+        Ok(snapshot)
+    }
+
+    /// Returns the most recent received command frame and the bitfield of the frames before it.
+    pub fn acknowledgement(&self) -> Option<(CommandFrame, u32)> {
+        self.received
+            .iter()
+            .next_back()
+            .map(|latest| (*latest, acknowledgement_bits(*latest, &self.received)))
+    }
+
+    /// Returns the acknowledgement as a message that can be sent to the server.
+    pub fn message<ClientToServerMessage, ClientToServerCommand>(
+        &self,
+    ) -> Option<self::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>> {
+        self.acknowledgement()
+            .map(|(latest, bits)| self::ClientToServerMessage::Acknowledge(latest, bits))
+    }
+}
+
+impl Default for SnapshotAcknowledger {
+    fn default() -> Self {
+        SnapshotAcknowledger::new()
+    }
+}
+
+/// The server side bookkeeping of the world states a single client acknowledged.
+pub struct SnapshotAcknowledgements {
+    latest: Option<CommandFrame>,
+    acknowledged: BTreeSet<CommandFrame>,
+    sent: BTreeMap<CommandFrame, Instant>,
+    round_trip_time: Option<Duration>,
+}
+
+impl SnapshotAcknowledgements {
+    pub fn new() -> SnapshotAcknowledgements {
+        SnapshotAcknowledgements {
+            latest: None,
+            acknowledged: BTreeSet::new(),
+            sent: BTreeMap::new(),
+            round_trip_time: None,
+        }
+    }
+
+    /// Records the moment the world state of the given command frame was sent to the client.
+    ///
+    /// Frames that are older than the bookkeeping window are forgotten, so the bookkeeping of a
+    /// client that never acknowledges stays bounded.
+    pub fn sent(&mut self, command_frame: CommandFrame, now: Instant) {
+        self.sent.entry(command_frame).or_insert(now);
+
+        let oldest = command_frame.saturating_sub(ACKNOWLEDGEMENT_WINDOW);
+        while let Some(&frame) = self.sent.keys().next() {
+            if frame >= oldest {
+                break;
+            }
+            self.sent.remove(&frame);
+        }
+    }
+
+    /// Processes an acknowledgement of the client.
+    ///
+    /// The round trip time is measured from the moment the latest acknowledged frame was sent.
+    /// Frames that were never sent to the client are ignored, an acknowledgement with such a
+    /// latest frame is dropped altogether.
+    pub fn acknowledge(&mut self, latest: CommandFrame, bits: u32, now: Instant) {
+        if !self.sent.contains_key(&latest) && !self.acknowledged.contains(&latest) {
+            return;
+        }
+
+        let mut frames = vec![latest];
+        frames.extend(
+            (0..ACKNOWLEDGEMENT_BITS.min(latest))
+                .filter(|n| bits & (1 << n) != 0)
+                .map(|n| latest - n - 1),
+        );
+
+        for frame in frames {
+            if let Some(sent_at) = self.sent.remove(&frame) {
+                self.acknowledged.insert(frame);
+
+                if frame == latest {
+                    self.round_trip_time = Some(now.duration_since(sent_at));
+                }
+            }
+        }
+
+        if self.latest < Some(latest) {
+            self.latest = Some(latest);
+        }
+
+        if let Some(latest) = self.latest {
+            let oldest = latest.saturating_sub(ACKNOWLEDGEMENT_WINDOW);
+            self.acknowledged = self.acknowledged.split_off(&oldest);
+            self.sent = self.sent.split_off(&oldest);
+        }
+    }
+
+    /// Returns the most recent command frame the client acknowledged.
+    pub fn latest(&self) -> Option<CommandFrame> {
+        self.latest
+    }
+
+    /// Returns true if the client acknowledged the world state of the given command frame.
+    ///
+    /// Frames older than the bookkeeping window are reported as not acknowledged.
+    pub fn is_acknowledged(&self, command_frame: CommandFrame) -> bool {
+        self.acknowledged.contains(&command_frame)
+    }
+
+    /// Returns the command frames that were sent to the client but are not acknowledged yet.
+    pub fn unacknowledged(&self) -> impl Iterator<Item = &CommandFrame> {
+        self.sent.keys()
+    }
+
+    /// Returns the most recent measured round trip time.
+    pub fn round_trip_time(&self) -> Option<Duration> {
+        self.round_trip_time
+    }
+}
+
+impl Default for SnapshotAcknowledgements {
+    fn default() -> Self {
+        SnapshotAcknowledgements::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        error::ErrorKind,
+        synchronisation::{ComponentData, SnapshotHistory, WorldSnapshot},
+        transport::{SnapshotAcknowledgements, SnapshotAcknowledger},
+    };
+
+    #[test]
+    fn acknowledger_sets_bits_for_previous_frames() {
+        let mut acknowledger = SnapshotAcknowledger::new();
+        assert_eq!(acknowledger.acknowledgement(), None);
+
+        acknowledger.receive(10);
+        acknowledger.receive(8);
+        acknowledger.receive(11);
+
+        // Frame 10 is one frame before 11, frame 8 three frames.
+        assert_eq!(acknowledger.acknowledgement(), Some((11, 0b101)));
+
+        acknowledger.receive(100);
+        assert_eq!(acknowledger.acknowledgement(), Some((100, 0)));
+    }
+
+    #[test]
+    fn acknowledgements_process_bitfield() {
+        let now = Instant::now();
+        let mut acknowledgements = SnapshotAcknowledgements::new();
+
+        for frame in 1..=4 {
+            acknowledgements.sent(frame, now);
+        }
+
+        acknowledgements.acknowledge(4, 0b10, now + Duration::from_millis(50));
+
+        assert_eq!(acknowledgements.latest(), Some(4));
+        assert!(acknowledgements.is_acknowledged(4));
+        assert!(acknowledgements.is_acknowledged(2));
+        assert!(!acknowledgements.is_acknowledged(3));
+        assert_eq!(
+            acknowledgements.unacknowledged().collect::<Vec<_>>(),
+            vec![&1, &3]
+        );
+        assert_eq!(
+            acknowledgements.round_trip_time(),
+            Some(Duration::from_millis(50))
+        );
+
+        // An older acknowledgement that arrives late does not move the latest frame back.
+        acknowledgements.acknowledge(3, 0, now + Duration::from_millis(60));
+        assert_eq!(acknowledgements.latest(), Some(4));
+        assert!(acknowledgements.is_acknowledged(3));
+    }
+
+    #[test]
+    fn acknowledgement_of_frames_never_sent_is_ignored() {
+        let now = Instant::now();
+        let mut acknowledgements = SnapshotAcknowledgements::new();
+
+        acknowledgements.sent(1, now);
+        acknowledgements.sent(2, now);
+
+        acknowledgements.acknowledge(5_000, 0, now);
+        assert_eq!(acknowledgements.latest(), None);
+        assert!(!acknowledgements.is_acknowledged(5_000));
+        assert_eq!(acknowledgements.unacknowledged().count(), 2);
+
+        // Frame 3 was never sent, frame 1 was.
+        acknowledgements.sent(4, now);
+        acknowledgements.acknowledge(4, 0b101, now);
+        assert_eq!(acknowledgements.latest(), Some(4));
+        assert!(acknowledgements.is_acknowledged(1));
+        assert!(!acknowledgements.is_acknowledged(3));
+        assert_eq!(
+            acknowledgements.unacknowledged().collect::<Vec<_>>(),
+            vec![&2]
+        );
+    }
+
+    #[test]
+    fn acknowledger_only_acknowledges_applied_states() {
+        let mut history = SnapshotHistory::new();
+        let mut acknowledger = SnapshotAcknowledger::new();
+
+        let mut first = WorldSnapshot::new(1);
+        first.insert_entity(1, vec![ComponentData::new(1, vec![1])]);
+        let second = WorldSnapshot::new(2);
+
+        let full = first.delta(None, history.diffs());
+        acknowledger.apply(&mut history, &full).unwrap();
+        assert_eq!(acknowledger.acknowledgement(), Some((1, 0)));
+
+        // The baseline of this state was never received.
+        let mut delta = second.delta(Some(&first), history.diffs());
+        delta.baseline = Some(0);
+        assert!(matches!(
+            acknowledger.apply(&mut history, &delta),
+            Err(ErrorKind::BaselineNotFound(0))
+        ));
+        assert_eq!(acknowledger.acknowledgement(), Some((1, 0)));
+    }
+
+    #[test]
+    fn sent_frames_of_silent_client_are_bounded() {
+        let now = Instant::now();
+        let mut acknowledgements = SnapshotAcknowledgements::new();
+
+        for frame in 0..10_000 {
+            acknowledgements.sent(frame, now);
+        }
+
+        assert_eq!(acknowledgements.unacknowledged().count(), 1025);
+        assert_eq!(
+            acknowledgements.unacknowledged().next(),
+            Some(&(10_000 - 1 - 1024))
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::{any::Any, net::SocketAddr};
+
+use crate::transport::RejectReason;
+
+/// Application defined data attached to a `Client` when it is authenticated.
+pub type UserData = Box<dyn Any + Send + Sync>;
+
+/// The outcome of authenticating a client.
+pub enum Authentication {
+    /// The client is registered with the `PostOffice`, with the optional user data attached.
+    Accept(Option<UserData>),
+    /// The client is refused for the given reason.
+    Reject(RejectReason),
+    /// The outcome is not known yet, `Authenticator::poll` is called on the next ticks.
+    Defer,
+}
+
+/// Decides whether a client that passed the version check gets a `Client` in the `PostOffice`.
+pub trait Authenticator: Send + Sync {
+    /// Called with the address of a client and the payload of its `Hello`.
+    fn authenticate(&mut self, addr: SocketAddr, payload: &[u8]) -> Authentication;
+
+    /// Called every tick for a client whose authentication was deferred, until it is accepted or
+    /// rejected.
+    fn poll(&mut self, _addr: SocketAddr) -> Authentication {
+        Authentication::Defer
+    }
+
+    /// Called when a client whose authentication was deferred disconnected, or did not complete
+    /// its authentication within the handshake timeout, so its verification can be dropped.
+    fn cancel(&mut self, _addr: SocketAddr) {}
+}
+
+/// Accepts every client, this is the authenticator of a new `PostOffice`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AcceptAll;
+
+impl Authenticator for AcceptAll {
+    fn authenticate(&mut self, _addr: SocketAddr, _payload: &[u8]) -> Authentication {
+        Authentication::Accept(None)
+    }
+}
+
+/// Accepts the clients whose `Hello` payload equals a shared secret.
+///
+/// Meant as a stand-in for a real authenticator in tests and during development.
+#[derive(Clone, Debug)]
+pub struct SharedSecretAuthenticator {
+    secret: Vec<u8>,
+}
+
+impl SharedSecretAuthenticator {
+    pub fn new(secret: impl Into<Vec<u8>>) -> SharedSecretAuthenticator {
+        SharedSecretAuthenticator {
+            secret: secret.into(),
+        }
+    }
+}
+
+impl Authenticator for SharedSecretAuthenticator {
+    fn authenticate(&mut self, _addr: SocketAddr, payload: &[u8]) -> Authentication {
+        if payload == self.secret.as_slice() {
+            Authentication::Accept(None)
+        } else {
+            Authentication::Reject(RejectReason::Unauthorized(
+                "The shared secret does not match.".to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::transport::{
+        Authentication, Authenticator, RejectReason, SharedSecretAuthenticator,
+    };
+
+    #[test]
+    fn shared_secret_authenticator_checks_payload() {
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let mut authenticator = SharedSecretAuthenticator::new("secret");
+
+        assert!(matches!(
+            authenticator.authenticate(addr, b"secret"),
+            Authentication::Accept(None)
+        ));
+        assert!(matches!(
+            authenticator.authenticate(addr, b"guess"),
+            Authentication::Reject(RejectReason::Unauthorized(_))
+        ));
+    }
+}
@@ -1,8 +1,18 @@
-use std::{net::SocketAddr, time::Instant};
+use std::{
+    any::Any,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+    net::SocketAddr,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
-    synchronisation::{CommandFrame, NetworkCommand, NetworkMessage, ServerCommandBuffer},
-    transport::{message, PostBox},
+    synchronisation::{
+        timestamp, CommandFrame, NetworkCommand, NetworkMessage, ServerCommandBuffer,
+        TimeSyncResponse,
+    },
+    transport::{message, PostBox, SessionToken, SnapshotAcknowledgements, UserData},
+    uid::{Uid, UidAllocator, UidRange},
 };
 
 pub type ClientId = u16;
@@ -15,11 +25,15 @@ where
 {
     client_id: ClientId,
     addr: SocketAddr,
+    session_token: SessionToken,
     message_postbox:
         PostBox<ClientToServerMessage, message::ServerToClientMessage<ServerToClientMessage>>,
     pub(crate) command_postbox: ServerCommandBuffer<ClientToServerCommand>,
     connected_at: Instant,
     last_packet: Instant,
+    acknowledgements: SnapshotAcknowledgements,
+    user_data: Option<UserData>,
+    reserved_uids: Vec<UidRange>,
 }
 
 impl<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>
@@ -33,11 +47,15 @@ where
         Client {
             client_id: connection_id,
             addr,
+            session_token: new_session_token(connection_id),
             message_postbox: PostBox::new(),
             command_postbox: ServerCommandBuffer::new(),
 
             last_packet: Instant::now(),
             connected_at: Instant::now(),
+            acknowledgements: SnapshotAcknowledgements::new(),
+            user_data: None,
+            reserved_uids: Vec::new(),
         }
     }
 
@@ -47,6 +65,7 @@ where
         server_command_frame: CommandFrame,
     ) {
         self.last_packet = Instant::now();
+        let received = timestamp();
 
         match message {
             message::ClientToServerMessage::Message(message) => {
@@ -56,10 +75,51 @@ where
                 self.command_postbox
                     .push(command, client_command_frame, server_command_frame);
             }
-            message::ClientToServerMessage::TimeSync => {}
+            message::ClientToServerMessage::TimeSync(client_sent) => {
+                self.message_postbox
+                    .send(message::ServerToClientMessage::TimeSync(TimeSyncResponse {
+                        client_sent,
+                        server_received: received,
+                        // Stamped by `drain_outgoing` when the reply is sent.
+                        server_sent: 0,
+                        command_frame: server_command_frame,
+                    }));
+            }
+            message::ClientToServerMessage::Acknowledge(latest, bits) => {
+                self.acknowledgements
+                    .acknowledge(latest, bits, self.last_packet);
+            }
         };
     }
 
+    /// Marks the snapshot of the given command frame as received by the client.
+    ///
+    /// The most recent acknowledged snapshot is the baseline for the next state sent to this
+    /// client. Snapshots that were never sent to the client are ignored.
+    pub fn acknowledge_snapshot(&mut self, command_frame: CommandFrame) {
+        self.acknowledgements
+            .acknowledge(command_frame, 0, Instant::now());
+    }
+
+    /// Returns the command frame of the most recent snapshot the client acknowledged.
+    pub fn acknowledged_snapshot(&self) -> Option<CommandFrame> {
+        self.acknowledgements.latest()
+    }
+
+    /// Returns the bookkeeping of the world states this client acknowledged.
+    pub fn acknowledgements(&self) -> &SnapshotAcknowledgements {
+        &self.acknowledgements
+    }
+
+    pub fn acknowledgements_mut(&mut self) -> &mut SnapshotAcknowledgements {
+        &mut self.acknowledgements
+    }
+
+    /// Marks that a packet, such as a heartbeat, was received from the client.
+    pub fn packet_received(&mut self) {
+        self.last_packet = Instant::now();
+    }
+
     pub fn connected_at(&self) -> Instant {
         self.connected_at
     }
@@ -76,6 +136,139 @@ where
         self.client_id
     }
 
+    /// Returns the token the client presents to resume its session after losing its connection.
+    pub fn session_token(&self) -> SessionToken {
+        self.session_token
+    }
+
+    /// Moves the client to the address it reconnected from.
+    ///
+    /// The acknowledged snapshots are forgotten, so that the client receives the full world state
+    /// with the next snapshot.
+    pub(crate) fn resume(&mut self, addr: SocketAddr) {
+        self.addr = addr;
+        self.last_packet = Instant::now();
+        self.acknowledgements = SnapshotAcknowledgements::new();
+    }
+
+    /// Reserves `count` identifiers in the allocator for the entities this client predicts, and
+    /// sends them to the client.
+    ///
+    /// Returns `None` if the allocator has less than `count` unused identifiers left.
+    pub fn reserve_uids<T: Hash + Eq + Clone>(
+        &mut self,
+        allocator: &mut UidAllocator<T>,
+        count: u32,
+    ) -> Option<UidRange> {
+        let range = allocator.reserve(count)?;
+
+        self.reserved_uids.push(range);
+        self.message_postbox
+            .send(message::ServerToClientMessage::UidsReserved(range));
+        Some(range)
+    }
+
+    /// Returns true if the identifier is reserved for this client, and was not confirmed or
+    /// rejected yet.
+    pub fn owns_uid(&self, uid: Uid) -> bool {
+        self.reserved_uids.iter().any(|range| range.contains(uid))
+    }
+
+    /// Assigns the identifier the client predicted to the entity that was spawned for it.
+    ///
+    /// The entity is inserted with this identifier in the next world state, which confirms the
+    /// prediction. An identifier that was not reserved for this client, or that is already in use,
+    /// is rejected instead. Returns whether the identifier was assigned.
+    pub fn confirm_spawn<T: Hash + Eq + Clone>(
+        &mut self,
+        allocator: &mut UidAllocator<T>,
+        entity: T,
+        uid: Uid,
+    ) -> bool {
+        if !self.take_reserved_uid(uid) || allocator.contains_val(&uid) {
+            self.send_spawn_rejected(uid);
+            return false;
+        }
+
+        allocator.allocate(entity, Some(uid));
+        true
+    }
+
+    /// Tells the client the entity it predicted with the given identifier is not spawned.
+    ///
+    /// A reserved identifier is handed back to the allocator.
+    pub fn reject_spawn<T: Hash + Eq + Clone>(
+        &mut self,
+        allocator: &mut UidAllocator<T>,
+        uid: Uid,
+    ) {
+        if self.take_reserved_uid(uid) {
+            allocator.release_reserved(UidRange {
+                start: uid,
+                end: uid + 1,
+            });
+        }
+
+        self.send_spawn_rejected(uid);
+    }
+
+    /// Removes the identifiers that are still reserved for this client, and returns them.
+    pub(crate) fn take_reserved_uids(&mut self) -> Vec<UidRange> {
+        std::mem::take(&mut self.reserved_uids)
+    }
+
+    /// Removes the identifier from the reservations of this client, and returns whether it was
+    /// reserved.
+    fn take_reserved_uid(&mut self, uid: Uid) -> bool {
+        let position = match self
+            .reserved_uids
+            .iter()
+            .position(|range| range.contains(uid))
+        {
+            Some(position) => position,
+            None => return false,
+        };
+
+        let range = self.reserved_uids.swap_remove(position);
+        self.reserved_uids.extend(
+            [
+                UidRange {
+                    start: range.start,
+                    end: uid,
+                },
+                UidRange {
+                    start: uid + 1,
+                    end: range.end,
+                },
+            ]
+            .iter()
+            .filter(|range| !range.is_empty()),
+        );
+        true
+    }
+
+    fn send_spawn_rejected(&mut self, uid: Uid) {
+        self.message_postbox
+            .send(message::ServerToClientMessage::SpawnRejected(uid));
+    }
+
+    /// Returns the user data the `Authenticator` attached to this client, if it is a `T`.
+    pub fn user_data<T: Any>(&self) -> Option<&T> {
+        self.user_data
+            .as_ref()
+            .and_then(|user_data| user_data.downcast_ref())
+    }
+
+    pub fn user_data_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.user_data
+            .as_mut()
+            .and_then(|user_data| user_data.downcast_mut())
+    }
+
+    pub fn set_user_data(&mut self, user_data: UserData) {
+        self.user_data = Some(user_data);
+    }
+
     pub fn postbox_mut(
         &mut self,
     ) -> &mut PostBox<ClientToServerMessage, message::ServerToClientMessage<ServerToClientMessage>>
@@ -83,6 +276,26 @@ where
         &mut self.message_postbox
     }
 
+    /// Drains the messages that are sent to the client.
+    ///
+    /// Time sync replies are stamped with the moment they are drained, so the time they waited in
+    /// the postbox is not counted as network latency. Replies that were stamped before, for
+    /// example by a `ConditionedServerTransport` that delays them, keep their stamp.
+    pub fn drain_outgoing(&mut self) -> Vec<message::ServerToClientMessage<ServerToClientMessage>> {
+        let sent = timestamp();
+        let mut messages = self.message_postbox.drain_outgoing(|_| true);
+
+        for message in messages.iter_mut() {
+            if let message::ServerToClientMessage::TimeSync(response) = message {
+                if response.server_sent == 0 {
+                    response.server_sent = sent.max(response.server_received);
+                }
+            }
+        }
+
+        messages
+    }
+
     pub fn postbox(
         &self,
     ) -> &PostBox<ClientToServerMessage, message::ServerToClientMessage<ServerToClientMessage>>
@@ -99,9 +312,26 @@ where
     }
 }
 
+/// Returns a token that is hard to guess for the session of the given client.
+fn new_session_token(client_id: ClientId) -> SessionToken {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u16(client_id);
+
+    if let Ok(since_epoch) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u128(since_epoch.as_nanos());
+    }
+
+    hasher.finish()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::transport::{Client, ClientToServerMessage, ServerToClientMessage};
+    use std::{thread, time::Duration};
+
+    use crate::{
+        transport::{Client, ClientToServerMessage, ServerToClientMessage},
+        uid::{self, UidAllocator},
+    };
 
     #[test]
     fn command_message_is_added_to_command_inbox() {
@@ -125,4 +355,89 @@ mod tests {
         let mut postbox = client.postbox_mut();
         assert_eq!(postbox.drain_inbox(|_| true).len(), 1);
     }
+
+    #[test]
+    fn time_sync_request_is_answered_with_server_frame() {
+        let mut client = Client::<u32, u32, u32>::new("127.0.0.1:0".parse().unwrap(), 0);
+
+        client.add_received_message(ClientToServerMessage::TimeSync(5), 42);
+        thread::sleep(Duration::from_millis(5));
+
+        match client.drain_outgoing().pop() {
+            Some(ServerToClientMessage::TimeSync(response)) => {
+                assert_eq!(response.client_sent, 5);
+                assert_eq!(response.command_frame, 42);
+                // The reply is stamped when it is sent, not when it is queued.
+                assert!(response.server_sent >= response.server_received + 5000);
+            }
+            _ => panic!("Expected a time sync reply."),
+        }
+    }
+
+    #[test]
+    fn acknowledge_message_updates_acknowledged_snapshot() {
+        let mut client = Client::<u32, u32, u32>::new("127.0.0.1:0".parse().unwrap(), 0);
+
+        let connected_at = client.connected_at();
+        client.acknowledgements_mut().sent(4, connected_at);
+        client.acknowledgements_mut().sent(5, connected_at);
+        client.add_received_message(ClientToServerMessage::Acknowledge(5, 0b1), 1);
+
+        assert_eq!(client.acknowledged_snapshot(), Some(5));
+        assert!(client.acknowledgements().is_acknowledged(4));
+        assert!(client.acknowledgements().round_trip_time().is_some());
+
+        // An older snapshot does not replace the most recent baseline.
+        client.acknowledge_snapshot(3);
+        assert_eq!(client.acknowledged_snapshot(), Some(5));
+    }
+
+    #[test]
+    fn predicted_spawn_is_confirmed_only_with_reserved_unused_uid() {
+        let mut client = Client::<u32, u32, u32>::new("127.0.0.1:0".parse().unwrap(), 0);
+        let mut allocator = UidAllocator::new();
+
+        let range = client.reserve_uids(&mut allocator, 2).unwrap();
+        match client.postbox_mut().drain_outgoing(|_| true).pop() {
+            Some(ServerToClientMessage::UidsReserved(reserved)) => assert_eq!(reserved, range),
+            _ => panic!("Expected the reserved ids."),
+        }
+
+        assert!(client.confirm_spawn(&mut allocator, "projectile", range.start));
+        assert_eq!(allocator.get(&"projectile"), range.start);
+        assert!(!client.owns_uid(range.start));
+        assert!(client.owns_uid(range.start + 1));
+
+        // An id that is in use or not reserved for the client is rejected.
+        assert!(!client.confirm_spawn(&mut allocator, "duplicate", range.start));
+        assert!(!client.confirm_spawn(&mut allocator, "foreign", range.end));
+
+        let rejected = client
+            .postbox_mut()
+            .drain_outgoing(|_| true)
+            .into_iter()
+            .filter_map(|message| match message {
+                ServerToClientMessage::SpawnRejected(uid) => Some(uid),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rejected, vec![range.start, range.end]);
+    }
+
+    #[test]
+    fn rejected_spawn_hands_reserved_uid_back() {
+        let mut client = Client::<u32, u32, u32>::new("127.0.0.1:0".parse().unwrap(), 0);
+        let mut allocator = UidAllocator::new();
+
+        let range = client.reserve_uids(&mut allocator, 2).unwrap();
+        client.reject_spawn(&mut allocator, range.start);
+
+        assert!(!client.owns_uid(range.start));
+        assert!(allocator.is_stale(range.start));
+        assert_eq!(
+            uid::index(allocator.allocate("reused", None).unwrap()),
+            range.start
+        );
+        assert!(client.confirm_spawn(&mut allocator, "projectile", range.start + 1));
+    }
 }
@@ -0,0 +1,761 @@
+//! Simulation of bad network conditions on top of any transport.
+//!
+//! The [ConditionedClientTransport](./struct.ConditionedClientTransport.html) and
+//! [ConditionedServerTransport](./struct.ConditionedServerTransport.html) wrap a transport and
+//! inject latency, jitter, loss, duplication and reordering into the messages that pass through.
+//! All randomness comes from a seeded generator, so a run with the same seed and the same message
+//! timing behaves identical.
+//!
+//! The client wrapper conditions both directions. The server wrapper conditions the outgoing
+//! messages only, because received commands are buffered against the command frame at which they
+//! arrive. Wrap the clients to simulate the client to server direction.
+
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use log::error;
+
+use crate::{
+    event::NetworkEventQueue,
+    synchronisation::{CommandFrame, NetworkCommand, NetworkMessage},
+    transport,
+    transport::{
+        ClientId, ClientTransport, DisconnectReason, PostBox, PostOffice, ServerTransport,
+    },
+};
+
+/// The network conditions to simulate.
+#[derive(Clone, Debug)]
+pub struct NetworkConditions {
+    /// The delay that is added to every message.
+    pub latency: Duration,
+    /// A random delay between zero and this value that is added to every message.
+    pub jitter: Duration,
+    /// The chance, between 0 and 1, that a message is lost.
+    pub loss: f32,
+    /// The chance, between 0 and 1, that a message is delivered twice.
+    pub duplication: f32,
+    /// The chance, between 0 and 1, that a message is held back by `reorder_delay`, allowing
+    /// messages sent after it to overtake it.
+    pub reordering: f32,
+    /// The extra delay of a reordered message.
+    pub reorder_delay: Duration,
+    /// The seed of the random generator.
+    pub seed: u64,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        NetworkConditions {
+            latency: Duration::from_millis(0),
+            jitter: Duration::from_millis(0),
+            loss: 0.0,
+            duplication: 0.0,
+            reordering: 0.0,
+            reorder_delay: Duration::from_millis(50),
+            seed: 0,
+        }
+    }
+}
+
+/// A small xorshift random generator, this makes the simulation reproducible across platforms.
+#[derive(Clone, Debug)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Xorshift gets stuck on a zero state.
+        Rng {
+            state: seed ^ 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// Returns a random number in the range `[0, 1)`.
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn chance(&mut self, chance: f32) -> bool {
+        chance > 0.0 && self.next_f32() < chance
+    }
+
+    /// Returns a random duration in the range `[0, max]`.
+    fn duration(&mut self, max: Duration) -> Duration {
+        let max_nanos = max.as_nanos() as u64;
+
+        if max_nanos == 0 {
+            return Duration::from_nanos(0);
+        }
+
+        Duration::from_nanos(self.next_u64() % (max_nanos + 1))
+    }
+}
+
+/// Decides for each message if, when and how often it is delivered.
+#[derive(Clone, Debug)]
+pub struct LinkConditioner {
+    conditions: NetworkConditions,
+    rng: Rng,
+}
+
+impl LinkConditioner {
+    pub fn new(conditions: NetworkConditions) -> LinkConditioner {
+        LinkConditioner {
+            rng: Rng::new(conditions.seed),
+            conditions,
+        }
+    }
+
+    pub fn conditions(&self) -> &NetworkConditions {
+        &self.conditions
+    }
+
+    /// Changes the simulated conditions, the random generator is not reseeded.
+    pub fn set_conditions(&mut self, conditions: NetworkConditions) {
+        self.conditions = conditions;
+    }
+
+    /// Returns the moments at which a message sent at `now` should be delivered.
+    ///
+    /// The result is empty when the message is lost and has two entries when it is duplicated.
+    pub fn schedule(&mut self, now: Instant) -> Vec<Instant> {
+        if self.rng.chance(self.conditions.loss) {
+            return Vec::new();
+        }
+
+        let copies = if self.rng.chance(self.conditions.duplication) {
+            2
+        } else {
+            1
+        };
+
+        (0..copies)
+            .map(|_| {
+                let mut delay = self.conditions.latency + self.rng.duration(self.conditions.jitter);
+
+                if self.rng.chance(self.conditions.reordering) {
+                    delay += self.conditions.reorder_delay;
+                }
+
+                now + delay
+            })
+            .collect()
+    }
+}
+
+/// A queue that holds values until their release moment has passed.
+pub struct DelayQueue<T> {
+    entries: Vec<(Instant, u64, T)>,
+    counter: u64,
+}
+
+impl<T> DelayQueue<T> {
+    pub fn new() -> DelayQueue<T> {
+        DelayQueue {
+            entries: Vec::new(),
+            counter: 0,
+        }
+    }
+
+    /// Adds a value that should be released at `release_at`.
+    pub fn push(&mut self, release_at: Instant, value: T) {
+        self.entries.push((release_at, self.counter, value));
+        self.counter += 1;
+    }
+
+    /// Removes and returns the values whose release moment has passed, in order of release.
+    /// Values with the same release moment are returned in the order they were pushed.
+    pub fn drain_due(&mut self, now: Instant) -> Vec<T> {
+        let (mut due, pending): (Vec<_>, Vec<_>) = self
+            .entries
+            .drain(..)
+            .partition(|(release_at, _, _)| *release_at <= now);
+        self.entries = pending;
+
+        due.sort_by_key(|(release_at, order, _)| (*release_at, *order));
+        due.into_iter().map(|(_, _, value)| value).collect()
+    }
+
+    /// Removes and returns the values that match the predicate regardless of their release
+    /// moment, in order of release.
+    pub fn drain_matching(&mut self, mut predicate: impl FnMut(&T) -> bool) -> Vec<T> {
+        let (mut matching, rest): (Vec<_>, Vec<_>) = self
+            .entries
+            .drain(..)
+            .partition(|(_, _, value)| predicate(value));
+        self.entries = rest;
+
+        matching.sort_by_key(|(release_at, order, _)| (*release_at, *order));
+        matching.into_iter().map(|(_, _, value)| value).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<T> Default for DelayQueue<T> {
+    fn default() -> Self {
+        DelayQueue::new()
+    }
+}
+
+type DelayedMessage = Box<dyn Any + Send + Sync>;
+
+/// Queues each message as often and as long as the conditioner decides.
+fn condition<T: Clone + Send + Sync + 'static>(
+    conditioner: &mut LinkConditioner,
+    queue: &mut DelayQueue<DelayedMessage>,
+    message: T,
+    now: Instant,
+) {
+    for release_at in conditioner.schedule(now) {
+        queue.push(release_at, Box::new(message.clone()));
+    }
+}
+
+/// Restores the concrete type of a delayed message.
+fn restore<T: 'static>(message: DelayedMessage) -> Option<T> {
+    match message.downcast::<T>() {
+        Ok(message) => Some(*message),
+        Err(_) => {
+            error!("Dropped a delayed message because its type changed while it was delayed.");
+            None
+        }
+    }
+}
+
+/// Wraps a client transport and simulates network conditions in both directions.
+pub struct ConditionedClientTransport<T: ClientTransport> {
+    inner: T,
+    outgoing_conditioner: LinkConditioner,
+    incoming_conditioner: LinkConditioner,
+    outgoing: DelayQueue<DelayedMessage>,
+    incoming: DelayQueue<DelayedMessage>,
+}
+
+impl<T: ClientTransport> ConditionedClientTransport<T> {
+    /// Wraps the transport and simulates the same conditions in both directions.
+    pub fn new(inner: T, conditions: NetworkConditions) -> ConditionedClientTransport<T> {
+        let mut incoming = conditions.clone();
+        // Use a different sequence of random numbers for each direction.
+        incoming.seed = incoming.seed.wrapping_add(1);
+
+        ConditionedClientTransport::with_conditions(inner, conditions, incoming)
+    }
+
+    /// Wraps the transport and simulates different conditions for each direction.
+    pub fn with_conditions(
+        inner: T,
+        outgoing: NetworkConditions,
+        incoming: NetworkConditions,
+    ) -> ConditionedClientTransport<T> {
+        ConditionedClientTransport {
+            inner,
+            outgoing_conditioner: LinkConditioner::new(outgoing),
+            incoming_conditioner: LinkConditioner::new(incoming),
+            outgoing: DelayQueue::new(),
+            incoming: DelayQueue::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn outgoing_conditioner_mut(&mut self) -> &mut LinkConditioner {
+        &mut self.outgoing_conditioner
+    }
+
+    pub fn incoming_conditioner_mut(&mut self) -> &mut LinkConditioner {
+        &mut self.incoming_conditioner
+    }
+}
+
+impl<T: ClientTransport> ClientTransport for ConditionedClientTransport<T> {
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let now = Instant::now();
+
+        // Receive into a separate postbox so that only the newly received messages are delayed.
+        let mut received = PostBox::<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >::new();
+        self.inner.receive(&mut received, network_events);
+
+        for message in received.drain_inbox(|_| true) {
+            condition(
+                &mut self.incoming_conditioner,
+                &mut self.incoming,
+                message,
+                now,
+            );
+        }
+
+        for message in self.incoming.drain_due(now) {
+            if let Some(message) = restore(message) {
+                postbox.add_to_inbox(message);
+            }
+        }
+    }
+
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let now = Instant::now();
+
+        for message in postbox.drain_outgoing(|_| true) {
+            condition(
+                &mut self.outgoing_conditioner,
+                &mut self.outgoing,
+                message,
+                now,
+            );
+        }
+
+        for message in self.outgoing.drain_due(now) {
+            if let Some(message) = restore(message) {
+                postbox.send(message);
+            }
+        }
+
+        self.inner.send(postbox, network_events);
+    }
+
+    /// Messages that are still delayed are sent before the disconnect.
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        reason: DisconnectReason,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let pending = postbox.drain_outgoing(|_| true);
+
+        for message in self.outgoing.drain_matching(|_| true) {
+            if let Some(message) = restore(message) {
+                postbox.send(message);
+            }
+        }
+
+        for message in pending {
+            postbox.send(message);
+        }
+
+        self.inner.disconnect(reason, postbox, network_events);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.inner.is_connected()
+    }
+}
+
+/// Wraps a server transport and simulates network conditions for the messages sent to clients.
+pub struct ConditionedServerTransport<T: ServerTransport> {
+    inner: T,
+    conditioners: HashMap<ClientId, LinkConditioner>,
+    conditions: NetworkConditions,
+    outgoing: DelayQueue<(ClientId, DelayedMessage)>,
+}
+
+impl<T: ServerTransport> ConditionedServerTransport<T> {
+    pub fn new(inner: T, conditions: NetworkConditions) -> ConditionedServerTransport<T> {
+        ConditionedServerTransport {
+            inner,
+            conditioners: HashMap::new(),
+            conditions,
+            outgoing: DelayQueue::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Returns the conditioner of the given client, it is created with the default conditions of
+    /// this transport if it does not exist yet.
+    pub fn conditioner_mut(&mut self, client_id: ClientId) -> &mut LinkConditioner {
+        let conditions = &self.conditions;
+
+        self.conditioners.entry(client_id).or_insert_with(|| {
+            let mut conditions = conditions.clone();
+            // Use a different sequence of random numbers for each client.
+            conditions.seed = conditions.seed.wrapping_add(client_id as u64);
+            LinkConditioner::new(conditions)
+        })
+    }
+}
+
+impl<T: ServerTransport> ServerTransport for ConditionedServerTransport<T> {
+    fn accept<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        self.inner.accept(postoffice, network_events);
+    }
+
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        command_frame: CommandFrame,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        self.inner
+            .receive(postoffice, command_frame, network_events);
+    }
+
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let now = Instant::now();
+
+        // Clients can be removed by the inner transport, for example when they time out. A client
+        // that gets the id of a removed client must not inherit its conditioner or messages.
+        let client_ids = postoffice
+            .clients()
+            .map(|(client_id, _)| *client_id)
+            .collect::<HashSet<_>>();
+        self.conditioners
+            .retain(|client_id, _| client_ids.contains(client_id));
+        self.outgoing
+            .drain_matching(|(client_id, _)| !client_ids.contains(client_id));
+
+        for (client_id, client) in postoffice.clients_mut() {
+            // Time sync replies are stamped before the delay, which simulates network latency.
+            let messages = client.drain_outgoing();
+
+            for message in messages {
+                for release_at in self.conditioner_mut(*client_id).schedule(now) {
+                    self.outgoing
+                        .push(release_at, (*client_id, Box::new(message.clone())));
+                }
+            }
+        }
+
+        for (client_id, message) in self.outgoing.drain_due(now) {
+            // Messages of clients that disconnected while the message was delayed are dropped.
+            if let (Some(client), Some(message)) =
+                (postoffice.client_by_id_mut(&client_id), restore(message))
+            {
+                client.postbox_mut().send(message);
+            }
+        }
+
+        self.inner.send(postoffice, network_events);
+    }
+
+    /// Messages that are still delayed are sent before the disconnect.
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        client_id: ClientId,
+        reason: DisconnectReason,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let delayed = self
+            .outgoing
+            .drain_matching(|(delayed_client_id, _)| *delayed_client_id == client_id);
+
+        if let Some(client) = postoffice.client_by_id_mut(&client_id) {
+            let pending = client.postbox_mut().drain_outgoing(|_| true);
+
+            for (_, message) in delayed {
+                if let Some(message) = restore(message) {
+                    client.postbox_mut().send(message);
+                }
+            }
+
+            for message in pending {
+                client.postbox_mut().send(message);
+            }
+        }
+
+        self.conditioners.remove(&client_id);
+        self.inner
+            .disconnect(client_id, reason, postoffice, network_events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        event::NetworkEventQueue,
+        synchronisation::timestamp,
+        transport::{
+            conditioner::{
+                ConditionedClientTransport, ConditionedServerTransport, DelayQueue,
+                LinkConditioner, NetworkConditions,
+            },
+            memory::MemoryServerResource,
+            ClientToServerMessage, ClientTransport, PostBox, PostOffice, ServerToClientMessage,
+            ServerTransport,
+        },
+    };
+
+    fn conditions() -> NetworkConditions {
+        NetworkConditions {
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(20),
+            loss: 0.2,
+            duplication: 0.1,
+            reordering: 0.1,
+            seed: 42,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn same_seed_is_reproducible() {
+        let now = Instant::now();
+        let mut first = LinkConditioner::new(conditions());
+        let mut second = LinkConditioner::new(conditions());
+
+        for _ in 0..100 {
+            assert_eq!(first.schedule(now), second.schedule(now));
+        }
+    }
+
+    #[test]
+    fn delay_stays_within_latency_and_jitter() {
+        let now = Instant::now();
+        let mut conditioner = LinkConditioner::new(NetworkConditions {
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(20),
+            ..Default::default()
+        });
+
+        for _ in 0..100 {
+            let schedule = conditioner.schedule(now);
+            assert_eq!(schedule.len(), 1);
+            assert!(schedule[0] >= now + Duration::from_millis(50));
+            assert!(schedule[0] <= now + Duration::from_millis(70));
+        }
+    }
+
+    #[test]
+    fn loss_and_duplication_are_applied() {
+        let now = Instant::now();
+
+        let mut lossy = LinkConditioner::new(NetworkConditions {
+            loss: 1.0,
+            ..Default::default()
+        });
+        assert!(lossy.schedule(now).is_empty());
+
+        let mut duplicating = LinkConditioner::new(NetworkConditions {
+            duplication: 1.0,
+            ..Default::default()
+        });
+        assert_eq!(duplicating.schedule(now).len(), 2);
+
+        let mut conditioner = LinkConditioner::new(NetworkConditions {
+            loss: 0.5,
+            ..Default::default()
+        });
+        let delivered = (0..1000)
+            .filter(|_| !conditioner.schedule(now).is_empty())
+            .count();
+        assert!(delivered > 400 && delivered < 600);
+    }
+
+    #[test]
+    fn delay_queue_releases_in_order() {
+        let now = Instant::now();
+        let mut queue = DelayQueue::new();
+
+        queue.push(now + Duration::from_millis(20), 2);
+        queue.push(now + Duration::from_millis(10), 1);
+        queue.push(now + Duration::from_millis(30), 3);
+
+        assert!(queue.drain_due(now).is_empty());
+        assert_eq!(queue.drain_due(now + Duration::from_millis(20)), vec![1, 2]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.drain_due(now + Duration::from_millis(30)), vec![3]);
+    }
+
+    #[test]
+    fn conditioned_client_delays_messages() {
+        let mut server = MemoryServerResource::new();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let mut client = ConditionedClientTransport::new(
+            server.connect().unwrap(),
+            NetworkConditions {
+                latency: Duration::from_millis(20),
+                ..Default::default()
+            },
+        );
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        server.accept(&mut postoffice, &mut events);
+
+        postbox.send(ClientToServerMessage::Message(1));
+        client.send(&mut postbox, &mut events);
+        server.receive(&mut postoffice, 0, &mut events);
+
+        let (_, remote_client) = postoffice.clients_mut().next().unwrap();
+        assert!(remote_client.postbox().empty_inbox());
+
+        std::thread::sleep(Duration::from_millis(25));
+        client.send(&mut postbox, &mut events);
+        server.receive(&mut postoffice, 0, &mut events);
+
+        let (_, remote_client) = postoffice.clients_mut().next().unwrap();
+        assert_eq!(remote_client.postbox_mut().drain_inbox(|_| true), vec![1]);
+    }
+
+    #[test]
+    fn time_sync_through_conditioned_server_measures_delay() {
+        let mut server = ConditionedServerTransport::new(
+            MemoryServerResource::new(),
+            NetworkConditions {
+                latency: Duration::from_millis(30),
+                ..Default::default()
+            },
+        );
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let mut client = server.inner().connect().unwrap();
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        server.accept(&mut postoffice, &mut events);
+
+        postbox.send(ClientToServerMessage::TimeSync(timestamp()));
+        client.send(&mut postbox, &mut events);
+        server.receive(&mut postoffice, 0, &mut events);
+        server.send(&mut postoffice, &mut events);
+
+        std::thread::sleep(Duration::from_millis(35));
+        server.send(&mut postoffice, &mut events);
+        client.receive(&mut postbox, &mut events);
+
+        match postbox.drain_inbox(|_| true).pop() {
+            Some(ServerToClientMessage::TimeSync(response)) => {
+                // The delay of the server is network latency, not processing time.
+                let sample = response.sample(timestamp());
+                assert!(sample.round_trip_time >= Duration::from_millis(30));
+            }
+            _ => panic!("Expected a time sync reply."),
+        }
+    }
+
+    #[test]
+    fn removed_client_leaves_no_conditioner_or_delayed_messages() {
+        let mut server = ConditionedServerTransport::new(
+            MemoryServerResource::new(),
+            NetworkConditions {
+                latency: Duration::from_millis(50),
+                ..Default::default()
+            },
+        );
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let _client = server.inner().connect().unwrap();
+        server.accept(&mut postoffice, &mut events);
+
+        postoffice.broadcast(ServerToClientMessage::Message(1));
+        server.send(&mut postoffice, &mut events);
+        assert_eq!(server.conditioners.len(), 1);
+        assert_eq!(server.outgoing.len(), 1);
+
+        // The client is removed without going through the conditioned transport.
+        let client_id = *postoffice.clients().next().unwrap().0;
+        postoffice.remove_client(&client_id).unwrap();
+        server.send(&mut postoffice, &mut events);
+
+        assert!(server.conditioners.is_empty());
+        assert!(server.outgoing.is_empty());
+    }
+}
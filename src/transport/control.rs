@@ -0,0 +1,397 @@
+use std::{net::SocketAddr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    event::{NetworkEvent, NetworkEventQueue},
+    synchronisation::{NetworkCommand, NetworkMessage},
+    transport::{Authentication, ClientId, PostOffice},
+};
+
+/// The version of the protocol spoken by the transports of this crate.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Identifies the session of a client, a client that lost its connection presents it to resume
+/// the session.
+///
+/// Tokens are hard to guess, but they are not a cryptographic secret.
+pub type SessionToken = u64;
+
+/// Packets that are exchanged by the transports themselves, next to the messages of the game.
+///
+/// Control packets do not depend on the message types of the game, so that peers with another
+/// protocol version can still decode them. New variants have to be appended.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ControlPacket {
+    /// The first packet of a client.
+    Hello(Hello),
+    /// The reply of the server to the `Hello` of a client.
+    HandshakeReply(HandshakeReply),
+    /// Keeps the connection alive when there is nothing else to send.
+    Heartbeat,
+    /// The last packet of a peer that closes the connection.
+    Disconnect(DisconnectReason),
+    /// The first packet of a client that reconnects, with the token of the session it resumes.
+    Resume(Hello, SessionToken),
+}
+
+impl ControlPacket {
+    /// Returns the hello of a `Hello` or `Resume` packet, together with the session to resume.
+    pub(crate) fn into_hello(self) -> Option<(Hello, Option<SessionToken>)> {
+        match self {
+            ControlPacket::Hello(hello) => Some((hello, None)),
+            ControlPacket::Resume(hello, session) => Some((hello, Some(session))),
+            _ => None,
+        }
+    }
+}
+
+/// Introduces a client to the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub game_id: String,
+    /// Application defined data, such as the name of the player.
+    pub payload: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HandshakeReply {
+    /// The client is registered under the given id and session.
+    Accepted(ClientId, SessionToken),
+    /// The client is refused, the server closes the connection.
+    Rejected(RejectReason),
+}
+
+/// The reason the server refused a client.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RejectReason {
+    VersionMismatch {
+        server: u32,
+        client: u32,
+    },
+    GameMismatch {
+        server: String,
+        client: String,
+    },
+    Custom(String),
+    /// The `Authenticator` of the server refused the client.
+    Unauthorized(String),
+    /// All client ids are taken.
+    ServerFull,
+    /// The authentication of the client did not complete within the handshake timeout.
+    AuthenticationTimeout,
+}
+
+/// The reason a connection was closed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DisconnectReason {
+    /// Nothing was received from the remote within the heartbeat timeout.
+    Timeout,
+    /// The connection was closed or reset without notice.
+    ConnectionLost,
+    /// The server removed the client.
+    Kicked,
+    /// The server is shutting down.
+    ServerShutdown,
+    /// The peer speaks another protocol version.
+    VersionMismatch,
+    /// The player left the game.
+    UserQuit,
+    Custom(String),
+}
+
+impl DisconnectReason {
+    /// Returns true if the connection was lost rather than closed on purpose, in which case the
+    /// client can resume its session.
+    pub fn can_resume(&self) -> bool {
+        matches!(
+            self,
+            DisconnectReason::Timeout | DisconnectReason::ConnectionLost
+        )
+    }
+}
+
+/// The identity a transport introduces itself with, and expects from its peers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandshakeConfig {
+    pub protocol_version: u32,
+    pub game_id: String,
+    /// The payload of the `Hello` of a client, not used by the server.
+    pub payload: Vec<u8>,
+    /// The server closes a connection that sent no `Hello`, or whose authentication did not
+    /// complete, within this timeout.
+    pub timeout: Duration,
+}
+
+impl HandshakeConfig {
+    /// Returns a new `HandshakeConfig` for the given game with the current protocol version.
+    pub fn new(game_id: impl Into<String>) -> HandshakeConfig {
+        HandshakeConfig {
+            protocol_version: PROTOCOL_VERSION,
+            game_id: game_id.into(),
+            payload: Vec::new(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Returns the `Hello` a client sends to introduce itself.
+    pub fn hello(&self) -> Hello {
+        Hello {
+            protocol_version: self.protocol_version,
+            game_id: self.game_id.clone(),
+            payload: self.payload.clone(),
+        }
+    }
+
+    /// Returns whether the server accepts the `Hello` of a client.
+    pub fn validate(&self, hello: &Hello) -> Result<(), RejectReason> {
+        if hello.protocol_version != self.protocol_version {
+            return Err(RejectReason::VersionMismatch {
+                server: self.protocol_version,
+                client: hello.protocol_version,
+            });
+        }
+
+        if hello.game_id != self.game_id {
+            return Err(RejectReason::GameMismatch {
+                server: self.game_id.clone(),
+                client: hello.game_id.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        HandshakeConfig::new("")
+    }
+}
+
+/// Validates the `Hello` of a client, authenticates it and registers the client with the
+/// `PostOffice`.
+///
+/// Returns the reply for the client, or `None` if the authentication is deferred. A client that
+/// is already registered, for example because it resent its `Hello`, is accepted again under the
+/// same id. A client that presents the token of a session that is kept by the `PostOffice` gets
+/// its `Client` back without being authenticated again. If the session expired, the client is
+/// accepted as a new client.
+pub(crate) fn accept_hello<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    handshake: &HandshakeConfig,
+    hello: &Hello,
+    session: Option<SessionToken>,
+    addr: SocketAddr,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    network_events: &mut NetworkEventQueue,
+) -> Option<HandshakeReply> {
+    if let Err(reason) = handshake.validate(hello) {
+        return Some(reject(addr, reason, network_events));
+    }
+
+    if let Some(client) = postoffice.client_by_addr_mut(&addr) {
+        return Some(HandshakeReply::Accepted(
+            client.client_id(),
+            client.session_token(),
+        ));
+    }
+
+    if let Some(session) = session {
+        if let Some(client_id) = postoffice.resume_session(session, addr) {
+            network_events.enqueue(NetworkEvent::Reconnected(addr, client_id));
+            return Some(HandshakeReply::Accepted(client_id, session));
+        }
+    }
+
+    let authentication = postoffice
+        .authenticator_mut()
+        .authenticate(addr, &hello.payload);
+
+    complete_authentication(authentication, addr, postoffice, network_events)
+}
+
+/// Polls the deferred authentication of a client.
+///
+/// Returns the reply for the client, or `None` if the authentication is still deferred.
+pub(crate) fn poll_authentication<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    addr: SocketAddr,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    network_events: &mut NetworkEventQueue,
+) -> Option<HandshakeReply> {
+    let authentication = postoffice.authenticator_mut().poll(addr);
+
+    complete_authentication(authentication, addr, postoffice, network_events)
+}
+
+/// Cancels the deferred authentication of a client that closed its connection.
+pub(crate) fn cancel_authentication<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    addr: SocketAddr,
+    reason: DisconnectReason,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    network_events: &mut NetworkEventQueue,
+) {
+    postoffice.authenticator_mut().cancel(addr);
+    network_events.enqueue(NetworkEvent::ConnectionFailed(addr, reason));
+}
+
+/// Cancels the deferred authentication of a client that did not complete within the handshake
+/// timeout, and returns the reply that rejects the client.
+pub(crate) fn expire_authentication<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    addr: SocketAddr,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    network_events: &mut NetworkEventQueue,
+) -> HandshakeReply {
+    postoffice.authenticator_mut().cancel(addr);
+    reject(addr, RejectReason::AuthenticationTimeout, network_events)
+}
+
+fn complete_authentication<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    authentication: Authentication,
+    addr: SocketAddr,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    network_events: &mut NetworkEventQueue,
+) -> Option<HandshakeReply> {
+    match authentication {
+        Authentication::Accept(user_data) => match postoffice
+            .add_client(addr)
+            .and_then(|client_id| postoffice.client_by_id_mut(&client_id))
+        {
+            Some(client) => {
+                if let Some(user_data) = user_data {
+                    client.set_user_data(user_data);
+                }
+
+                network_events.enqueue(NetworkEvent::Connected(addr));
+                Some(HandshakeReply::Accepted(
+                    client.client_id(),
+                    client.session_token(),
+                ))
+            }
+            None => {
+                let reason = if postoffice.client_exists(addr) {
+                    RejectReason::Custom("Client could not be registered.".to_string())
+                } else {
+                    RejectReason::ServerFull
+                };
+                Some(reject(addr, reason, network_events))
+            }
+        },
+        Authentication::Reject(reason) => Some(reject(addr, reason, network_events)),
+        Authentication::Defer => None,
+    }
+}
+
+fn reject(
+    addr: SocketAddr,
+    reason: RejectReason,
+    network_events: &mut NetworkEventQueue,
+) -> HandshakeReply {
+    network_events.enqueue(NetworkEvent::Rejected(addr, reason.clone()));
+    HandshakeReply::Rejected(reason)
+}
+
+/// Returns the event of a client whose connection was closed, a client that was not accepted yet
+/// has no id.
+pub(crate) fn disconnected_event(
+    addr: SocketAddr,
+    client_id: Option<ClientId>,
+    reason: DisconnectReason,
+) -> NetworkEvent {
+    match client_id {
+        Some(client_id) => NetworkEvent::Disconnected(addr, client_id, reason),
+        None => NetworkEvent::ConnectionFailed(addr, reason),
+    }
+}
+
+/// Processes the reply of the server on the client side.
+///
+/// A client that resumed its session with the id it had before, `previous`, is reconnected.
+/// Returns the assigned client id and session, or `None` if the client was rejected.
+pub(crate) fn handle_reply(
+    reply: HandshakeReply,
+    addr: SocketAddr,
+    previous: Option<ClientId>,
+    network_events: &mut NetworkEventQueue,
+) -> Option<(ClientId, SessionToken)> {
+    match reply {
+        HandshakeReply::Accepted(client_id, session) => {
+            if previous == Some(client_id) {
+                network_events.enqueue(NetworkEvent::Reconnected(addr, client_id));
+            } else {
+                network_events.enqueue(NetworkEvent::Connected(addr));
+            }
+            Some((client_id, session))
+        }
+        HandshakeReply::Rejected(reason) => {
+            network_events.enqueue(NetworkEvent::Rejected(addr, reason));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::{HandshakeConfig, RejectReason};
+
+    #[test]
+    fn validate_rejects_other_version_and_game() {
+        let server = HandshakeConfig::new("game");
+
+        assert!(server
+            .validate(&HandshakeConfig::new("game").hello())
+            .is_ok());
+
+        let mut other_version = HandshakeConfig::new("game");
+        other_version.protocol_version += 1;
+        match server.validate(&other_version.hello()) {
+            Err(RejectReason::VersionMismatch { server, client }) => assert_eq!(client, server + 1),
+            _ => panic!("Expected a version mismatch."),
+        }
+
+        match server.validate(&HandshakeConfig::new("other").hello()) {
+            Err(RejectReason::GameMismatch { .. }) => {}
+            _ => panic!("Expected a game mismatch."),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Length-prefixed framing for stream based transports.
+//!
+//! A stream like TCP has no notion of message boundaries, a single `read` can return half a packet
+//! or several packets glued together. Each payload is therefore prefixed with a header containing
+//! its length so that the receiving side can reassemble the original packets.
+//!
+//! | Bytes | Description |
+//! | :-- | :-- |
+//! | 0..4 | The payload length as big-endian `u32`. |
+//! | 4.. | The payload. |
+
+use std::{
+    convert::TryInto,
+    io::{self, Write},
+};
+
+use crate::error::ErrorKind;
+
+/// The size of the length header that precedes each frame.
+pub const FRAME_HEADER_SIZE: usize = 4;
+
+/// The default maximum payload size of a single frame (1 MiB).
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Reassembles length-prefixed frames from bytes read from a stream.
+#[derive(Debug)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl FrameReader {
+    /// Returns a new `FrameReader` which rejects frames larger than `max_frame_size`.
+    pub fn new(max_frame_size: usize) -> FrameReader {
+        FrameReader {
+            buffer: Vec::new(),
+            max_frame_size,
+        }
+    }
+
+    /// Appends bytes read from the stream to the reassembly buffer.
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Returns the next complete frame if one is buffered.
+    ///
+    /// Returns `Ok(None)` when more bytes are needed and an error when the header announces a frame
+    /// larger than the configured maximum. In the latter case the stream can not be recovered and
+    /// should be dropped.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, ErrorKind> {
+        if self.buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let header: [u8; FRAME_HEADER_SIZE] = self.buffer[..FRAME_HEADER_SIZE]
+            .try_into()
+            .expect("Slice has the size of the header.");
+        let frame_size = u32::from_be_bytes(header) as usize;
+
+        if frame_size > self.max_frame_size {
+            return Err(ErrorKind::FramingError(format!(
+                "Frame of {} bytes exceeds the maximum frame size of {} bytes.",
+                frame_size, self.max_frame_size
+            )));
+        }
+
+        if self.buffer.len() < FRAME_HEADER_SIZE + frame_size {
+            return Ok(None);
+        }
+
+        let frame = self.buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + frame_size].to_vec();
+        self.buffer.drain(..FRAME_HEADER_SIZE + frame_size);
+
+        Ok(Some(frame))
+    }
+
+    /// Returns the number of bytes that are buffered but not yet returned as frame.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        FrameReader::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+/// Buffers length-prefixed frames and writes them to a non-blocking stream.
+///
+/// Non-blocking streams may accept only a part of the data.
+/// The remainder is kept and written on the next call to `flush`.
+#[derive(Debug)]
+pub struct FrameWriter {
+    pending: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl FrameWriter {
+    /// Returns a new `FrameWriter` which refuses frames larger than `max_frame_size`.
+    pub fn new(max_frame_size: usize) -> FrameWriter {
+        FrameWriter {
+            pending: Vec::new(),
+            max_frame_size,
+        }
+    }
+
+    /// Prefixes the payload with its length and queues it to be written.
+    pub fn push_frame(&mut self, payload: &[u8]) -> Result<(), ErrorKind> {
+        if payload.len() > self.max_frame_size {
+            return Err(ErrorKind::FramingError(format!(
+                "Frame of {} bytes exceeds the maximum frame size of {} bytes.",
+                payload.len(),
+                self.max_frame_size
+            )));
+        }
+
+        self.pending
+            .extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        self.pending.extend_from_slice(payload);
+
+        Ok(())
+    }
+
+    /// Writes as much of the queued data as the stream accepts.
+    ///
+    /// Returns `Ok(true)` when all queued data is written and `Ok(false)` when the stream would
+    /// block and data remains queued.
+    pub fn flush<W: Write>(&mut self, writer: &mut W) -> io::Result<bool> {
+        let mut written = 0;
+
+        let result = loop {
+            if written == self.pending.len() {
+                break Ok(true);
+            }
+
+            match writer.write(&self.pending[written..]) {
+                Ok(0) => {
+                    break Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "Stream did not accept any bytes.",
+                    ))
+                }
+                Ok(len) => written += len,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(false),
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.pending.drain(..written);
+        result
+    }
+
+    /// Returns true if there is no data waiting to be written.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns the number of bytes waiting to be written.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl Default for FrameWriter {
+    fn default() -> Self {
+        FrameWriter::new(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use crate::transport::framing::{FrameReader, FrameWriter};
+
+    /// A writer that accepts a limited number of bytes per call and blocks every other call.
+    struct ChokedWriter {
+        written: Vec<u8>,
+        chunk: usize,
+        block: bool,
+    }
+
+    impl Write for ChokedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.block = !self.block;
+            if self.block {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "blocked"));
+            }
+
+            let len = buf.len().min(self.chunk);
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut writer = FrameWriter::default();
+        writer.push_frame(payload).unwrap();
+        let mut buffer = Vec::new();
+        writer.flush(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn should_reassemble_split_frame() {
+        let data = frame(b"hello world");
+        let mut reader = FrameReader::default();
+
+        for byte in data.iter() {
+            assert!(reader.next_frame().unwrap().is_none());
+            reader.extend(&[*byte]);
+        }
+
+        assert_eq!(reader.next_frame().unwrap().unwrap(), b"hello world");
+        assert_eq!(reader.buffered(), 0);
+    }
+
+    #[test]
+    fn should_split_coalesced_frames() {
+        let mut data = frame(b"first");
+        data.extend(frame(b""));
+        data.extend(frame(b"third"));
+
+        let mut reader = FrameReader::default();
+        reader.extend(&data);
+
+        assert_eq!(reader.next_frame().unwrap().unwrap(), b"first");
+        assert_eq!(reader.next_frame().unwrap().unwrap(), b"");
+        assert_eq!(reader.next_frame().unwrap().unwrap(), b"third");
+        assert!(reader.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn should_reject_frame_exceeding_max_size() {
+        let mut reader = FrameReader::new(4);
+        reader.extend(&frame(b"too large"));
+
+        assert!(reader.next_frame().is_err());
+
+        let mut writer = FrameWriter::new(4);
+        assert!(writer.push_frame(b"too large").is_err());
+        assert!(writer.is_empty());
+    }
+
+    #[test]
+    fn should_keep_remainder_on_partial_write() {
+        let mut writer = FrameWriter::default();
+        writer.push_frame(b"partial writes").unwrap();
+        let expected_len = writer.pending();
+
+        let mut stream = ChokedWriter {
+            written: Vec::new(),
+            chunk: 3,
+            block: false,
+        };
+
+        let mut flushes = 0;
+        while !writer.flush(&mut stream).unwrap() {
+            flushes += 1;
+            assert_eq!(writer.pending() + stream.written.len(), expected_len);
+        }
+
+        assert!(flushes > 1);
+        assert!(writer.is_empty());
+
+        let mut reader = FrameReader::default();
+        reader.extend(&stream.written);
+        assert_eq!(reader.next_frame().unwrap().unwrap(), b"partial writes");
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    event::{NetworkEvent, NetworkEventQueue},
+    synchronisation::{NetworkCommand, NetworkMessage},
+    transport::{DisconnectReason, PostOffice},
+};
+
+/// Configuration of the keep-alive packets and the idle timeout of a connection.
+#[derive(Clone, Debug)]
+pub struct HeartbeatConfig {
+    /// A heartbeat is sent when nothing else was sent to the remote during this interval.
+    pub interval: Duration,
+    /// A remote from which nothing was received during this timeout is disconnected.
+    pub timeout: Duration,
+}
+
+impl HeartbeatConfig {
+    /// Returns whether a heartbeat should be sent when the last packet was sent at `last_sent`.
+    pub fn is_heartbeat_due(&self, last_sent: Instant, now: Instant) -> bool {
+        now.saturating_duration_since(last_sent) >= self.interval
+    }
+
+    /// Returns whether a remote whose last packet was received at `last_received` timed out.
+    pub fn is_timed_out(&self, last_received: Instant, now: Instant) -> bool {
+        now.saturating_duration_since(last_received) > self.timeout
+    }
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Suspends the clients that timed out in the `PostOffice` and emits their disconnected events.
+///
+/// Returns the addresses of the suspended clients, the transport should close their connections.
+pub(crate) fn disconnect_idle_clients<
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    heartbeat: &HeartbeatConfig,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    network_events: &mut NetworkEventQueue,
+) -> Vec<SocketAddr> {
+    postoffice
+        .suspend_idle_clients(heartbeat.timeout, Instant::now())
+        .into_iter()
+        .map(|(client_id, addr)| {
+            network_events.enqueue(NetworkEvent::Disconnected(
+                addr,
+                client_id,
+                DisconnectReason::Timeout,
+            ));
+            addr
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::transport::HeartbeatConfig;
+
+    #[test]
+    fn heartbeat_is_due_after_interval_and_timeout_after_timeout() {
+        let config = HeartbeatConfig {
+            interval: Duration::from_millis(100),
+            timeout: Duration::from_millis(500),
+        };
+        let start = Instant::now();
+
+        assert!(!config.is_heartbeat_due(start, start + Duration::from_millis(50)));
+        assert!(config.is_heartbeat_due(start, start + Duration::from_millis(100)));
+
+        assert!(!config.is_timed_out(start, start + Duration::from_millis(500)));
+        assert!(config.is_timed_out(start, start + Duration::from_millis(501)));
+    }
+}
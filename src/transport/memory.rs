@@ -0,0 +1,1282 @@
+//! In-process transport that connects clients and server through channels.
+//!
+//! This transport can be used to run a server and several clients inside a single process, such as
+//! for tests, bots or a listen server. Messages are still serialized so that the same bugs surface
+//! as with the network transports.
+//!
+//! Clients connect through a [MemoryConnector](./struct.MemoryConnector.html) which can be cloned and
+//! moved to other threads.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{channel, Receiver, Sender, TryRecvError},
+        Arc,
+    },
+    time::Instant,
+};
+
+use log::{debug, error};
+use serde::Serialize;
+
+use crate::{
+    compression::{CompressionStrategy, DefaultCompression},
+    error::ErrorKind,
+    event::{NetworkEvent, NetworkEventQueue},
+    serialization::{DefaultSerialization, SerializationStrategy},
+    synchronisation::{CommandFrame, NetworkCommand, NetworkMessage},
+    transport,
+    transport::{
+        control, heartbeat, ClientId, ClientTransport, ControlPacket, DisconnectReason,
+        HandshakeConfig, HandshakeReply, HeartbeatConfig, Packer, Payload, PostBox, PostOffice,
+        ServerTransport,
+    },
+};
+
+/// One direction of an in-memory connection pair.
+struct MemoryLink {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+    buffered: VecDeque<Vec<u8>>,
+    last_sent: Instant,
+}
+
+impl MemoryLink {
+    fn new(sender: Sender<Vec<u8>>, receiver: Receiver<Vec<u8>>) -> MemoryLink {
+        MemoryLink {
+            sender,
+            receiver,
+            buffered: VecDeque::new(),
+            last_sent: Instant::now(),
+        }
+    }
+
+    /// Returns the next received packet, the packets buffered by `buffer` come first.
+    fn try_recv(&mut self) -> Result<Vec<u8>, TryRecvError> {
+        match self.buffered.pop_front() {
+            Some(packet) => Ok(packet),
+            None => self.receiver.try_recv(),
+        }
+    }
+
+    /// Buffers the received packets for a later `try_recv`, returns false if the other half of
+    /// the link was dropped.
+    fn buffer(&mut self) -> bool {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(packet) => self.buffered.push_back(packet),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+
+    /// Sends the packet, returns false if the other half of the link was dropped.
+    fn send(&mut self, packet: Vec<u8>) -> bool {
+        self.last_sent = Instant::now();
+        self.sender.send(packet).is_ok()
+    }
+
+    /// Sends a heartbeat if nothing was sent during the heartbeat interval.
+    fn send_heartbeat<S: SerializationStrategy, C: CompressionStrategy>(
+        &mut self,
+        packer: &Packer<S, C>,
+        heartbeat: &HeartbeatConfig,
+    ) -> bool {
+        if !heartbeat.is_heartbeat_due(self.last_sent, Instant::now()) {
+            return true;
+        }
+
+        match packer.pack_control(&ControlPacket::Heartbeat) {
+            Ok(packet) => self.send(packet),
+            Err(e) => {
+                error!(
+                    "Error occurred when packing memory heartbeat. Reason: {:?}",
+                    e
+                );
+                true
+            }
+        }
+    }
+
+    /// Sends the given messages, if any, followed by a disconnect.
+    fn send_disconnect<S: SerializationStrategy, C: CompressionStrategy, T: Serialize>(
+        &mut self,
+        packer: &Packer<S, C>,
+        packets: &[T],
+        reason: DisconnectReason,
+    ) {
+        if !packets.is_empty() {
+            match packer.serialization().serialize(packets) {
+                Ok(serialized) => {
+                    self.send(packer.pack_messages(&serialized));
+                }
+                Err(e) => {
+                    error!(
+                        "Error occurred when serializing memory-packet. Reason: {:?}",
+                        e
+                    );
+                }
+            }
+        }
+
+        match packer.pack_control(&ControlPacket::Disconnect(reason)) {
+            Ok(packet) => {
+                self.send(packet);
+            }
+            Err(e) => {
+                error!(
+                    "Error occurred when packing memory disconnect. Reason: {:?}",
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// The server half of a connection that is not yet accepted by the server.
+struct PendingConnection {
+    addr: SocketAddr,
+    link: MemoryLink,
+}
+
+/// A cloneable handle used to connect clients to a `MemoryServerResource`.
+#[derive(Clone)]
+pub struct MemoryConnector<
+    S: SerializationStrategy = DefaultSerialization,
+    C: CompressionStrategy = DefaultCompression,
+> {
+    pending: Sender<PendingConnection>,
+    next_addr: Arc<AtomicU64>,
+    handshake: HandshakeConfig,
+    heartbeat: HeartbeatConfig,
+    packer: Packer<S, C>,
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> MemoryConnector<S, C> {
+    /// Connects a new client to the server, the client uses the same packer and handshake config
+    /// as the server.
+    ///
+    /// The client is registered with the `PostOffice` on the next call to `accept` on the server.
+    pub fn connect(&self) -> Result<MemoryClientResource<S, C>, ErrorKind> {
+        self.connect_with(self.handshake.clone())
+    }
+
+    /// Connects a new client that introduces itself with the given handshake config.
+    pub fn connect_with(
+        &self,
+        handshake: HandshakeConfig,
+    ) -> Result<MemoryClientResource<S, C>, ErrorKind> {
+        // The `PostOffice` identifies clients by address, so every client gets a unique fake one.
+        let addr = fake_addr(self.next_addr.fetch_add(1, Ordering::SeqCst)).ok_or_else(|| {
+            ErrorKind::IoError(std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                "The fake addresses of the memory server ran out.",
+            ))
+        })?;
+
+        let (client_sender, server_receiver) = channel();
+        let (server_sender, client_receiver) = channel();
+
+        // The channel is ordered, so the hello is received before any message of the client.
+        let hello = self
+            .packer
+            .pack_control(&ControlPacket::Hello(handshake.hello()))?;
+        let _ = client_sender.send(hello);
+
+        self.pending
+            .send(PendingConnection {
+                addr,
+                link: MemoryLink::new(server_sender, server_receiver),
+            })
+            .map_err(|_| {
+                ErrorKind::IoError(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionRefused,
+                    "The memory server was dropped.",
+                ))
+            })?;
+
+        Ok(MemoryClientResource {
+            addr,
+            link: MemoryLink::new(client_sender, client_receiver),
+            connected: true,
+            client_id: None,
+            heartbeat: self.heartbeat.clone(),
+            last_received: Instant::now(),
+            packer: self.packer.clone(),
+        })
+    }
+}
+
+/// Returns the fake address with the given number, spread over the port and the IPv4 octets.
+///
+/// Returns `None` if the number does not fit, the address would repeat an earlier one.
+fn fake_addr(number: u64) -> Option<SocketAddr> {
+    if number >> 48 != 0 {
+        return None;
+    }
+
+    let ip = ((number >> 16) as u32).to_be_bytes();
+    Some(SocketAddr::from((ip, number as u16)))
+}
+
+pub struct MemoryServerResource<
+    S: SerializationStrategy = DefaultSerialization,
+    C: CompressionStrategy = DefaultCompression,
+> {
+    pending: Receiver<PendingConnection>,
+    connector: MemoryConnector<S, C>,
+    handshaking: HashMap<SocketAddr, MemoryLink>,
+    authenticating: HashMap<SocketAddr, Instant>,
+    connections: HashMap<SocketAddr, MemoryLink>,
+}
+
+impl MemoryServerResource {
+    pub fn new() -> MemoryServerResource {
+        MemoryServerResource::with_packer(Packer::default())
+    }
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> MemoryServerResource<S, C> {
+    /// Returns a new `MemoryServerResource` whose server and clients pack their packets with the
+    /// given packer.
+    pub fn with_packer(packer: Packer<S, C>) -> MemoryServerResource<S, C> {
+        MemoryServerResource::with_handshake(HandshakeConfig::default(), packer)
+    }
+
+    /// Returns a new `MemoryServerResource` that only accepts clients whose hello matches the
+    /// given handshake config.
+    pub fn with_handshake(
+        handshake: HandshakeConfig,
+        packer: Packer<S, C>,
+    ) -> MemoryServerResource<S, C> {
+        let (sender, receiver) = channel();
+
+        MemoryServerResource {
+            pending: receiver,
+            connector: MemoryConnector {
+                pending: sender,
+                next_addr: Arc::new(AtomicU64::new(1)),
+                handshake,
+                heartbeat: HeartbeatConfig::default(),
+                packer,
+            },
+            handshaking: HashMap::new(),
+            authenticating: HashMap::new(),
+            connections: HashMap::new(),
+        }
+    }
+
+    pub fn handshake(&self) -> &HandshakeConfig {
+        &self.connector.handshake
+    }
+
+    pub fn heartbeat(&self) -> &HeartbeatConfig {
+        &self.connector.heartbeat
+    }
+
+    /// Sets the heartbeat config of the server and of the clients that connect after this call.
+    pub fn set_heartbeat(&mut self, heartbeat: HeartbeatConfig) {
+        self.connector.heartbeat = heartbeat;
+    }
+
+    /// Returns a handle that can be used to connect clients to this server.
+    pub fn connector(&self) -> MemoryConnector<S, C> {
+        self.connector.clone()
+    }
+
+    /// Connects a new client to this server.
+    pub fn connect(&self) -> Result<MemoryClientResource<S, C>, ErrorKind> {
+        self.connector.connect()
+    }
+
+    /// Drops the connection with the given address, the client will notice it on its next receive.
+    pub fn drop_connection(&mut self, addr: SocketAddr) -> bool {
+        self.connections.remove(&addr).is_some()
+    }
+}
+
+impl Default for MemoryServerResource {
+    fn default() -> Self {
+        MemoryServerResource::new()
+    }
+}
+
+pub struct MemoryClientResource<
+    S: SerializationStrategy = DefaultSerialization,
+    C: CompressionStrategy = DefaultCompression,
+> {
+    addr: SocketAddr,
+    link: MemoryLink,
+    connected: bool,
+    client_id: Option<ClientId>,
+    heartbeat: HeartbeatConfig,
+    last_received: Instant,
+    packer: Packer<S, C>,
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> MemoryClientResource<S, C> {
+    /// Returns the fake address under which the server knows this client.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Returns the id the server assigned to this client, once the handshake is accepted.
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.client_id
+    }
+
+    pub fn heartbeat(&self) -> &HeartbeatConfig {
+        &self.heartbeat
+    }
+
+    pub fn set_heartbeat(&mut self, heartbeat: HeartbeatConfig) {
+        self.heartbeat = heartbeat;
+    }
+
+    fn disconnected(&mut self, reason: DisconnectReason, network_events: &mut NetworkEventQueue) {
+        self.connected = false;
+        network_events.enqueue(control::disconnected_event(
+            self.addr,
+            self.client_id,
+            reason,
+        ));
+    }
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> ServerTransport
+    for MemoryServerResource<S, C>
+{
+    fn accept<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        while let Ok(pending) = self.pending.try_recv() {
+            debug!("Incoming memory connection: {:?}", pending.addr);
+
+            self.handshaking.insert(pending.addr, pending.link);
+        }
+
+        let packer = &self.connector.packer;
+        let mut handshaken = Vec::new();
+        let mut deferred = Vec::new();
+
+        let now = Instant::now();
+
+        for (addr, link) in self.handshaking.iter_mut() {
+            if let Some(deferred_at) = self.authenticating.get(addr) {
+                // Packets that follow the hello are buffered until the client is accepted.
+                if !link.buffer() {
+                    control::cancel_authentication(
+                        *addr,
+                        DisconnectReason::ConnectionLost,
+                        postoffice,
+                        network_events,
+                    );
+                    handshaken.push((*addr, false));
+                    continue;
+                }
+
+                let reply = match control::poll_authentication(*addr, postoffice, network_events) {
+                    Some(reply) => reply,
+                    None if now.saturating_duration_since(*deferred_at)
+                        > self.connector.handshake.timeout =>
+                    {
+                        debug!("Memory client {:?} was not authenticated in time.", addr);
+                        control::expire_authentication(*addr, postoffice, network_events)
+                    }
+                    None => continue,
+                };
+
+                handshaken.push((*addr, reply_to_hello(packer, link, reply)));
+                continue;
+            }
+
+            let packet = match link.try_recv() {
+                Ok(packet) => packet,
+                Err(TryRecvError::Empty) => continue,
+                Err(TryRecvError::Disconnected) => {
+                    handshaken.push((*addr, false));
+                    continue;
+                }
+            };
+
+            let hello = packer.unpack_payload(&packet).map(|payload| match payload {
+                Payload::Control(control) => control.into_hello(),
+                Payload::Messages(_) => None,
+            });
+
+            let reply = match hello {
+                Ok(Some((hello, session))) => match control::accept_hello(
+                    &self.connector.handshake,
+                    &hello,
+                    session,
+                    *addr,
+                    postoffice,
+                    network_events,
+                ) {
+                    Some(reply) => reply,
+                    None => {
+                        deferred.push(*addr);
+                        continue;
+                    }
+                },
+                Ok(None) => {
+                    error!(
+                        "Error occurred when handshaking with memory client: {:?}. Reason: Expected a hello.",
+                        addr
+                    );
+                    handshaken.push((*addr, false));
+                    continue;
+                }
+                Err(e) => {
+                    network_events.enqueue(NetworkEvent::Error(*addr, e));
+                    handshaken.push((*addr, false));
+                    continue;
+                }
+            };
+
+            handshaken.push((*addr, reply_to_hello(packer, link, reply)));
+        }
+
+        self.authenticating
+            .extend(deferred.into_iter().map(|addr| (addr, now)));
+
+        // Rejected links are dropped, the client notices it after it received the reply.
+        for (addr, accepted) in handshaken {
+            self.authenticating.remove(&addr);
+
+            if let Some(link) = self.handshaking.remove(&addr) {
+                if accepted {
+                    self.connections.insert(addr, link);
+                }
+            }
+        }
+    }
+
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        command_frame: CommandFrame,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let mut disconnected = Vec::new();
+
+        let packer = &self.connector.packer;
+
+        for (addr, link) in self.connections.iter_mut() {
+            let client = match postoffice.client_by_addr_mut(addr) {
+                Some(client) => client,
+                None => continue,
+            };
+
+            loop {
+                match link.try_recv() {
+                    Ok(packet) => {
+                        client.packet_received();
+
+                        let unpacked = match packer.unpack_payload(&packet) {
+                            Ok(Payload::Messages(unpacked)) => unpacked,
+                            Ok(Payload::Control(ControlPacket::Disconnect(reason))) => {
+                                network_events.enqueue(NetworkEvent::Disconnected(
+                                    *addr,
+                                    client.client_id(),
+                                    reason.clone(),
+                                ));
+                                disconnected.push((*addr, client.client_id(), reason));
+                                break;
+                            }
+                            Ok(Payload::Control(control)) => {
+                                debug!("Ignored control packet from {:?}: {:?}", addr, control);
+                                continue;
+                            }
+                            Err(e) => {
+                                network_events.enqueue(NetworkEvent::Error(*addr, e));
+                                continue;
+                            }
+                        };
+
+                        match packer.serialization().deserialize::<Vec<
+                            transport::ClientToServerMessage<
+                                ClientToServerMessage,
+                                ClientToServerCommand,
+                            >,
+                        >>(&unpacked)
+                        {
+                            Ok(deserialized) => {
+                                for message in deserialized.into_iter() {
+                                    client.add_received_message(message, command_frame);
+                                }
+                            }
+                            Err(e) => {
+                                error!(
+                                    "Error occurred when deserializing memory-packet. Reason: {:?}",
+                                    e
+                                );
+                            }
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        network_events.enqueue(NetworkEvent::Disconnected(
+                            *addr,
+                            client.client_id(),
+                            DisconnectReason::ConnectionLost,
+                        ));
+                        disconnected.push((
+                            *addr,
+                            client.client_id(),
+                            DisconnectReason::ConnectionLost,
+                        ));
+                        break;
+                    }
+                }
+            }
+        }
+
+        for (addr, client_id, reason) in disconnected {
+            self.connections.remove(&addr);
+            postoffice.client_disconnected(&client_id, &reason);
+        }
+
+        // Dropping the link of a timed out client disconnects it.
+        for addr in heartbeat::disconnect_idle_clients(
+            &self.connector.heartbeat,
+            postoffice,
+            network_events,
+        ) {
+            self.connections.remove(&addr);
+        }
+    }
+
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        _network_events: &mut NetworkEventQueue,
+    ) {
+        for (_, client) in postoffice.clients_mut() {
+            let link = match self.connections.get_mut(&client.addr()) {
+                Some(link) => link,
+                None => continue,
+            };
+
+            let packets = client.drain_outgoing();
+
+            // A dropped receiver is detected by `receive` on the next tick.
+            if packets.is_empty() {
+                link.send_heartbeat(&self.connector.packer, &self.connector.heartbeat);
+                continue;
+            }
+
+            match self.connector.packer.serialization().serialize(&packets) {
+                Ok(serialized) => {
+                    link.send(self.connector.packer.pack_messages(&serialized));
+                }
+                Err(e) => {
+                    error!(
+                        "Error occurred when serializing memory-packet. Reason: {:?}",
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        client_id: ClientId,
+        reason: DisconnectReason,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let client = match postoffice.client_by_id_mut(&client_id) {
+            Some(client) => client,
+            None => return,
+        };
+
+        let addr = client.addr();
+        let packets = client.drain_outgoing();
+
+        // The client receives the queued packets before it notices the dropped link.
+        if let Some(mut link) = self.connections.remove(&addr) {
+            link.send_disconnect(&self.connector.packer, &packets, reason.clone());
+        }
+
+        if let Err(e) = postoffice.remove_client(&client_id) {
+            error!(
+                "Error occurred when removing memory client. Reason: {:?}",
+                e
+            );
+        }
+        network_events.enqueue(NetworkEvent::Disconnected(addr, client_id, reason));
+    }
+}
+
+/// Sends the handshake reply over the given link, returns whether the client was accepted.
+fn reply_to_hello<S: SerializationStrategy, C: CompressionStrategy>(
+    packer: &Packer<S, C>,
+    link: &MemoryLink,
+    reply: HandshakeReply,
+) -> bool {
+    let accepted = matches!(reply, HandshakeReply::Accepted(..));
+
+    match packer.pack_control(&ControlPacket::HandshakeReply(reply)) {
+        Ok(packet) => {
+            let _ = link.sender.send(packet);
+        }
+        Err(e) => {
+            error!(
+                "Error occurred when sending memory handshake reply. Reason: {:?}",
+                e
+            );
+        }
+    }
+
+    accepted
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> ClientTransport
+    for MemoryClientResource<S, C>
+{
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        if !self.connected {
+            return;
+        }
+
+        loop {
+            match self.link.receiver.try_recv() {
+                Ok(packet) => {
+                    self.last_received = Instant::now();
+
+                    let unpacked = match self.packer.unpack_payload(&packet) {
+                        Ok(Payload::Messages(unpacked)) => unpacked,
+                        Ok(Payload::Control(ControlPacket::HandshakeReply(reply))) => {
+                            self.client_id =
+                                control::handle_reply(reply, self.addr, None, network_events)
+                                    .map(|(client_id, _)| client_id);
+
+                            if self.client_id.is_none() {
+                                self.connected = false;
+                                return;
+                            }
+                            continue;
+                        }
+                        Ok(Payload::Control(ControlPacket::Disconnect(reason))) => {
+                            self.disconnected(reason, network_events);
+                            return;
+                        }
+                        Ok(Payload::Control(control)) => {
+                            debug!("Ignored control packet from server: {:?}", control);
+                            continue;
+                        }
+                        Err(e) => {
+                            network_events.enqueue(NetworkEvent::Error(self.addr, e));
+                            continue;
+                        }
+                    };
+
+                    match self.packer.serialization().deserialize::<Vec<
+                        transport::ServerToClientMessage<ServerToClientMessage>,
+                    >>(&unpacked)
+                    {
+                        Ok(deserialized) => {
+                            for message in deserialized.into_iter() {
+                                postbox.add_to_inbox(message);
+                            }
+                        }
+                        Err(e) => {
+                            error!(
+                                "Error occurred when deserializing memory-packet. Reason: {:?}",
+                                e
+                            );
+                        }
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnected(DisconnectReason::ConnectionLost, network_events);
+                    return;
+                }
+            }
+        }
+
+        if self
+            .heartbeat
+            .is_timed_out(self.last_received, Instant::now())
+        {
+            self.disconnected(DisconnectReason::Timeout, network_events);
+        }
+    }
+
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        if !self.connected {
+            return;
+        }
+
+        if postbox.empty_outgoing() {
+            if !self.link.send_heartbeat(&self.packer, &self.heartbeat) {
+                self.disconnected(DisconnectReason::ConnectionLost, network_events);
+            }
+            return;
+        }
+
+        let packets = postbox.drain_outgoing(|_| true);
+
+        match self.packer.serialization().serialize(&packets) {
+            Ok(serialized) => {
+                if !self.link.send(self.packer.pack_messages(&serialized)) {
+                    self.disconnected(DisconnectReason::ConnectionLost, network_events);
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Error occurred when serializing memory-packet. Reason: {:?}",
+                    e
+                );
+            }
+        }
+    }
+
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        reason: DisconnectReason,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        if !self.connected {
+            return;
+        }
+
+        let packets = postbox.drain_outgoing(|_| true);
+        self.link
+            .send_disconnect(&self.packer, &packets, reason.clone());
+
+        self.disconnected(reason, network_events);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        error::ErrorKind,
+        event::{NetworkEvent, NetworkEventQueue},
+        synchronisation::WorldState,
+        transport::{
+            memory::{fake_addr, MemoryServerResource},
+            Authentication, Authenticator, ClientToServerMessage, ClientTransport,
+            DisconnectReason, HandshakeConfig, HeartbeatConfig, Packer, PostBox, PostOffice,
+            RejectReason, ServerToClientMessage, ServerTransport, SharedSecretAuthenticator,
+        },
+    };
+
+    /// Accepts the clients once the verification of their token has completed.
+    struct DeferredAuthenticator {
+        verified: Arc<AtomicBool>,
+        cancelled: Arc<Mutex<Vec<SocketAddr>>>,
+    }
+
+    impl Authenticator for DeferredAuthenticator {
+        fn authenticate(&mut self, _addr: SocketAddr, _payload: &[u8]) -> Authentication {
+            Authentication::Defer
+        }
+
+        fn poll(&mut self, _addr: SocketAddr) -> Authentication {
+            if self.verified.load(Ordering::SeqCst) {
+                Authentication::Accept(Some(Box::new(String::from("player"))))
+            } else {
+                Authentication::Defer
+            }
+        }
+
+        fn cancel(&mut self, addr: SocketAddr) {
+            self.cancelled.lock().unwrap().push(addr);
+        }
+    }
+
+    type ClientPostBox = PostBox<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>;
+
+    #[test]
+    fn server_and_clients_exchange_messages_in_process() {
+        let mut server = MemoryServerResource::new();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut server_events = NetworkEventQueue::new();
+
+        let mut clients = (0..2)
+            .map(|_| (server.connect().unwrap(), ClientPostBox::new()))
+            .collect::<Vec<_>>();
+        let mut client_events = NetworkEventQueue::new();
+
+        server.accept(&mut postoffice, &mut server_events);
+        assert_eq!(postoffice.client_count(), 2);
+
+        for (i, (client, postbox)) in clients.iter_mut().enumerate() {
+            postbox.send(ClientToServerMessage::Message(i as u32));
+            postbox.send(ClientToServerMessage::Command(1, i as u32));
+            client.send(postbox, &mut client_events);
+        }
+
+        server.receive(&mut postoffice, 1, &mut server_events);
+
+        for (_, client) in postoffice.clients_mut() {
+            assert_eq!(client.postbox_mut().drain_inbox(|_| true).len(), 1);
+            assert_eq!(
+                client.command_postbox_mut().drain_frame(1).unwrap().len(),
+                1
+            );
+        }
+
+        postoffice.broadcast(ServerToClientMessage::StateUpdate(WorldState::new(1)));
+        server.send(&mut postoffice, &mut server_events);
+
+        for (client, postbox) in clients.iter_mut() {
+            client.receive(postbox, &mut client_events);
+            assert!(client.client_id().is_some());
+            assert_eq!(postbox.drain_inbox(|_| true).len(), 1);
+        }
+    }
+
+    #[test]
+    fn client_with_other_protocol_version_is_rejected() {
+        let mut server: MemoryServerResource =
+            MemoryServerResource::with_handshake(HandshakeConfig::new("game"), Packer::default());
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let mut handshake = HandshakeConfig::new("game");
+        handshake.protocol_version += 1;
+        let mut client = server.connector().connect_with(handshake).unwrap();
+
+        server.accept(&mut postoffice, &mut events);
+        assert_eq!(postoffice.client_count(), 0);
+
+        match events.dequeue() {
+            Some(NetworkEvent::Rejected(addr, RejectReason::VersionMismatch { .. })) => {
+                assert_eq!(addr, client.addr())
+            }
+            _ => panic!("Expected a rejected event."),
+        }
+
+        client.receive(&mut ClientPostBox::new(), &mut events);
+
+        assert!(!client.is_connected());
+        assert!(client.client_id().is_none());
+        match events.dequeue() {
+            Some(NetworkEvent::Rejected(_, RejectReason::VersionMismatch { .. })) => {}
+            _ => panic!("Expected a rejected event."),
+        }
+    }
+
+    #[test]
+    fn client_that_was_not_accepted_reports_failed_connection() {
+        let server: MemoryServerResource = MemoryServerResource::new();
+        let mut client = server.connector().connect().unwrap();
+        let mut events = NetworkEventQueue::new();
+
+        drop(server);
+        client.receive(&mut ClientPostBox::new(), &mut events);
+
+        assert!(!client.is_connected());
+        match events.dequeue() {
+            Some(NetworkEvent::ConnectionFailed(addr, DisconnectReason::ConnectionLost)) => {
+                assert_eq!(addr, client.addr())
+            }
+            _ => panic!("Expected a failed connection event."),
+        }
+    }
+
+    #[test]
+    fn clients_can_connect_from_other_threads() {
+        let mut server = MemoryServerResource::new();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let connector = server.connector();
+        let mut client = thread::spawn(move || connector.connect().unwrap())
+            .join()
+            .unwrap();
+
+        server.accept(&mut postoffice, &mut events);
+
+        match events.dequeue() {
+            Some(NetworkEvent::Connected(addr)) => assert_eq!(addr, client.addr()),
+            _ => panic!("Expected a connected event."),
+        }
+
+        drop(server);
+
+        let mut postbox = ClientPostBox::new();
+        client.receive(&mut postbox, &mut events);
+
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn client_with_wrong_secret_is_rejected() {
+        let mut server = MemoryServerResource::new();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        postoffice.set_authenticator(SharedSecretAuthenticator::new("secret"));
+        let mut events = NetworkEventQueue::new();
+
+        let mut handshake = server.handshake().clone();
+        handshake.payload = b"secret".to_vec();
+        let accepted = server.connector().connect_with(handshake.clone()).unwrap();
+
+        handshake.payload = b"guess".to_vec();
+        let rejected = server.connector().connect_with(handshake).unwrap();
+
+        server.accept(&mut postoffice, &mut events);
+
+        assert_eq!(postoffice.client_count(), 1);
+        assert!(postoffice.client_by_addr_mut(&accepted.addr()).is_some());
+        assert!(postoffice.client_by_addr_mut(&rejected.addr()).is_none());
+    }
+
+    #[test]
+    fn deferred_authentication_is_polled_on_later_ticks() {
+        let mut server = MemoryServerResource::new();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let verified = Arc::new(AtomicBool::new(false));
+        postoffice.set_authenticator(DeferredAuthenticator {
+            verified: verified.clone(),
+            cancelled: Arc::new(Mutex::new(Vec::new())),
+        });
+        let mut events = NetworkEventQueue::new();
+
+        let mut client = server.connect().unwrap();
+        let mut postbox = ClientPostBox::new();
+        postbox.send(ClientToServerMessage::Message(1));
+        client.send(&mut postbox, &mut events);
+
+        server.accept(&mut postoffice, &mut events);
+        server.accept(&mut postoffice, &mut events);
+        assert_eq!(postoffice.client_count(), 0);
+        assert!(events.dequeue().is_none());
+
+        verified.store(true, Ordering::SeqCst);
+        server.accept(&mut postoffice, &mut events);
+        server.receive(&mut postoffice, 0, &mut events);
+
+        let remote_client = postoffice.client_by_addr_mut(&client.addr()).unwrap();
+        assert_eq!(
+            remote_client.user_data::<String>().map(String::as_str),
+            Some("player")
+        );
+        // Messages sent before the client was accepted are not lost.
+        assert_eq!(remote_client.postbox_mut().drain_inbox(|_| true), vec![1]);
+
+        client.receive(&mut postbox, &mut events);
+        assert!(client.client_id().is_some());
+    }
+
+    #[test]
+    fn pending_authentication_is_cancelled_when_client_leaves_or_times_out() {
+        let handshake = HandshakeConfig {
+            timeout: Duration::from_millis(50),
+            ..HandshakeConfig::default()
+        };
+        let mut server: MemoryServerResource =
+            MemoryServerResource::with_handshake(handshake, Packer::default());
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let cancelled = Arc::new(Mutex::new(Vec::new()));
+        postoffice.set_authenticator(DeferredAuthenticator {
+            verified: Arc::new(AtomicBool::new(false)),
+            cancelled: cancelled.clone(),
+        });
+        let mut events = NetworkEventQueue::new();
+
+        let leaving = server.connect().unwrap();
+        let mut waiting = server.connect().unwrap();
+        let leaving_addr = leaving.addr();
+        server.accept(&mut postoffice, &mut events);
+
+        drop(leaving);
+        server.accept(&mut postoffice, &mut events);
+        assert_eq!(*cancelled.lock().unwrap(), vec![leaving_addr]);
+        match events.dequeue() {
+            Some(NetworkEvent::ConnectionFailed(addr, DisconnectReason::ConnectionLost)) => {
+                assert_eq!(addr, leaving_addr)
+            }
+            _ => panic!("Expected a failed connection event."),
+        }
+
+        thread::sleep(Duration::from_millis(60));
+        server.accept(&mut postoffice, &mut events);
+        assert_eq!(
+            *cancelled.lock().unwrap(),
+            vec![leaving_addr, waiting.addr()]
+        );
+        assert!(matches!(
+            events.dequeue(),
+            Some(NetworkEvent::Rejected(
+                _,
+                RejectReason::AuthenticationTimeout
+            ))
+        ));
+
+        waiting.receive(&mut ClientPostBox::new(), &mut events);
+        assert!(!waiting.is_connected());
+        assert!(matches!(
+            events.dequeue(),
+            Some(NetworkEvent::Rejected(
+                _,
+                RejectReason::AuthenticationTimeout
+            ))
+        ));
+        assert_eq!(postoffice.client_count(), 0);
+    }
+
+    #[test]
+    fn invalid_packet_is_reported_as_error() {
+        let mut server = MemoryServerResource::new();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let client = server.connect().unwrap();
+        server.accept(&mut postoffice, &mut events);
+        events.dequeue();
+
+        client.link.sender.send(vec![9, 9, 9]).unwrap();
+        server.receive(&mut postoffice, 0, &mut events);
+
+        match events.dequeue() {
+            Some(NetworkEvent::Error(addr, ErrorKind::CompressionError(_))) => {
+                assert_eq!(addr, client.addr())
+            }
+            _ => panic!("Expected a compression error event."),
+        }
+        assert!(events.dequeue().is_none());
+    }
+
+    #[test]
+    fn dropped_client_disconnects() {
+        let mut server = MemoryServerResource::new();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let client = server.connect().unwrap();
+        server.accept(&mut postoffice, &mut events);
+        events.dequeue();
+
+        drop(client);
+        server.receive(&mut postoffice, 0, &mut events);
+
+        match events.dequeue() {
+            Some(NetworkEvent::Disconnected(_, 0, DisconnectReason::ConnectionLost)) => {}
+            _ => panic!("Expected a disconnected event."),
+        }
+    }
+
+    #[test]
+    fn silent_client_times_out_and_heartbeats_keep_others_connected() {
+        let mut server = MemoryServerResource::new();
+        server.set_heartbeat(HeartbeatConfig {
+            interval: Duration::from_millis(5),
+            timeout: Duration::from_millis(50),
+        });
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let mut active = server.connect().unwrap();
+        let silent = server.connect().unwrap();
+        let mut postbox = ClientPostBox::new();
+        server.accept(&mut postoffice, &mut events);
+
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(10));
+            active.send(&mut postbox, &mut events);
+            server.receive(&mut postoffice, 0, &mut events);
+        }
+
+        assert!(postoffice.client_by_addr_mut(&active.addr()).is_some());
+        assert!(postoffice.client_by_addr_mut(&silent.addr()).is_none());
+
+        let timed_out = std::iter::from_fn(|| events.dequeue()).any(|event| {
+            matches!(event, NetworkEvent::Disconnected(addr, _, DisconnectReason::Timeout) if addr == silent.addr())
+        });
+        assert!(timed_out);
+    }
+
+    #[test]
+    fn kicked_client_receives_pending_messages_and_reason() {
+        let mut server = MemoryServerResource::new();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let mut client = server.connect().unwrap();
+        let mut postbox = ClientPostBox::new();
+        let mut client_events = NetworkEventQueue::new();
+        server.accept(&mut postoffice, &mut events);
+
+        let client_id = postoffice
+            .client_by_addr_mut(&client.addr())
+            .unwrap()
+            .client_id();
+        postoffice.broadcast(ServerToClientMessage::Message(3));
+        server.disconnect(
+            client_id,
+            DisconnectReason::Kicked,
+            &mut postoffice,
+            &mut events,
+        );
+
+        assert_eq!(postoffice.client_count(), 0);
+        match events.dequeue() {
+            Some(NetworkEvent::Connected(_)) => {}
+            _ => panic!("Expected a connected event."),
+        }
+        match events.dequeue() {
+            Some(NetworkEvent::Disconnected(_, id, DisconnectReason::Kicked)) => {
+                assert_eq!(id, client_id)
+            }
+            _ => panic!("Expected a disconnected event."),
+        }
+
+        client.receive(&mut postbox, &mut client_events);
+
+        assert!(!client.is_connected());
+        match postbox.drain_inbox(|_| true).first() {
+            Some(ServerToClientMessage::Message(3)) => {}
+            _ => panic!("Expected the message sent before the disconnect."),
+        }
+        match client_events.dequeue() {
+            Some(NetworkEvent::Connected(_)) => {}
+            _ => panic!("Expected a connected event."),
+        }
+        match client_events.dequeue() {
+            Some(NetworkEvent::Disconnected(_, id, DisconnectReason::Kicked)) => {
+                assert_eq!(id, client_id)
+            }
+            _ => panic!("Expected a disconnected event."),
+        }
+    }
+
+    #[test]
+    fn quitting_client_flushes_messages_before_disconnect() {
+        let mut server = MemoryServerResource::new();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+
+        let mut client = server.connect().unwrap();
+        let mut postbox = ClientPostBox::new();
+        server.accept(&mut postoffice, &mut events);
+        events.dequeue();
+
+        postbox.send(ClientToServerMessage::Message(4));
+        client.disconnect(DisconnectReason::UserQuit, &mut postbox, &mut events);
+        assert!(!client.is_connected());
+        events.dequeue();
+
+        let client_id = postoffice
+            .client_by_addr_mut(&client.addr())
+            .unwrap()
+            .client_id();
+        server.receive(&mut postoffice, 0, &mut events);
+
+        match events.dequeue() {
+            Some(NetworkEvent::Disconnected(addr, id, DisconnectReason::UserQuit)) => {
+                assert_eq!(addr, client.addr());
+                assert_eq!(id, client_id);
+            }
+            _ => panic!("Expected a disconnected event."),
+        }
+        assert_eq!(postoffice.client_count(), 0);
+    }
+
+    #[test]
+    fn fake_addresses_do_not_repeat_after_port_range() {
+        let server = MemoryServerResource::new();
+        server
+            .connector
+            .next_addr
+            .store(u16::MAX as u64, Ordering::SeqCst);
+
+        let first = server.connect().unwrap().addr();
+        let second = server.connect().unwrap().addr();
+
+        assert_eq!(first, SocketAddr::from(([0, 0, 0, 0], u16::MAX)));
+        assert_eq!(second, SocketAddr::from(([0, 0, 0, 1], 0)));
+        assert_eq!(fake_addr(1 << 48), None);
+    }
+}
@@ -1,12 +1,19 @@
 use serde::{Deserialize, Serialize};
 
-use crate::synchronisation::{CommandFrame, NetworkMessage, WorldState};
+use crate::{
+    synchronisation::{CommandFrame, EntityId, NetworkMessage, TimeSyncResponse, WorldState},
+    uid::UidRange,
+};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum ClientToServerMessage<Message, Command> {
     Message(Message),
     Command(CommandFrame, Command),
-    TimeSync,
+    /// Requests the clock of the server, contains the `timestamp` at which the client sent it.
+    TimeSync(u64),
+    /// Acknowledges the received world states with the most recent command frame and a bitfield
+    /// of the 32 frames before it, bit `n` acknowledges frame `latest - n - 1`.
+    Acknowledge(CommandFrame, u32),
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -15,6 +22,13 @@ pub enum ServerToClientMessage<Message> {
     StateUpdate(WorldState),
     Message(Message),
     InitialStateSync(Vec<u8>),
+    /// The reply to a `ClientToServerMessage::TimeSync` request.
+    TimeSync(TimeSyncResponse),
+    /// Identifiers the server reserved for the entities the client predicts.
+    UidsReserved(UidRange),
+    /// The server did not spawn the predicted entity with the given id, the client should despawn
+    /// it.
+    SpawnRejected(EntityId),
 }
 
 impl<Message: Serialize + for<'a> Deserialize<'a> + Send + Sync + Clone + 'static> NetworkMessage
@@ -0,0 +1,258 @@
+use crate::{
+    compression::{CompressionStrategy, ModificationCompressor},
+    error::ErrorKind,
+    serialization::SerializationStrategy,
+    transport::ControlPacket,
+};
+
+/// The flag of a packet whose payload is sent as is.
+const RAW_FLAG: u8 = 0;
+/// The flag of a packet whose payload is compressed.
+const COMPRESSED_FLAG: u8 = 1;
+
+/// The kind of a payload that contains serialized messages.
+const MESSAGES_KIND: u8 = 0;
+/// The kind of a payload that contains a `ControlPacket`.
+const CONTROL_KIND: u8 = 1;
+
+/// The number of bytes the packer prepends to a packet.
+pub const PACKET_FLAG_SIZE: usize = 1;
+
+/// Packets smaller than this number of bytes are not compressed by default.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 64;
+
+/// The content of a packet.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Payload {
+    /// The serialized messages of the game.
+    Messages(Vec<u8>),
+    Control(ControlPacket),
+}
+
+/// Holds the serialization and compression strategies of a transport, and compresses outgoing
+/// packets and decompresses incoming packets.
+///
+/// Every packet starts with a flag that tells whether the payload is compressed.
+/// The payloads of `pack_messages` and `pack_control` start with a byte that tells whether they
+/// contain messages or a control packet.
+/// Payloads smaller than the compression threshold, or payloads that do not get smaller by
+/// compressing them, are sent uncompressed.
+#[derive(Clone, Debug)]
+pub struct Packer<S: SerializationStrategy, C: CompressionStrategy> {
+    serialization: S,
+    compression: ModificationCompressor<C>,
+    compression_threshold: usize,
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> Packer<S, C> {
+    pub fn new(serialization: S, compression: C) -> Packer<S, C> {
+        Packer::with_threshold(serialization, compression, DEFAULT_COMPRESSION_THRESHOLD)
+    }
+
+    /// Returns a packer that does not try to compress payloads smaller than `compression_threshold`.
+    pub fn with_threshold(
+        serialization: S,
+        compression: C,
+        compression_threshold: usize,
+    ) -> Packer<S, C> {
+        Packer {
+            serialization,
+            compression: ModificationCompressor::new(compression),
+            compression_threshold,
+        }
+    }
+
+    /// Returns the serialization strategy used for the messages in the packets.
+    pub fn serialization(&self) -> &S {
+        &self.serialization
+    }
+
+    /// Returns the compressor used by this packer.
+    pub fn compression(&self) -> &ModificationCompressor<C> {
+        &self.compression
+    }
+
+    /// Returns the packet for the given payload, the payload is compressed if that saves bytes.
+    pub fn pack(&self, payload: &[u8]) -> Vec<u8> {
+        if payload.len() >= self.compression_threshold {
+            let compressed = self.compression.compress(payload);
+
+            if compressed.len() < payload.len() {
+                return with_flag(COMPRESSED_FLAG, &compressed);
+            }
+        }
+
+        with_flag(RAW_FLAG, payload)
+    }
+
+    /// Returns the packet for the given serialized messages.
+    pub fn pack_messages(&self, serialized: &[u8]) -> Vec<u8> {
+        self.pack(&with_flag(MESSAGES_KIND, serialized))
+    }
+
+    /// Returns the packet for the given control packet.
+    pub fn pack_control(&self, control: &ControlPacket) -> Result<Vec<u8>, ErrorKind> {
+        let serialized = self.serialization.serialize(control)?;
+        Ok(self.pack(&with_flag(CONTROL_KIND, &serialized)))
+    }
+
+    /// Returns the payload of a packet created by `pack_messages` or `pack_control`.
+    pub fn unpack_payload(&self, packet: &[u8]) -> Result<Payload, ErrorKind> {
+        let unpacked = self.unpack(packet)?;
+
+        match unpacked.split_first() {
+            Some((&MESSAGES_KIND, messages)) => Ok(Payload::Messages(messages.to_vec())),
+            Some((&CONTROL_KIND, control)) => {
+                Ok(Payload::Control(self.serialization.deserialize(control)?))
+            }
+            Some((kind, _)) => Err(ErrorKind::SerializationError(format!(
+                "Unknown payload kind {}.",
+                kind
+            ))),
+            None => Err(ErrorKind::SerializationError(
+                "Payload does not contain a kind.".to_string(),
+            )),
+        }
+    }
+
+    /// Returns the payload of the given packet.
+    ///
+    /// A packet without a valid flag or with a payload that can not be decompressed results in a
+    /// `CompressionError`.
+    pub fn unpack(&self, packet: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+        match packet.split_first() {
+            Some((&RAW_FLAG, payload)) => Ok(payload.to_vec()),
+            Some((&COMPRESSED_FLAG, payload)) => self.compression.decompress(payload),
+            Some((flag, _)) => Err(ErrorKind::CompressionError(format!(
+                "Unknown packet flag {}.",
+                flag
+            ))),
+            None => Err(ErrorKind::CompressionError(
+                "Packet does not contain a flag.".to_string(),
+            )),
+        }
+    }
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> Default for Packer<S, C> {
+    fn default() -> Self {
+        Packer::new(Default::default(), Default::default())
+    }
+}
+
+fn with_flag(flag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(payload.len() + PACKET_FLAG_SIZE);
+    packet.push(flag);
+    packet.extend_from_slice(payload);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compression::{CompressionStrategy, NoCompression},
+        error::ErrorKind,
+        serialization::DefaultSerialization,
+        transport::{ControlPacket, HandshakeConfig, Packer, Payload},
+    };
+
+    /// Compresses by run-length encoding, which makes it easy to create compressible data.
+    #[derive(Clone, Default)]
+    struct RunLength;
+
+    impl CompressionStrategy for RunLength {
+        fn compress(&self, buffer: &[u8]) -> Vec<u8> {
+            let mut compressed = Vec::new();
+
+            for byte in buffer {
+                match compressed.len() {
+                    len if len >= 2
+                        && compressed[len - 1] == *byte
+                        && compressed[len - 2] < u8::MAX =>
+                    {
+                        compressed[len - 2] += 1
+                    }
+                    _ => compressed.extend_from_slice(&[1, *byte]),
+                }
+            }
+
+            compressed
+        }
+
+        fn decompress(&self, buffer: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+            if buffer.len() % 2 != 0 {
+                return Err(ErrorKind::CompressionError("Odd length.".to_string()));
+            }
+
+            Ok(buffer
+                .chunks(2)
+                .flat_map(|run| std::iter::repeat(run[1]).take(run[0] as usize))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn compressible_payload_is_compressed() {
+        let packer = Packer::new(DefaultSerialization::default(), RunLength);
+        let payload = vec![7; 200];
+
+        let packet = packer.pack(&payload);
+        assert!(packet.len() < payload.len());
+        assert_eq!(packer.unpack(&packet).unwrap(), payload);
+    }
+
+    #[test]
+    fn small_or_incompressible_payload_is_sent_raw() {
+        let packer = Packer::new(DefaultSerialization::default(), RunLength);
+
+        let small = vec![7; 10];
+        assert_eq!(packer.pack(&small).len(), small.len() + 1);
+
+        let incompressible = (0..200).collect::<Vec<u8>>();
+        let packet = packer.pack(&incompressible);
+        assert_eq!(packet.len(), incompressible.len() + 1);
+        assert_eq!(packer.unpack(&packet).unwrap(), incompressible);
+    }
+
+    #[test]
+    fn invalid_packet_is_compression_error() {
+        let packer = Packer::new(DefaultSerialization::default(), RunLength);
+
+        for packet in [&[][..], &[9, 1, 2][..], &[1, 1][..]].iter() {
+            match packer.unpack(packet) {
+                Err(ErrorKind::CompressionError(_)) => {}
+                _ => panic!("Expected a compression error."),
+            }
+        }
+
+        assert_eq!(
+            Packer::new(DefaultSerialization::default(), NoCompression)
+                .unpack(&[0, 1, 2])
+                .unwrap(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn payload_kind_separates_messages_from_control_packets() {
+        let packer = Packer::new(DefaultSerialization::default(), NoCompression);
+        let hello = ControlPacket::Hello(HandshakeConfig::new("game").hello());
+
+        match packer.unpack_payload(&packer.pack_control(&hello).unwrap()) {
+            Ok(Payload::Control(control)) => assert_eq!(control, hello),
+            _ => panic!("Expected a control packet."),
+        }
+
+        assert_eq!(
+            packer
+                .unpack_payload(&packer.pack_messages(&[1, 2]))
+                .unwrap(),
+            Payload::Messages(vec![1, 2])
+        );
+
+        match packer.unpack_payload(&[0, 7]) {
+            Err(ErrorKind::SerializationError(_)) => {}
+            _ => panic!("Expected a serialization error."),
+        }
+    }
+}
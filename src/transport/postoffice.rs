@@ -3,16 +3,22 @@ use std::{
         hash_map::{Iter, IterMut},
         HashMap,
     },
+    hash::Hash,
     iter::Filter,
     net::SocketAddr,
+    time::{Duration, Instant},
 };
 
 use log::debug;
 
 use crate::{
-    synchronisation::{NetworkCommand, NetworkMessage},
+    error::ErrorKind,
+    synchronisation::{
+        ComponentDiffs, NetworkCommand, NetworkMessage, SnapshotHistory, WorldSnapshot,
+    },
     transport,
-    transport::{Client, ClientId},
+    transport::{AcceptAll, Authenticator, Client, ClientId, DisconnectReason, SessionToken},
+    uid::{UidAllocator, UidRange},
 };
 
 pub struct PostOffice<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>
@@ -25,6 +31,20 @@ where
         ClientId,
         Client<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>,
     >,
+    /// Clients that lost their connection, with the moment they lost it.
+    suspended: HashMap<
+        ClientId,
+        (
+            Client<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>,
+            Instant,
+        ),
+    >,
+    session_grace: Duration,
+    snapshots: SnapshotHistory,
+    authenticator: Box<dyn Authenticator>,
+    next_client_id: ClientId,
+    /// Identifiers that were reserved for clients that are removed since.
+    released_uids: Vec<UidRange>,
 }
 
 impl<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>
@@ -36,11 +56,55 @@ where
 {
     pub fn new() -> PostOffice<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>
     {
+        PostOffice::with_snapshot_history(SnapshotHistory::new())
+    }
+
+    /// Returns a new `PostOffice` that encodes world states against the snapshots of the given
+    /// history.
+    pub fn with_snapshot_history(
+        snapshots: SnapshotHistory,
+    ) -> PostOffice<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand> {
         PostOffice {
             clients: HashMap::new(),
+            suspended: HashMap::new(),
+            session_grace: Duration::from_secs(0),
+            snapshots,
+            authenticator: Box::new(AcceptAll),
+            next_client_id: 0,
+            released_uids: Vec::new(),
         }
     }
 
+    /// Sets the authenticator that decides whether a connecting client gets a `Client`.
+    pub fn set_authenticator(&mut self, authenticator: impl Authenticator + 'static) {
+        self.authenticator = Box::new(authenticator);
+    }
+
+    pub fn authenticator_mut(&mut self) -> &mut dyn Authenticator {
+        self.authenticator.as_mut()
+    }
+
+    /// Sets how long the `Client` of a client that lost its connection is kept, so that the client
+    /// can resume its session. The default of zero does not keep sessions.
+    pub fn set_session_grace(&mut self, session_grace: Duration) {
+        self.session_grace = session_grace;
+    }
+
+    pub fn session_grace(&self) -> Duration {
+        self.session_grace
+    }
+
+    /// Returns the history of snapshots sent by `broadcast_snapshot`.
+    pub fn snapshot_history(&self) -> &SnapshotHistory {
+        &self.snapshots
+    }
+
+    /// Returns the component types whose changes `broadcast_snapshot` sends as serde-diff
+    /// differences, to register types.
+    pub fn component_diffs_mut(&mut self) -> &mut ComponentDiffs {
+        self.snapshots.diffs_mut()
+    }
+
     pub fn clients(
         &self,
     ) -> Iter<ClientId, Client<ServerToClientMessage, ClientToServerMessage, ClientToServerCommand>>
@@ -57,25 +121,150 @@ where
         self.clients.iter_mut()
     }
 
+    /// Registers a client for the given address and returns its id.
+    ///
+    /// Returns `None` if a client with this address exists, or if all ids are taken by connected
+    /// and suspended clients.
     pub fn add_client(&mut self, addr: SocketAddr) -> Option<ClientId> {
-        let new_client_id = self.client_count() as u16;
-        if !self.client_exists(addr) {
-            self.clients
-                .insert(new_client_id, Client::new(addr, new_client_id));
-            return Some(new_client_id);
-        } else {
-            None
+        if self.client_exists(addr) {
+            return None;
         }
+
+        self.remove_expired_sessions(Instant::now());
+
+        // Ids are not derived from the client count, which shrinks when clients are removed.
+        let new_client_id = (0..=ClientId::MAX)
+            .map(|n| self.next_client_id.wrapping_add(n))
+            .find(|client_id| {
+                !self.clients.contains_key(client_id) && !self.suspended.contains_key(client_id)
+            })?;
+        self.next_client_id = new_client_id.wrapping_add(1);
+
+        self.clients
+            .insert(new_client_id, Client::new(addr, new_client_id));
+        Some(new_client_id)
     }
 
-    pub fn remove_client(&mut self, client_id: &ClientId) {
-        if !self.clients.contains_key(client_id) {
-            self.clients.remove(client_id);
-        } else {
-            panic!("Tried to remove client, but it doesn't exist.");
+    pub fn remove_client(&mut self, client_id: &ClientId) -> Result<(), ErrorKind> {
+        let mut client = self
+            .clients
+            .remove(client_id)
+            .ok_or(ErrorKind::ClientNotFound(*client_id))?;
+
+        self.released_uids.extend(client.take_reserved_uids());
+        Ok(())
+    }
+
+    /// Hands the identifiers that were reserved for removed clients back to the allocator.
+    ///
+    /// Clients are removed by the transports as well, so this is called regularly, for example
+    /// before reserving identifiers.
+    pub fn release_uids<T: Hash + Eq + Clone>(&mut self, allocator: &mut UidAllocator<T>) {
+        for range in self.released_uids.drain(..) {
+            allocator.release_reserved(range);
         }
     }
 
+    /// Removes a client that lost its connection.
+    ///
+    /// The client is kept during the session grace period, in which it can resume its session
+    /// from another address.
+    pub fn suspend_client(&mut self, client_id: &ClientId) {
+        let now = Instant::now();
+        self.remove_expired_sessions(now);
+
+        if let Some(mut client) = self.clients.remove(client_id) {
+            if self.session_grace > Duration::from_secs(0) {
+                self.suspended.insert(*client_id, (client, now));
+            } else {
+                self.released_uids.extend(client.take_reserved_uids());
+            }
+        }
+    }
+
+    /// Suspends a client that lost its connection, and removes a client that disconnected on
+    /// purpose.
+    pub(crate) fn client_disconnected(&mut self, client_id: &ClientId, reason: &DisconnectReason) {
+        if reason.can_resume() {
+            self.suspend_client(client_id);
+        } else if let Err(e) = self.remove_client(client_id) {
+            debug!("Disconnected client was already removed: {:?}", e);
+        }
+    }
+
+    /// Suspends the clients from which no packet was received within `timeout`, and returns their
+    /// ids and addresses.
+    pub fn suspend_idle_clients(
+        &mut self,
+        timeout: Duration,
+        now: Instant,
+    ) -> Vec<(ClientId, SocketAddr)> {
+        let idle = self
+            .clients
+            .iter()
+            .filter(|(_, client)| now.saturating_duration_since(client.last_packet()) > timeout)
+            .map(|(client_id, client)| (*client_id, client.addr()))
+            .collect::<Vec<_>>();
+
+        for (client_id, _) in idle.iter() {
+            self.suspend_client(client_id);
+        }
+
+        idle
+    }
+
+    /// Returns the number of suspended clients whose session can still be resumed.
+    pub fn suspended_count(&self) -> usize {
+        self.suspended.len()
+    }
+
+    /// Moves the client of the given session to the address it reconnected from.
+    ///
+    /// A client that reconnects before its lost connection was noticed takes over its `Client`
+    /// directly. Returns the id of the client, or `None` if the session does not exist or expired.
+    pub(crate) fn resume_session(
+        &mut self,
+        session: SessionToken,
+        addr: SocketAddr,
+    ) -> Option<ClientId> {
+        self.remove_expired_sessions(Instant::now());
+
+        let suspended = self
+            .suspended
+            .iter()
+            .find(|(_, (client, _))| client.session_token() == session)
+            .map(|(client_id, _)| *client_id);
+
+        if let Some((mut client, _)) =
+            suspended.and_then(|client_id| self.suspended.remove(&client_id))
+        {
+            let client_id = client.client_id();
+            client.resume(addr);
+            self.clients.insert(client_id, client);
+            return Some(client_id);
+        }
+
+        let client = self
+            .clients
+            .values_mut()
+            .find(|client| client.session_token() == session)?;
+        client.resume(addr);
+        Some(client.client_id())
+    }
+
+    fn remove_expired_sessions(&mut self, now: Instant) {
+        let session_grace = self.session_grace;
+        let released_uids = &mut self.released_uids;
+
+        self.suspended.retain(|_, (client, suspended_at)| {
+            let keep = now.saturating_duration_since(*suspended_at) <= session_grace;
+            if !keep {
+                released_uids.extend(client.take_reserved_uids());
+            }
+            keep
+        });
+    }
+
     pub fn client_exists(&self, addr: SocketAddr) -> bool {
         self.clients.values().any(|v| v.addr() == addr)
     }
@@ -119,6 +308,8 @@ where
 
     pub fn broadcast(&mut self, message: transport::ServerToClientMessage<ServerToClientMessage>) {
         debug!("Broadcast Message");
+        let now = Instant::now();
+
         for client in self.clients_mut() {
             let mut message = message.clone();
             let client_offset_from_server = client.1.command_postbox().command_frame_offset();
@@ -127,6 +318,10 @@ where
             // The client uses this value to adjust his local synchronisation speed.
             if let transport::ServerToClientMessage::StateUpdate(ref mut world_state) = message {
                 world_state.command_frame_offset = client_offset_from_server;
+                client
+                    .1
+                    .acknowledgements_mut()
+                    .sent(world_state.command_frame, now);
             }
 
             let postbox = client.1.postbox_mut();
@@ -134,18 +329,47 @@ where
             postbox.send(message);
         }
     }
+
+    /// Sends the snapshot to all clients as a `StateUpdate`.
+    ///
+    /// Each client receives the differences with the last snapshot it acknowledged.
+    /// A client that did not acknowledge a snapshot, or whose acknowledged snapshot is no longer
+    /// in the history, receives the full snapshot.
+    pub fn broadcast_snapshot(&mut self, snapshot: WorldSnapshot) {
+        debug!("Broadcast Snapshot");
+        let now = Instant::now();
+
+        for (_, client) in self.clients.iter_mut() {
+            let mut world_state = self
+                .snapshots
+                .delta(&snapshot, client.acknowledged_snapshot());
+            world_state.command_frame_offset = client.command_postbox().command_frame_offset();
+            client
+                .acknowledgements_mut()
+                .sent(world_state.command_frame, now);
+
+            client
+                .postbox_mut()
+                .send(transport::ServerToClientMessage::StateUpdate(world_state));
+        }
+
+        self.snapshots.push(snapshot);
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::SocketAddr;
+    use std::{net::SocketAddr, thread, time::Duration};
 
     use crate::{
-        synchronisation::WorldState,
+        error::ErrorKind,
+        serialization::{DefaultSerialization, SerializationStrategy},
+        synchronisation::{ComponentData, WorldSnapshot, WorldState},
         transport::{
-            Client, ClientId, ClientToServerMessage, PostOffice, ServerToClientMessage,
-            ServerToClientMessage::StateUpdate,
+            Client, ClientId, ClientToServerMessage, DisconnectReason, PostOffice,
+            ServerToClientMessage, ServerToClientMessage::StateUpdate,
         },
+        uid::{self, UidAllocator},
     };
 
     fn test_payload() -> &'static [u8] {
@@ -218,6 +442,194 @@ mod tests {
         };
     }
 
+    #[test]
+    fn broadcast_snapshot_should_encode_against_acknowledged_snapshot() {
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        postoffice
+            .component_diffs_mut()
+            .register::<u32, _>(1, DefaultSerialization::default());
+        let component = |value: u32| {
+            ComponentData::new(
+                1,
+                DefaultSerialization::default().serialize(&value).unwrap(),
+            )
+        };
+
+        let acknowledging = postoffice
+            .add_client("127.0.0.1:10".parse().unwrap())
+            .unwrap();
+        let lagging = postoffice
+            .add_client("127.0.0.1:20".parse().unwrap())
+            .unwrap();
+
+        let mut snapshot = WorldSnapshot::new(1);
+        snapshot.insert_entity(1, vec![component(1)]);
+        snapshot.insert_entity(2, vec![component(2)]);
+        postoffice.broadcast_snapshot(snapshot.clone());
+
+        postoffice
+            .client_by_id_mut(&acknowledging)
+            .unwrap()
+            .acknowledge_snapshot(1);
+
+        snapshot.set_component(2, component(3));
+        postoffice.broadcast_snapshot(snapshot);
+
+        let mut latest_state = |client_id| match postoffice
+            .client_by_id_mut(&client_id)
+            .unwrap()
+            .postbox_mut()
+            .drain_outgoing(|_| true)
+            .pop()
+        {
+            Some(StateUpdate(world_state)) => world_state,
+            _ => panic!("Expected a state update."),
+        };
+
+        let delta = latest_state(acknowledging);
+        assert_eq!(delta.baseline, Some(1));
+        assert!(!delta.is_full);
+        assert!(delta.inserted.is_empty());
+        assert_eq!(delta.changed.len(), 1);
+
+        let full = latest_state(lagging);
+        assert_eq!(full.baseline, None);
+        assert!(full.is_full);
+        assert_eq!(full.inserted.len(), 2);
+    }
+
+    #[test]
+    fn client_ids_skip_ids_in_use_after_wrapping() {
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        postoffice.set_session_grace(Duration::from_secs(10));
+
+        let first = postoffice
+            .add_client("127.0.0.1:10".parse().unwrap())
+            .unwrap();
+        let suspended = postoffice
+            .add_client("127.0.0.1:20".parse().unwrap())
+            .unwrap();
+        postoffice.suspend_client(&suspended);
+        postoffice.next_client_id = ClientId::MAX;
+
+        let last = postoffice.add_client("127.0.0.1:30".parse().unwrap());
+        let wrapped = postoffice.add_client("127.0.0.1:40".parse().unwrap());
+
+        assert_eq!(last, Some(ClientId::MAX));
+        // Id 0 is connected and id 1 is suspended.
+        assert_eq!(wrapped, Some(2));
+        assert_eq!(
+            postoffice.client_by_id_mut(&first).unwrap().addr(),
+            "127.0.0.1:10".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn reserved_uids_of_removed_clients_are_released() {
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        postoffice.set_session_grace(Duration::from_millis(10));
+        let mut allocator = UidAllocator::<u32>::new();
+
+        let removed = postoffice
+            .add_client("127.0.0.1:10".parse().unwrap())
+            .unwrap();
+        let suspended = postoffice
+            .add_client("127.0.0.1:20".parse().unwrap())
+            .unwrap();
+        let mut reserve = |postoffice: &mut PostOffice<u32, u32, u32>, client_id| {
+            postoffice
+                .client_by_id_mut(&client_id)
+                .unwrap()
+                .reserve_uids(&mut allocator, 2)
+                .unwrap()
+        };
+        let first = reserve(&mut postoffice, removed);
+        let second = reserve(&mut postoffice, suspended);
+
+        postoffice.remove_client(&removed).unwrap();
+        postoffice.suspend_client(&suspended);
+        // The session of the suspended client expires when the next client is added.
+        thread::sleep(Duration::from_millis(20));
+        postoffice.add_client("127.0.0.1:30".parse().unwrap());
+
+        postoffice.release_uids(&mut allocator);
+        for uid in first.iter().chain(second.iter()) {
+            assert!(allocator.is_stale(uid));
+        }
+        assert_eq!(
+            uid::index(allocator.allocate(1, None).unwrap()),
+            first.start
+        );
+    }
+
+    #[test]
+    fn suspended_client_resumes_session_with_full_state() {
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        postoffice.set_session_grace(Duration::from_secs(10));
+
+        let client_id = postoffice
+            .add_client("127.0.0.1:10".parse().unwrap())
+            .unwrap();
+
+        let mut snapshot = WorldSnapshot::new(1);
+        snapshot.insert_entity(1, vec![ComponentData::new(1, vec![1])]);
+        postoffice.broadcast_snapshot(snapshot.clone());
+
+        let session = {
+            let client = postoffice.client_by_id_mut(&client_id).unwrap();
+            client.postbox_mut().drain_outgoing(|_| true);
+            client.command_postbox.command_frame_offset = 4;
+            client.acknowledge_snapshot(1);
+            client.session_token()
+        };
+
+        postoffice.client_disconnected(&client_id, &DisconnectReason::ConnectionLost);
+        assert_eq!(postoffice.client_count(), 0);
+        assert_eq!(postoffice.suspended_count(), 1);
+
+        let new_addr: SocketAddr = "127.0.0.1:11".parse().unwrap();
+        assert_eq!(postoffice.resume_session(session + 1, new_addr), None);
+        assert_eq!(
+            postoffice.resume_session(session, new_addr),
+            Some(client_id)
+        );
+        assert_eq!(postoffice.suspended_count(), 0);
+
+        snapshot.set_component(1, ComponentData::new(1, vec![2]));
+        postoffice.broadcast_snapshot(snapshot);
+
+        let client = postoffice.client_by_addr_mut(&new_addr).unwrap();
+        assert_eq!(client.client_id(), client_id);
+        assert_eq!(client.acknowledged_snapshot(), None);
+        assert_eq!(client.command_postbox.command_frame_offset, 4);
+        match client.postbox_mut().drain_outgoing(|_| true).pop() {
+            Some(StateUpdate(world_state)) => assert_eq!(world_state.baseline, None),
+            _ => panic!("Expected a state update."),
+        }
+    }
+
+    #[test]
+    fn kicked_client_can_not_resume_session() {
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        postoffice.set_session_grace(Duration::from_secs(10));
+
+        let client_id = postoffice
+            .add_client("127.0.0.1:10".parse().unwrap())
+            .unwrap();
+        let session = postoffice
+            .client_by_id_mut(&client_id)
+            .unwrap()
+            .session_token();
+
+        postoffice.client_disconnected(&client_id, &DisconnectReason::Kicked);
+
+        assert_eq!(postoffice.suspended_count(), 0);
+        assert_eq!(
+            postoffice.resume_session(session, "127.0.0.1:11".parse().unwrap()),
+            None
+        );
+    }
+
     #[test]
     fn get_client_by_address_should_return() {
         let mut postoffice = PostOffice::<u32, u32, u32>::new();
@@ -273,6 +685,21 @@ mod tests {
             .is_none());
     }
 
+    #[test]
+    fn removing_unknown_client_returns_error() {
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+
+        let client_id = postoffice
+            .add_client("127.0.0.1:10".parse().unwrap())
+            .unwrap();
+
+        assert!(postoffice.remove_client(&client_id).is_ok());
+        assert!(matches!(
+            postoffice.remove_client(&client_id),
+            Err(ErrorKind::ClientNotFound(id)) if id == client_id
+        ));
+    }
+
     #[test]
     fn returns_only_clients_with_inbox() {
         let mut postoffice = PostOffice::<u32, u32, u32>::new();
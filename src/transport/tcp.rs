@@ -1,49 +1,211 @@
 use std::{
     collections::{hash_map::IterMut, HashMap},
     io,
-    io::Write,
-    net::{SocketAddr, TcpListener, TcpStream},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::{Duration, Instant},
 };
 
 use crate::{
+    compression::{CompressionStrategy, DefaultCompression},
     error::ErrorKind,
     event::{NetworkEvent, NetworkEventQueue},
+    serialization::{DefaultSerialization, SerializationStrategy},
     synchronisation::{CommandFrame, NetworkCommand, NetworkMessage},
     transport,
-    transport::{PostBox, PostOffice},
+    transport::{
+        control, heartbeat, ClientId, ClientTransport, ControlPacket, DisconnectReason,
+        FrameReader, FrameWriter, HandshakeConfig, HandshakeReply, HeartbeatConfig, Packer,
+        Payload, PostBox, PostOffice, ServerTransport, SessionToken,
+    },
 };
 use log::{debug, error};
+use serde::Serialize;
 use std::{
     collections::hash_map::{Iter, Keys},
     io::Read,
+    mem,
 };
 
-pub struct TcpClientResource {
+/// The size of the buffer the transports read into before bytes are reassembled into frames.
+const RECV_BUFFER_SIZE: usize = 4096;
+
+/// The maximum time the remaining frames of a closing stream are written for, before it is shut
+/// down anyway.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Configuration of the attempts of a client to resume its session after losing its connection.
+#[derive(Clone, Debug)]
+pub struct ReconnectConfig {
+    /// The delay before the first attempt, it doubles with every failed attempt.
+    pub initial_delay: Duration,
+    /// The maximum delay between two attempts.
+    pub max_delay: Duration,
+    /// The client stops reconnecting after this number of failed attempts.
+    pub max_attempts: u32,
+    /// The maximum time a single attempt blocks while connecting.
+    pub connect_timeout: Duration,
+}
+
+impl ReconnectConfig {
+    /// Returns the delay before the given attempt, counting from zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(8),
+            max_attempts: 10,
+            connect_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+pub struct TcpClientResource<
+    S: SerializationStrategy = DefaultSerialization,
+    C: CompressionStrategy = DefaultCompression,
+> {
     stream: TcpStream,
+    server_addr: SocketAddr,
+    handshake: HandshakeConfig,
     connected: bool,
+    client_id: Option<ClientId>,
+    session: Option<SessionToken>,
+    reconnect: Option<ReconnectConfig>,
+    /// The number of failed attempts and the moment of the next attempt, while reconnecting.
+    reconnecting: Option<(u32, Instant)>,
+    heartbeat: HeartbeatConfig,
+    last_sent: Instant,
+    last_received: Instant,
+    reader: FrameReader,
+    writer: FrameWriter,
+    packer: Packer<S, C>,
+    recv_buffer: Vec<u8>,
 }
 
 impl TcpClientResource {
     pub fn new(addr: SocketAddr) -> Result<TcpClientResource, ErrorKind> {
-        let stream = TcpStream::connect(addr).unwrap();
-        stream.set_nonblocking(true).unwrap();
+        TcpClientResource::with_packer(addr, Packer::default())
+    }
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> TcpClientResource<S, C> {
+    /// Returns a new `TcpClientResource` that packs its packets with the given packer.
+    pub fn with_packer(
+        addr: SocketAddr,
+        packer: Packer<S, C>,
+    ) -> Result<TcpClientResource<S, C>, ErrorKind> {
+        TcpClientResource::with_handshake(addr, HandshakeConfig::default(), packer)
+    }
+
+    /// Returns a new `TcpClientResource` that introduces itself to the server with the given
+    /// handshake config.
+    pub fn with_handshake(
+        addr: SocketAddr,
+        handshake: HandshakeConfig,
+        packer: Packer<S, C>,
+    ) -> Result<TcpClientResource<S, C>, ErrorKind> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nonblocking(true)?;
 
-        Ok(TcpClientResource {
+        let hello = packer.pack_control(&ControlPacket::Hello(handshake.hello()))?;
+
+        let mut resource = TcpClientResource {
             stream,
+            server_addr: addr,
+            handshake,
             connected: true,
-        })
+            client_id: None,
+            session: None,
+            reconnect: None,
+            reconnecting: None,
+            heartbeat: HeartbeatConfig::default(),
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            reader: FrameReader::default(),
+            writer: FrameWriter::default(),
+            packer,
+            recv_buffer: vec![0; RECV_BUFFER_SIZE],
+        };
+
+        // The stream is ordered, messages sent before the reply arrives are processed after the hello.
+        resource.sent(&hello)?;
+
+        Ok(resource)
     }
 
     pub fn is_connected(&self) -> bool {
         self.connected
     }
 
+    /// Returns the id the server assigned to this client, once the handshake is accepted.
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.client_id
+    }
+
     pub fn set_connected(&mut self, connected: bool) {
         self.connected = connected;
     }
 
-    pub fn sent(&mut self, data: &[u8]) -> Result<usize, ErrorKind> {
-        Ok(self.stream.write(data)?)
+    /// Enables or disables resuming the session after the connection is lost, disabled by
+    /// default.
+    pub fn set_reconnect(&mut self, reconnect: Option<ReconnectConfig>) {
+        self.reconnect = reconnect;
+    }
+
+    pub fn reconnect(&self) -> Option<&ReconnectConfig> {
+        self.reconnect.as_ref()
+    }
+
+    /// Returns true if the client lost its connection and is trying to resume its session.
+    pub fn is_reconnecting(&self) -> bool {
+        self.reconnecting.is_some()
+    }
+
+    pub fn heartbeat(&self) -> &HeartbeatConfig {
+        &self.heartbeat
+    }
+
+    /// Sets the heartbeat interval, and the timeout after which a silent server is disconnected.
+    pub fn set_heartbeat(&mut self, heartbeat: HeartbeatConfig) {
+        self.heartbeat = heartbeat;
+    }
+
+    /// Queues the data as a single frame and writes as much of the queued frames as possible.
+    pub fn sent(&mut self, data: &[u8]) -> Result<(), ErrorKind> {
+        self.last_sent = Instant::now();
+        self.writer.push_frame(data)?;
+        self.flush()
+    }
+
+    /// Writes frames that could not be written completely by a previous call to `sent`.
+    pub fn flush(&mut self) -> Result<(), ErrorKind> {
+        self.writer.flush(&mut self.stream)?;
+        Ok(())
+    }
+
+    /// Reads all available bytes from the stream into the frame reassembly buffer.
+    ///
+    /// Returns the number of bytes read.
+    /// A closed stream is reported as `ConnectionReset` error.
+    pub fn receive(&mut self, recv_buffer: &mut [u8]) -> Result<usize, ErrorKind> {
+        let result = read_available(&mut self.stream, &mut self.reader, recv_buffer);
+
+        if matches!(result, Ok(received) if received > 0) {
+            self.last_received = Instant::now();
+        }
+
+        result
+    }
+
+    /// Returns the next complete frame received from the server.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, ErrorKind> {
+        self.reader.next_frame()
     }
 
     pub fn stream(&self) -> &TcpStream {
@@ -57,21 +219,159 @@ impl TcpClientResource {
     pub fn addr(&self) -> Result<SocketAddr, ErrorKind> {
         Ok(self.stream.local_addr()?)
     }
+
+    pub fn packer(&self) -> &Packer<S, C> {
+        &self.packer
+    }
+}
+
+/// A stream of the `TcpListenerResource` whose remaining frames are written before it is shut
+/// down.
+struct ClosingStream {
+    stream: TcpStream,
+    writer: FrameWriter,
+    deadline: Instant,
+}
+
+impl ClosingStream {
+    /// Writes as much of the remaining frames as possible without blocking, and shuts the stream
+    /// down once they are written or the deadline passed. Returns true if the stream is closed.
+    fn flush(&mut self, now: Instant) -> bool {
+        let shutdown = match self.writer.flush(&mut self.stream) {
+            Ok(true) => Shutdown::Write,
+            Ok(false) if now < self.deadline => return false,
+            Ok(false) => {
+                debug!("Remaining frames of closing TCP stream were not written in time.");
+                Shutdown::Both
+            }
+            Err(e) => {
+                debug!("Error occurred when closing TCP stream. Reason: {:?}", e);
+                return true;
+            }
+        };
+
+        if let Err(e) = self.stream.shutdown(shutdown) {
+            debug!("Error occurred when closing TCP stream. Reason: {:?}", e);
+        }
+        true
+    }
+}
+
+/// A stream accepted by the `TcpListenerResource` together with its framing state.
+pub struct TcpConnection {
+    pub active: bool,
+    pub stream: TcpStream,
+    client_id: Option<ClientId>,
+    authenticating: bool,
+    connected_at: Instant,
+    last_sent: Instant,
+    reader: FrameReader,
+    writer: FrameWriter,
+}
+
+impl TcpConnection {
+    pub fn new(stream: TcpStream) -> TcpConnection {
+        TcpConnection {
+            active: true,
+            stream,
+            client_id: None,
+            authenticating: false,
+            connected_at: Instant::now(),
+            last_sent: Instant::now(),
+            reader: FrameReader::default(),
+            writer: FrameWriter::default(),
+        }
+    }
+
+    /// Queues the data as a single frame and writes as much of the queued frames as possible.
+    pub fn sent(&mut self, data: &[u8]) -> Result<(), ErrorKind> {
+        self.last_sent = Instant::now();
+        self.writer.push_frame(data)?;
+        self.flush()
+    }
+
+    /// Writes frames that could not be written completely by a previous call to `sent`.
+    pub fn flush(&mut self) -> Result<(), ErrorKind> {
+        self.writer.flush(&mut self.stream)?;
+        Ok(())
+    }
+
+    /// Reads all available bytes from the stream into the frame reassembly buffer.
+    pub fn receive(&mut self, recv_buffer: &mut [u8]) -> Result<usize, ErrorKind> {
+        read_available(&mut self.stream, &mut self.reader, recv_buffer)
+    }
+
+    /// Returns the next complete frame received from this stream.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, ErrorKind> {
+        self.reader.next_frame()
+    }
+
+    /// Returns the id of the client on this stream, once its handshake is accepted.
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.client_id
+    }
 }
 
-pub struct TcpListenerResource {
+pub struct TcpListenerResource<
+    S: SerializationStrategy = DefaultSerialization,
+    C: CompressionStrategy = DefaultCompression,
+> {
     listener: Option<TcpListener>,
-    streams: HashMap<SocketAddr, (bool, TcpStream)>,
+    streams: HashMap<SocketAddr, TcpConnection>,
+    closing: Vec<ClosingStream>,
+    handshake: HandshakeConfig,
+    heartbeat: HeartbeatConfig,
+    packer: Packer<S, C>,
+    recv_buffer: Vec<u8>,
 }
 
 impl TcpListenerResource {
     pub fn new(listener: Option<TcpListener>) -> Self {
+        TcpListenerResource::with_packer(listener, Packer::default())
+    }
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> TcpListenerResource<S, C> {
+    /// Returns a new `TcpListenerResource` that packs its packets with the given packer.
+    pub fn with_packer(listener: Option<TcpListener>, packer: Packer<S, C>) -> Self {
+        Self::with_handshake(listener, HandshakeConfig::default(), packer)
+    }
+
+    /// Returns a new `TcpListenerResource` that only accepts clients whose hello matches the given
+    /// handshake config.
+    pub fn with_handshake(
+        listener: Option<TcpListener>,
+        handshake: HandshakeConfig,
+        packer: Packer<S, C>,
+    ) -> Self {
         Self {
             listener,
             streams: HashMap::new(),
+            closing: Vec::new(),
+            handshake,
+            heartbeat: HeartbeatConfig::default(),
+            packer,
+            recv_buffer: vec![0; RECV_BUFFER_SIZE],
         }
     }
 
+    pub fn handshake(&self) -> &HandshakeConfig {
+        &self.handshake
+    }
+
+    pub fn heartbeat(&self) -> &HeartbeatConfig {
+        &self.heartbeat
+    }
+
+    /// Sets the heartbeat interval, and the timeout after which a silent client is disconnected.
+    pub fn set_heartbeat(&mut self, heartbeat: HeartbeatConfig) {
+        self.heartbeat = heartbeat;
+    }
+
+    pub fn packer(&self) -> &Packer<S, C> {
+        &self.packer
+    }
+
     /// Returns an immutable reference to the listener if there is one configured.
     pub fn get(&self) -> Option<&TcpListener> {
         self.listener.as_ref()
@@ -92,43 +392,83 @@ impl TcpListenerResource {
         self.listener = None;
     }
 
-    /// Returns a tuple of an active TcpStream and whether ot not that stream is active
-    pub fn get_stream(&mut self, addr: SocketAddr) -> Option<&mut (bool, TcpStream)> {
+    /// Returns the connection of the stream with the given `SocketAddr`.
+    pub fn get_stream(&mut self, addr: SocketAddr) -> Option<&mut TcpConnection> {
         self.streams.get_mut(&addr)
     }
 
     /// Registers an new incoming stream to the TCP listener.
     pub fn register_stream(&mut self, addr: SocketAddr, stream: TcpStream) {
-        self.streams.insert(addr, (true, stream));
+        self.streams.insert(addr, TcpConnection::new(stream));
     }
 
     /// Drops the stream with the given `SocketAddr`. This will be called when a peer seems to have
     /// been disconnected
-    pub fn drop_stream(&mut self, addr: SocketAddr) -> Option<(bool, TcpStream)> {
+    pub fn drop_stream(&mut self, addr: SocketAddr) -> Option<TcpConnection> {
         self.streams.remove(&addr)
     }
 
+    /// Closes the stream after its remaining frames are written.
+    ///
+    /// The stream stays nonblocking, frames that can not be written right away are written by
+    /// `flush_closing` on later ticks, until the close timeout passes.
+    pub fn close_stream(&mut self, connection: TcpConnection) {
+        let mut closing = ClosingStream {
+            stream: connection.stream,
+            writer: connection.writer,
+            deadline: Instant::now() + CLOSE_TIMEOUT,
+        };
+
+        if !closing.flush(Instant::now()) {
+            self.closing.push(closing);
+        }
+    }
+
+    /// Writes the remaining frames of the closing streams, and shuts down the streams that are
+    /// written or whose close timeout passed. This is called by `tcp_server_sent_system`.
+    pub fn flush_closing(&mut self) {
+        let now = Instant::now();
+
+        self.closing = mem::take(&mut self.closing)
+            .into_iter()
+            .filter_map(|mut closing| {
+                if closing.flush(now) {
+                    None
+                } else {
+                    Some(closing)
+                }
+            })
+            .collect();
+    }
+
+    /// Returns the number of closed streams whose remaining frames are still being written.
+    pub fn closing_count(&self) -> usize {
+        self.closing.len()
+    }
+
     /// Returns an iterator over the Tcp listener its streams.
-    pub fn iter(&self) -> Iter<'_, SocketAddr, (bool, TcpStream)> {
+    pub fn iter(&self) -> Iter<'_, SocketAddr, TcpConnection> {
         self.streams.iter()
     }
 
     /// Returns a mutable iterator over the Tcp listener its streams.
-    pub fn iter_mut(&mut self) -> IterMut<'_, SocketAddr, (bool, TcpStream)> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, SocketAddr, TcpConnection> {
         self.streams.iter_mut()
     }
 
-    pub fn addresses(&self) -> Keys<SocketAddr, (bool, TcpStream)> {
+    pub fn addresses(&self) -> Keys<SocketAddr, TcpConnection> {
         self.streams.keys()
     }
 }
 
 pub fn tcp_connection_listener<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
     ServerToClientMessage: NetworkMessage,
     ClientToServerMessage: NetworkMessage,
     ClientToServerCommand: NetworkCommand,
 >(
-    tcp: &mut TcpListenerResource,
+    tcp: &mut TcpListenerResource<S, C>,
     postoffice: &mut PostOffice<
         ServerToClientMessage,
         ClientToServerMessage,
@@ -140,13 +480,32 @@ pub fn tcp_connection_listener<
         return;
     }
 
-    loop {
-        let (stream, addr) = match tcp.get().unwrap().accept() {
+    accept_streams(tcp);
+
+    let mut recv_buffer = mem::take(&mut tcp.recv_buffer);
+    handshake_streams(tcp, postoffice, network_events, &mut recv_buffer);
+    tcp.recv_buffer = recv_buffer;
+}
+
+/// Registers the incoming streams, they become clients once their handshake is accepted.
+fn accept_streams<S: SerializationStrategy, C: CompressionStrategy>(
+    tcp: &mut TcpListenerResource<S, C>,
+) {
+    while let Some(listener) = tcp.get() {
+        let (stream, addr) = match listener.accept() {
             Ok((stream, addr)) => {
-                stream
+                // A stream that can not be configured is dropped, the client notices it is closed.
+                let configured = stream
                     .set_nonblocking(true)
-                    .expect("Setting nonblocking mode");
-                stream.set_nodelay(true).expect("Setting nodelay");
+                    .and_then(|_| stream.set_nodelay(true));
+
+                if let Err(e) = configured {
+                    error!(
+                        "Error occurred when configuring TCP stream: {:?}. Reason: {:?}",
+                        addr, e
+                    );
+                    continue;
+                }
 
                 debug!("Incoming TCP connection: {:?}", addr);
 
@@ -163,18 +522,193 @@ pub fn tcp_connection_listener<
         };
 
         tcp.register_stream(addr, stream);
-        postoffice.add_client(addr);
-        network_events.enqueue(NetworkEvent::Connected(addr))
+    }
+}
+
+/// Reads the hello of the streams that are not yet accepted and replies to them, or polls their
+/// deferred authentication.
+///
+/// Streams that are rejected, that send something else than a hello, or that are not
+/// authenticated within the handshake timeout are closed. Frames that follow the hello are left for
+/// `tcp_server_receive_system`.
+fn handshake_streams<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    tcp: &mut TcpListenerResource<S, C>,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    network_events: &mut NetworkEventQueue,
+    recv_buffer: &mut [u8],
+) {
+    let now = Instant::now();
+    let mut closed = Vec::new();
+
+    for (addr, connection) in tcp.streams.iter_mut() {
+        if connection.client_id.is_some() {
+            continue;
+        }
+
+        if !connection.active {
+            if connection.authenticating {
+                control::cancel_authentication(
+                    *addr,
+                    DisconnectReason::ConnectionLost,
+                    postoffice,
+                    network_events,
+                );
+            }
+            closed.push(*addr);
+            continue;
+        }
+
+        let timed_out =
+            now.saturating_duration_since(connection.connected_at) > tcp.handshake.timeout;
+
+        if connection.authenticating {
+            // Frames that follow the hello stay in the reader until the stream is accepted.
+            if let Err(ErrorKind::IoError(e)) = connection.receive(recv_buffer) {
+                debug!("TCP stream {:?} closed during authentication: {}", addr, e);
+                control::cancel_authentication(
+                    *addr,
+                    DisconnectReason::ConnectionLost,
+                    postoffice,
+                    network_events,
+                );
+                closed.push(*addr);
+                continue;
+            }
+
+            let reply = match control::poll_authentication(*addr, postoffice, network_events) {
+                Some(reply) => reply,
+                None if timed_out => {
+                    debug!("TCP stream {:?} was not authenticated in time.", addr);
+                    control::expire_authentication(*addr, postoffice, network_events)
+                }
+                None => continue,
+            };
+
+            connection.authenticating = false;
+            reply_to_hello(&tcp.packer, connection, reply);
+
+            if !connection.active {
+                closed.push(*addr);
+            }
+            continue;
+        }
+
+        if timed_out {
+            debug!("TCP stream {:?} sent no hello in time.", addr);
+            closed.push(*addr);
+            continue;
+        }
+
+        let result = connection.receive(recv_buffer);
+
+        let reply = match connection.next_frame() {
+            Ok(Some(frame)) => {
+                match tcp
+                    .packer
+                    .unpack_payload(&frame)
+                    .map(|payload| match payload {
+                        Payload::Control(control) => control.into_hello(),
+                        Payload::Messages(_) => None,
+                    }) {
+                    Ok(Some((hello, session))) => match control::accept_hello(
+                        &tcp.handshake,
+                        &hello,
+                        session,
+                        *addr,
+                        postoffice,
+                        network_events,
+                    ) {
+                        Some(reply) => reply,
+                        None => {
+                            connection.authenticating = true;
+                            continue;
+                        }
+                    },
+                    Ok(None) => {
+                        error!(
+                        "Error occurred when handshaking with TCP stream: {:?}. Reason: Expected a hello.",
+                        addr
+                    );
+                        closed.push(*addr);
+                        continue;
+                    }
+                    Err(e) => {
+                        network_events.enqueue(NetworkEvent::Error(*addr, e));
+                        closed.push(*addr);
+                        continue;
+                    }
+                }
+            }
+            Ok(None) => {
+                if let Err(ErrorKind::IoError(e)) = result {
+                    debug!("TCP stream {:?} closed during handshake: {}", addr, e);
+                    closed.push(*addr);
+                }
+                continue;
+            }
+            Err(e) => {
+                error!("Error occurred when reading TCP-frame. Reason: {:?}", e);
+                closed.push(*addr);
+                continue;
+            }
+        };
+
+        reply_to_hello(&tcp.packer, connection, reply);
+
+        if !connection.active {
+            closed.push(*addr);
+        }
+    }
+
+    // The reply of a rejected stream is written before the stream is closed.
+    for addr in closed {
+        if let Some(connection) = tcp.drop_stream(addr) {
+            tcp.close_stream(connection);
+        }
+    }
+}
+
+fn reply_to_hello<S: SerializationStrategy, C: CompressionStrategy>(
+    packer: &Packer<S, C>,
+    connection: &mut TcpConnection,
+    reply: HandshakeReply,
+) {
+    match reply {
+        HandshakeReply::Accepted(client_id, _) => connection.client_id = Some(client_id),
+        // The stream is closed after the reply is written.
+        HandshakeReply::Rejected(_) => connection.active = false,
+    }
+
+    let result = packer
+        .pack_control(&ControlPacket::HandshakeReply(reply))
+        .and_then(|packet| connection.sent(&packet));
+
+    if let Err(e) = result {
+        error!(
+            "Error occurred when sending TCP handshake reply. Reason: {:?}",
+            e
+        );
     }
 }
 
 pub fn tcp_client_receive_system<
-    //    C: CompressionStrategy + 'static,
+    S: SerializationStrategy,
+    C: CompressionStrategy,
     ServerToClientMessage: NetworkMessage,
     ClientToServerMessage: NetworkMessage,
     ClientToServerCommand: NetworkCommand,
 >(
-    tcp: &mut TcpClientResource,
+    tcp: &mut TcpClientResource<S, C>,
     postbox: &mut PostBox<
         transport::ServerToClientMessage<ServerToClientMessage>,
         transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
@@ -182,70 +716,130 @@ pub fn tcp_client_receive_system<
     network_events: &mut NetworkEventQueue,
     recv_buffer: &mut Vec<u8>,
 ) {
-    let result = tcp.stream().read(recv_buffer);
+    if !tcp.is_connected() {
+        try_reconnect(tcp);
 
-    match result {
-        Ok(recv_len) => {
-            if recv_len < 5 {
-                return;
-            }
+        if !tcp.is_connected() {
+            return;
+        }
+    }
 
-            // match unpacker
-            //     .compression()
-            //     .decompress(&recv_buffer[..recv_len]) {
-            //     Ok(decompressed) => {
-            match bincode::deserialize::<Vec<transport::ServerToClientMessage<ServerToClientMessage>>>(
-                &recv_buffer[..recv_len],
-            ) {
-                Ok(deserialized) => {
-                    debug!("Received {} bytes from server.", recv_len);
-                    for packet in deserialized.into_iter() {
-                        postbox.add_to_inbox(packet);
+    // Frames that arrived before the stream was closed are still processed.
+    let result = tcp.receive(recv_buffer);
+
+    loop {
+        match tcp.next_frame() {
+            Ok(Some(frame)) => match tcp.packer.unpack_payload(&frame) {
+                Ok(Payload::Messages(unpacked)) => {
+                    match tcp.packer.serialization().deserialize::<Vec<
+                        transport::ServerToClientMessage<ServerToClientMessage>,
+                    >>(&unpacked)
+                    {
+                        Ok(deserialized) => {
+                            debug!("Received {} bytes from server.", frame.len());
+                            for packet in deserialized.into_iter() {
+                                postbox.add_to_inbox(packet);
+                            }
+                        }
+                        Err(e) => {
+                            error!(
+                                "Error occurred when deserializing TCP-packet. Reason: {:?}",
+                                e
+                            );
+                        }
+                    }
+                }
+                Ok(Payload::Control(ControlPacket::HandshakeReply(reply))) => {
+                    if let Ok(addr) = tcp.addr() {
+                        match control::handle_reply(reply, addr, tcp.client_id, network_events) {
+                            Some((client_id, session)) => {
+                                tcp.client_id = Some(client_id);
+                                tcp.session = Some(session);
+                            }
+                            None => {
+                                tcp.client_id = None;
+                                tcp.set_connected(false);
+                                return;
+                            }
+                        }
                     }
                 }
+                Ok(Payload::Control(ControlPacket::Disconnect(reason))) => {
+                    disconnect_client(tcp, network_events, reason);
+                    return;
+                }
+                Ok(Payload::Control(control)) => {
+                    debug!("Ignored control packet from server: {:?}", control);
+                }
                 Err(e) => {
-                    error!(
-                        "Error occurred when deserializing TCP-packet. Reason: {:?}",
-                        e
-                    );
+                    // The frame boundaries are intact, so the following frames can still be read.
+                    if let Ok(addr) = tcp.addr() {
+                        network_events.enqueue(NetworkEvent::Error(addr, e));
+                    }
                 }
+            },
+            Ok(None) => break,
+            Err(e) => {
+                // The stream can not be trusted anymore after receiving a malformed frame.
+                error!("Error occurred when reading TCP-frame. Reason: {:?}", e);
+                disconnect_client(tcp, network_events, DisconnectReason::ConnectionLost);
+                return;
             }
-            //     }
-            //     Err(e) => {
-            //         error!("Error occurred when decompressing TCP-packet. Reason: {:?}", e);
-            //     }
-            // }
-        }
-        Err(e) => {
-            match e.kind() {
-                io::ErrorKind::ConnectionReset => {
-                    let addr = tcp
-                        .addr()
-                        .expect("Can not read client local socket address.");
-                    tcp.set_connected(false);
-                    network_events.enqueue(NetworkEvent::Disconnected(addr, 0)) // TODO: replace with current client id
-                }
-                io::ErrorKind::WouldBlock => {}
-                _ => error!("Error occurred when receiving TCP-packet {}", e),
-            };
         }
     }
+
+    if let Err(ErrorKind::IoError(e)) = result {
+        match e.kind() {
+            io::ErrorKind::ConnectionReset => {
+                disconnect_client(tcp, network_events, DisconnectReason::ConnectionLost);
+                return;
+            }
+            _ => error!("Error occurred when receiving TCP-packet {}", e),
+        };
+    }
+
+    if tcp
+        .heartbeat
+        .is_timed_out(tcp.last_received, Instant::now())
+    {
+        disconnect_client(tcp, network_events, DisconnectReason::Timeout);
+    }
 }
 
 pub fn tcp_client_sent_system<
-    //    C: CompressionStrategy + 'static,
+    S: SerializationStrategy,
+    C: CompressionStrategy,
     ServerToClientMessage: NetworkMessage,
     ClientToServerMessage: NetworkMessage,
     ClientToServerCommand: NetworkCommand,
 >(
-    tcp: &mut TcpClientResource,
+    tcp: &mut TcpClientResource<S, C>,
     postbox: &mut PostBox<
         transport::ServerToClientMessage<ServerToClientMessage>,
         transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
     >,
     network_events: &mut NetworkEventQueue,
 ) {
+    if !tcp.is_connected() {
+        return;
+    }
+
     if postbox.empty_outgoing() {
+        let result = if tcp
+            .heartbeat
+            .is_heartbeat_due(tcp.last_sent, Instant::now())
+        {
+            tcp.packer
+                .pack_control(&ControlPacket::Heartbeat)
+                .and_then(|heartbeat| tcp.sent(&heartbeat))
+        } else {
+            // Finish writing frames that were only partially written in a previous tick.
+            tcp.flush()
+        };
+
+        if let Err(e) = result {
+            handle_client_sent_error(tcp, network_events, e);
+        }
         return;
     }
     let packets =
@@ -257,34 +851,13 @@ pub fn tcp_client_sent_system<
         return;
     }
 
-    match bincode::serialize(&packets) {
+    match tcp.packer.serialization().serialize(&packets) {
         Ok(serialized) => {
             debug!("Sending {} packets to host.", packets.len());
-            // let compressed = packer.compression().compress(&serialized);
-            //
-            // if let Err(e) = tcp_client.sent(&compressed) {
-            //     error!("Error occurred when sending TCP-packet. Reason: {:?}", e);
-            // }
-
-            if let Err(e) = tcp.sent(&serialized) {
-                match e {
-                    ErrorKind::IoError(e) => {
-                        match e.kind() {
-                            io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe => {
-                                let addr = tcp
-                                    .addr()
-                                    .expect("Failed tor read client local socket addr.");
-                                tcp.set_connected(false);
-                                network_events.enqueue(NetworkEvent::Disconnected(addr, 0))
-                                // TODO: replace with current client id
-                            }
-                            _ => {
-                                error!("Error occurred when sending TCP-packet. Reason: {:?}", e);
-                            }
-                        }
-                    }
-                    _ => {}
-                }
+            let packed = tcp.packer.pack_messages(&serialized);
+
+            if let Err(e) = tcp.sent(&packed) {
+                handle_client_sent_error(tcp, network_events, e);
             }
         }
         Err(e) => {
@@ -297,12 +870,13 @@ pub fn tcp_client_sent_system<
 }
 
 pub fn tcp_server_receive_system<
-    //    C: CompressionStrategy + 'static,
+    S: SerializationStrategy,
+    C: CompressionStrategy,
     ServerToClientMessage: NetworkMessage,
     ClientToServerMessage: NetworkMessage,
     ClientToServerCommand: NetworkCommand,
 >(
-    tcp: &mut TcpListenerResource,
+    tcp: &mut TcpListenerResource<S, C>,
     postoffice: &mut PostOffice<
         ServerToClientMessage,
         ClientToServerMessage,
@@ -312,50 +886,78 @@ pub fn tcp_server_receive_system<
     network_events: &mut NetworkEventQueue,
     recv_buffer: &mut Vec<u8>,
 ) {
-    for (_, (active, stream)) in tcp.iter_mut() {
+    let mut disconnected = Vec::new();
+    let mut stale = Vec::new();
+
+    for (addr, connection) in tcp.streams.iter_mut() {
+        if !connection.active {
+            continue;
+        }
+
         // If we can't get a peer_addr, there is likely something pretty wrong with the
         // connection so we'll mark it inactive.
-        let peer_addr = match stream.peer_addr() {
+        let peer_addr = match connection.stream.peer_addr() {
             Ok(addr) => addr,
             Err(_e) => {
-                *active = false;
+                connection.active = false;
                 continue;
             }
         };
 
-        loop {
-            let result = stream.read(recv_buffer);
+        let client_id = match connection.client_id {
+            Some(client_id) => client_id,
+            None => continue,
+        };
 
-            let client = postoffice
-                .client_by_addr_mut(&peer_addr)
-                .expect("Client should exist");
+        // Frames that arrived before the stream was closed are still processed.
+        let result = connection.receive(recv_buffer);
 
-            match result {
-                Ok(recv_len) => {
-                    if recv_len < 5 {
-                        *active = false;
-                        break;
-                    }
+        let client = match postoffice.client_by_id_mut(&client_id) {
+            Some(client) if client.addr() == *addr => client,
+            _ => {
+                // The client resumed its session on another stream.
+                stale.push(*addr);
+                continue;
+            }
+        };
+
+        if matches!(result, Ok(received) if received > 0) {
+            client.packet_received();
+        }
+
+        let mut disconnect_reason = None;
 
+        loop {
+            match connection.next_frame() {
+                Ok(Some(frame)) => {
                     debug!(
                         "Received {} bytes from TCP stream: {:?}.",
-                        recv_len, peer_addr
+                        frame.len(),
+                        peer_addr
                     );
 
-                    let buffer = &recv_buffer[..recv_len];
-
-                    // match unpacker
-                    //     .compression()
-                    //     .decompress(buffer) {
-                    //     Ok(decompressed) => {
-                    match bincode::deserialize::<
-                        Vec<
-                            transport::ClientToServerMessage<
-                                ClientToServerMessage,
-                                ClientToServerCommand,
-                            >,
+                    let unpacked = match tcp.packer.unpack_payload(&frame) {
+                        Ok(Payload::Messages(unpacked)) => unpacked,
+                        Ok(Payload::Control(ControlPacket::Disconnect(reason))) => {
+                            disconnect_reason = Some(reason);
+                            break;
+                        }
+                        Ok(Payload::Control(control)) => {
+                            debug!("Ignored control packet from {:?}: {:?}", peer_addr, control);
+                            continue;
+                        }
+                        Err(e) => {
+                            network_events.enqueue(NetworkEvent::Error(peer_addr, e));
+                            continue;
+                        }
+                    };
+
+                    match tcp.packer.serialization().deserialize::<Vec<
+                        transport::ClientToServerMessage<
+                            ClientToServerMessage,
+                            ClientToServerCommand,
                         >,
-                    >(buffer)
+                    >>(&unpacked)
                     {
                         Ok(deserialized) => {
                             debug!("Received {:?} packets", deserialized.len());
@@ -369,38 +971,63 @@ pub fn tcp_server_receive_system<
                                 "Error occurred when deserializing TCP-packet. Reason: {:?}",
                                 e
                             );
-                        } // };
-                          //     }
-                          //     Err(e) => {
-                          //         error!("Error occurred when decompressing TCP-packet. Reason: {:?}", e);
-                          //     }
+                        }
                     }
                 }
+                Ok(None) => break,
                 Err(e) => {
-                    match e.kind() {
-                        io::ErrorKind::ConnectionReset => {
-                            *active = false;
-                            network_events
-                                .enqueue(NetworkEvent::Disconnected(peer_addr, client.client_id()))
-                        }
-                        io::ErrorKind::WouldBlock => {}
-                        _ => {}
-                    };
-
+                    // The stream can not be trusted anymore after receiving a malformed frame.
+                    error!("Error occurred when reading TCP-frame. Reason: {:?}", e);
+                    disconnect_reason = Some(DisconnectReason::ConnectionLost);
                     break;
                 }
-            };
+            }
         }
-    }
+
+        if let Err(ErrorKind::IoError(e)) = result {
+            match e.kind() {
+                io::ErrorKind::ConnectionReset if disconnect_reason.is_none() => {
+                    disconnect_reason = Some(DisconnectReason::ConnectionLost);
+                }
+                io::ErrorKind::ConnectionReset => {}
+                _ => error!("Error occurred when receiving TCP-packet {}", e),
+            };
+        }
+
+        if let Some(reason) = disconnect_reason {
+            connection.active = false;
+            network_events.enqueue(NetworkEvent::Disconnected(
+                peer_addr,
+                client_id,
+                reason.clone(),
+            ));
+            disconnected.push((*addr, client_id, reason));
+        }
+    }
+
+    for addr in stale {
+        tcp.drop_stream(addr);
+    }
+
+    for (addr, client_id, reason) in disconnected {
+        tcp.drop_stream(addr);
+        postoffice.client_disconnected(&client_id, &reason);
+    }
+
+    // Dropping the stream of a timed out client closes it.
+    for addr in heartbeat::disconnect_idle_clients(&tcp.heartbeat, postoffice, network_events) {
+        tcp.drop_stream(addr);
+    }
 }
 
 pub fn tcp_server_sent_system<
-    //    C: CompressionStrategy + 'static,
+    S: SerializationStrategy,
+    C: CompressionStrategy,
     ServerToClientMessage: NetworkMessage,
     ClientToServerMessage: NetworkMessage,
     ClientToServerCommand: NetworkCommand,
 >(
-    tcp: &mut TcpListenerResource,
+    tcp: &mut TcpListenerResource<S, C>,
     postoffice: &mut PostOffice<
         ServerToClientMessage,
         ClientToServerMessage,
@@ -408,50 +1035,994 @@ pub fn tcp_server_sent_system<
     >,
     network_events: &mut NetworkEventQueue,
 ) {
+    tcp.flush_closing();
+
+    let now = Instant::now();
+    let mut disconnected = Vec::new();
+
     for client in postoffice.clients_mut() {
         let addr = client.1.addr();
 
-        let postbox = client.1.postbox_mut();
-        let client_stream = tcp
-            .get_stream(addr)
-            .expect("TCP didn't exist while it is supposed to.");
-
-        let packets = postbox
-            .drain_outgoing(|_| true)
-            .into_iter()
-            .collect::<Vec<transport::ServerToClientMessage<ServerToClientMessage>>>();
+        // The stream of a client can be dropped before the client is removed.
+        let client_stream = match tcp.streams.get_mut(&addr) {
+            Some(client_stream) => client_stream,
+            None => {
+                network_events.enqueue(NetworkEvent::Error(
+                    addr,
+                    ErrorKind::ConnectionNotFound(addr),
+                ));
+                network_events.enqueue(NetworkEvent::Disconnected(
+                    addr,
+                    *client.0,
+                    DisconnectReason::ConnectionLost,
+                ));
+                disconnected.push((addr, *client.0));
+                continue;
+            }
+        };
 
-        if packets.len() == 0 {
+        if !client_stream.active {
             continue;
         }
 
-        match bincode::serialize(&packets) {
-            Ok(serialized) => {
-                debug!("Sending {} packets to TCP stream.", packets.len());
+        let packets = client.1.drain_outgoing();
 
-                // let compressed = packer.compression().compress(&serialized);
-                //
-                // if let Err(e) = client_stream.1.write(&compressed) {
-                //     error!("Error occurred when sending TCP-packet. Reason: {:?}", e);
-                // }
+        let result = if packets.is_empty() {
+            if tcp.heartbeat.is_heartbeat_due(client_stream.last_sent, now) {
+                tcp.packer
+                    .pack_control(&ControlPacket::Heartbeat)
+                    .and_then(|heartbeat| client_stream.sent(&heartbeat))
+            } else {
+                // Finish writing frames that were only partially written in a previous tick.
+                client_stream.flush()
+            }
+        } else {
+            match tcp.packer.serialization().serialize(&packets) {
+                Ok(serialized) => {
+                    debug!("Sending {} packets to TCP stream.", packets.len());
+                    client_stream.sent(&tcp.packer.pack_messages(&serialized))
+                }
+                Err(e) => {
+                    error!(
+                        "Error occurred when serializing TCP-packet. Reason: {:?}",
+                        e
+                    );
+                    continue;
+                }
+            }
+        };
 
-                if let Err(e) = client_stream.1.write(&serialized) {
-                    match e.kind() {
-                        io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe => {
-                            network_events.enqueue(NetworkEvent::Disconnected(addr, *client.0))
-                        }
-                        _ => {
-                            error!("Error occurred when sending TCP-packet. Reason: {:?}", e);
-                        }
-                    }
+        if let Err(e) = result {
+            match e {
+                ErrorKind::IoError(ref io_error)
+                    if io_error.kind() == io::ErrorKind::ConnectionReset
+                        || io_error.kind() == io::ErrorKind::BrokenPipe =>
+                {
+                    client_stream.active = false;
+                    network_events.enqueue(NetworkEvent::Disconnected(
+                        addr,
+                        *client.0,
+                        DisconnectReason::ConnectionLost,
+                    ));
+                    disconnected.push((addr, *client.0));
+                }
+                _ => {
+                    error!("Error occurred when sending TCP-packet. Reason: {:?}", e);
                 }
             }
-            Err(e) => {
+        }
+    }
+
+    for (addr, client_id) in disconnected {
+        tcp.drop_stream(addr);
+        postoffice.suspend_client(&client_id);
+    }
+}
+
+/// Sends the outgoing messages of the client followed by a disconnect with the given reason, then
+/// closes its stream and removes it from the `PostOffice`.
+///
+/// Frames that can not be written right away are written by the next calls to
+/// `tcp_server_sent_system`, a server that shuts down keeps sending until `closing_count` is zero
+/// to deliver them.
+pub fn tcp_server_disconnect<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    tcp: &mut TcpListenerResource<S, C>,
+    client_id: ClientId,
+    reason: DisconnectReason,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    network_events: &mut NetworkEventQueue,
+) {
+    let client = match postoffice.client_by_id_mut(&client_id) {
+        Some(client) => client,
+        None => return,
+    };
+
+    let addr = client.addr();
+    let packets = client.drain_outgoing();
+
+    if let Some(mut connection) = tcp.drop_stream(addr) {
+        let result = pack_disconnect(&tcp.packer, &packets, reason.clone()).and_then(|frames| {
+            for frame in frames {
+                connection.writer.push_frame(&frame)?;
+            }
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            error!(
+                "Error occurred when packing TCP disconnect. Reason: {:?}",
+                e
+            );
+        }
+        tcp.close_stream(connection);
+    }
+
+    if let Err(e) = postoffice.remove_client(&client_id) {
+        error!("Error occurred when removing TCP client. Reason: {:?}", e);
+    }
+    network_events.enqueue(NetworkEvent::Disconnected(addr, client_id, reason));
+}
+
+/// Sends the outgoing messages of the postbox followed by a disconnect with the given reason to
+/// the server, then closes the stream.
+pub fn tcp_client_disconnect<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    tcp: &mut TcpClientResource<S, C>,
+    reason: DisconnectReason,
+    postbox: &mut PostBox<
+        transport::ServerToClientMessage<ServerToClientMessage>,
+        transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+    >,
+    network_events: &mut NetworkEventQueue,
+) {
+    if !tcp.is_connected() {
+        return;
+    }
+
+    let packets = postbox.drain_outgoing(|_| true);
+
+    let result = pack_disconnect(&tcp.packer, &packets, reason.clone()).and_then(|frames| {
+        for frame in frames {
+            tcp.writer.push_frame(&frame)?;
+        }
+        close_client_stream(&mut tcp.stream, &mut tcp.writer)
+    });
+
+    if let Err(e) = result {
+        error!("Error occurred when closing TCP stream. Reason: {:?}", e);
+    }
+
+    disconnect_client(tcp, network_events, reason);
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> ServerTransport
+    for TcpListenerResource<S, C>
+{
+    fn accept<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        tcp_connection_listener(self, postoffice, network_events);
+    }
+
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        command_frame: CommandFrame,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let mut recv_buffer = mem::take(&mut self.recv_buffer);
+        tcp_server_receive_system(
+            self,
+            postoffice,
+            command_frame,
+            network_events,
+            &mut recv_buffer,
+        );
+        self.recv_buffer = recv_buffer;
+    }
+
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        tcp_server_sent_system(self, postoffice, network_events);
+    }
+
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        client_id: ClientId,
+        reason: DisconnectReason,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        tcp_server_disconnect(self, client_id, reason, postoffice, network_events);
+    }
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> ClientTransport for TcpClientResource<S, C> {
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        let mut recv_buffer = mem::take(&mut self.recv_buffer);
+        tcp_client_receive_system(self, postbox, network_events, &mut recv_buffer);
+        self.recv_buffer = recv_buffer;
+    }
+
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        tcp_client_sent_system(self, postbox, network_events);
+    }
+
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        reason: DisconnectReason,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        tcp_client_disconnect(self, reason, postbox, network_events);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+}
+
+/// Reads from the stream until it would block and feeds the read bytes into the frame reader.
+fn read_available(
+    stream: &mut TcpStream,
+    reader: &mut FrameReader,
+    recv_buffer: &mut [u8],
+) -> Result<usize, ErrorKind> {
+    let mut received = 0;
+
+    loop {
+        match stream.read(recv_buffer) {
+            Ok(0) if !recv_buffer.is_empty() => {
+                return Err(ErrorKind::IoError(io::Error::new(
+                    io::ErrorKind::ConnectionReset,
+                    "Stream was closed by the peer.",
+                )));
+            }
+            Ok(0) => return Ok(received),
+            Ok(recv_len) => {
+                reader.extend(&recv_buffer[..recv_len]);
+                received += recv_len;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(received),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn disconnect_client<S: SerializationStrategy, C: CompressionStrategy>(
+    tcp: &mut TcpClientResource<S, C>,
+    network_events: &mut NetworkEventQueue,
+    reason: DisconnectReason,
+) {
+    // The local address of a stream that was reset can not always be read.
+    let addr = tcp.addr().unwrap_or(tcp.server_addr);
+    tcp.set_connected(false);
+
+    if let (true, Some(_), Some(reconnect)) = (reason.can_resume(), tcp.session, &tcp.reconnect) {
+        tcp.reconnecting = Some((0, Instant::now() + reconnect.delay(0)));
+    }
+
+    network_events.enqueue(control::disconnected_event(addr, tcp.client_id, reason))
+}
+
+/// Connects to the server again when the next attempt is due, and presents the session token.
+///
+/// The reply of the server is processed by the receive system like the reply to a hello.
+fn try_reconnect<S: SerializationStrategy, C: CompressionStrategy>(
+    tcp: &mut TcpClientResource<S, C>,
+) {
+    let (attempt, next_attempt, session, reconnect) =
+        match (tcp.reconnecting, tcp.session, &tcp.reconnect) {
+            (Some((attempt, next_attempt)), Some(session), Some(reconnect)) => {
+                (attempt, next_attempt, session, reconnect.clone())
+            }
+            _ => return,
+        };
+
+    let now = Instant::now();
+    if now < next_attempt {
+        return;
+    }
+
+    let stream = TcpStream::connect_timeout(&tcp.server_addr, reconnect.connect_timeout)
+        .and_then(|stream| stream.set_nonblocking(true).map(|_| stream));
+
+    match stream {
+        Ok(stream) => {
+            tcp.stream = stream;
+            tcp.reader = FrameReader::default();
+            tcp.writer = FrameWriter::default();
+            tcp.last_received = now;
+            tcp.reconnecting = None;
+            tcp.set_connected(true);
+
+            let result = tcp
+                .packer
+                .pack_control(&ControlPacket::Resume(tcp.handshake.hello(), session))
+                .and_then(|resume| tcp.sent(&resume));
+
+            if let Err(e) = result {
                 error!(
-                    "Error occurred when serializing TCP-packet. Reason: {:?}",
+                    "Error occurred when sending TCP session resume. Reason: {:?}",
                     e
                 );
             }
         }
+        Err(e) => {
+            debug!(
+                "Reconnect attempt {} to {:?} failed: {}",
+                attempt + 1,
+                tcp.server_addr,
+                e
+            );
+
+            tcp.reconnecting = if attempt + 1 < reconnect.max_attempts {
+                Some((attempt + 1, now + reconnect.delay(attempt + 1)))
+            } else {
+                None
+            };
+        }
+    }
+}
+
+/// Returns the frames with the given messages, if any, followed by the disconnect.
+fn pack_disconnect<S: SerializationStrategy, C: CompressionStrategy, T: Serialize>(
+    packer: &Packer<S, C>,
+    packets: &[T],
+    reason: DisconnectReason,
+) -> Result<Vec<Vec<u8>>, ErrorKind> {
+    let mut frames = Vec::new();
+
+    if !packets.is_empty() {
+        let serialized = packer.serialization().serialize(packets)?;
+        frames.push(packer.pack_messages(&serialized));
+    }
+
+    frames.push(packer.pack_control(&ControlPacket::Disconnect(reason))?);
+    Ok(frames)
+}
+
+/// Writes the queued frames of a client that disconnects to the stream and closes it for writing.
+///
+/// The stream is switched to blocking mode, so that the frames are not cut off by a full send
+/// buffer.
+fn close_client_stream(stream: &mut TcpStream, writer: &mut FrameWriter) -> Result<(), ErrorKind> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(CLOSE_TIMEOUT))?;
+    writer.flush(stream)?;
+    stream.shutdown(Shutdown::Write)?;
+    Ok(())
+}
+
+fn handle_client_sent_error<S: SerializationStrategy, C: CompressionStrategy>(
+    tcp: &mut TcpClientResource<S, C>,
+    network_events: &mut NetworkEventQueue,
+    error: ErrorKind,
+) {
+    match error {
+        ErrorKind::IoError(ref e)
+            if e.kind() == io::ErrorKind::ConnectionReset
+                || e.kind() == io::ErrorKind::BrokenPipe =>
+        {
+            disconnect_client(tcp, network_events, DisconnectReason::ConnectionLost)
+        }
+        _ => {
+            error!(
+                "Error occurred when sending TCP-packet. Reason: {:?}",
+                error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, TcpListener, TcpStream},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        error::ErrorKind,
+        event::{NetworkEvent, NetworkEventQueue},
+        transport::{
+            tcp::{
+                tcp_client_receive_system, tcp_client_sent_system, tcp_connection_listener,
+                tcp_server_receive_system, tcp_server_sent_system, ReconnectConfig,
+                TcpClientResource, TcpListenerResource,
+            },
+            Authentication, Authenticator, ClientToServerMessage, ClientTransport,
+            DisconnectReason, HandshakeConfig, HeartbeatConfig, Packer, PostBox, PostOffice,
+            RejectReason, ServerToClientMessage, ServerTransport,
+        },
+    };
+
+    #[test]
+    fn batches_survive_small_receive_buffers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut listener = TcpListenerResource::new(Some(listener));
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut server_events = NetworkEventQueue::new();
+
+        let mut client = TcpClientResource::new(server_addr).unwrap();
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        let mut client_events = NetworkEventQueue::new();
+
+        while postoffice.client_count() == 0 {
+            tcp_connection_listener(&mut listener, &mut postoffice, &mut server_events);
+        }
+
+        // Two separate batches end up coalesced in the stream.
+        for batch in 0..2 {
+            for i in 0..10 {
+                postbox.send(ClientToServerMessage::Message(batch * 10 + i));
+            }
+            tcp_client_sent_system(&mut client, &mut postbox, &mut client_events);
+        }
+
+        // A receive buffer smaller than a single frame forces frames to be split.
+        let mut recv_buffer = vec![0; 3];
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            tcp_server_receive_system(
+                &mut listener,
+                &mut postoffice,
+                0,
+                &mut server_events,
+                &mut recv_buffer,
+            );
+
+            let (_, client) = postoffice.clients_mut().next().unwrap();
+            received.extend(client.postbox_mut().drain_inbox(|_| true));
+
+            if received.len() == 20 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(received, (0..20).collect::<Vec<u32>>());
+
+        postoffice.broadcast(ServerToClientMessage::Message(1));
+        postoffice.broadcast(ServerToClientMessage::Message(2));
+        tcp_server_sent_system(&mut listener, &mut postoffice, &mut server_events);
+
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            tcp_client_receive_system(
+                &mut client,
+                &mut postbox,
+                &mut client_events,
+                &mut recv_buffer,
+            );
+            received.extend(postbox.drain_inbox(|_| true));
+
+            if received.len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(received.len(), 2);
+        assert!(client.is_connected());
+    }
+
+    #[test]
+    fn transport_traits_exchange_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut server = TcpListenerResource::new(Some(listener));
+        let mut client = TcpClientResource::new(server_addr).unwrap();
+
+        exchange_messages(&mut server, &mut client);
+    }
+
+    #[test]
+    fn client_times_out_when_server_is_silent() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut server = TcpListenerResource::new(Some(listener));
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut client = TcpClientResource::new(server_addr).unwrap();
+        client.set_heartbeat(HeartbeatConfig {
+            interval: Duration::from_millis(5),
+            timeout: Duration::from_millis(30),
+        });
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        let mut events = NetworkEventQueue::new();
+
+        while postoffice.client_count() == 0 {
+            server.accept(&mut postoffice, &mut events);
+        }
+
+        // The server never ticks its send system, so no heartbeats reach the client.
+        let mut recv_buffer = vec![0; 64];
+        for _ in 0..100 {
+            tcp_client_receive_system(&mut client, &mut postbox, &mut events, &mut recv_buffer);
+
+            if !client.is_connected() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(!client.is_connected());
+        let timed_out = std::iter::from_fn(|| events.dequeue()).any(|event| {
+            matches!(
+                event,
+                NetworkEvent::Disconnected(_, _, DisconnectReason::Timeout)
+            )
+        });
+        assert!(timed_out);
+    }
+
+    #[test]
+    fn server_shutdown_reaches_client_after_pending_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut server = TcpListenerResource::new(Some(listener));
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut client = TcpClientResource::new(server_addr).unwrap();
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        let mut server_events = NetworkEventQueue::new();
+        let mut client_events = NetworkEventQueue::new();
+
+        while postoffice.client_count() == 0 {
+            server.accept(&mut postoffice, &mut server_events);
+        }
+
+        postoffice.broadcast(ServerToClientMessage::Message(9));
+        server.shutdown(&mut postoffice, &mut server_events);
+        assert_eq!(postoffice.client_count(), 0);
+
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            ClientTransport::receive(&mut client, &mut postbox, &mut client_events);
+            received.extend(postbox.drain_inbox(|_| true));
+
+            if !client.is_connected() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(!client.is_connected());
+        match received.first() {
+            Some(ServerToClientMessage::Message(9)) => {}
+            _ => panic!("Expected the message sent before the shutdown."),
+        }
+        let disconnected =
+            std::iter::from_fn(|| client_events.dequeue()).find_map(|event| match event {
+                NetworkEvent::Disconnected(_, client_id, reason) => Some((client_id, reason)),
+                _ => None,
+            });
+        assert_eq!(
+            disconnected,
+            Some((
+                client.client_id().unwrap(),
+                DisconnectReason::ServerShutdown
+            ))
+        );
+    }
+
+    #[test]
+    fn client_resumes_session_after_connection_loss() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut server = TcpListenerResource::new(Some(listener));
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        postoffice.set_session_grace(Duration::from_secs(10));
+        let mut client = TcpClientResource::new(server_addr).unwrap();
+        client.set_reconnect(Some(ReconnectConfig {
+            initial_delay: Duration::from_millis(5),
+            ..ReconnectConfig::default()
+        }));
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        let mut server_events = NetworkEventQueue::new();
+        let mut client_events = NetworkEventQueue::new();
+
+        while client.client_id().is_none() {
+            server.accept(&mut postoffice, &mut server_events);
+            ClientTransport::receive(&mut client, &mut postbox, &mut client_events);
+            thread::sleep(Duration::from_millis(5));
+        }
+        let client_id = client.client_id().unwrap();
+
+        // Closing the stream on the server side is noticed by the client as a lost connection.
+        server.drop_stream(client.addr().unwrap());
+
+        let mut reconnected = false;
+        for _ in 0..200 {
+            server.accept(&mut postoffice, &mut server_events);
+            ClientTransport::receive(&mut client, &mut postbox, &mut client_events);
+
+            reconnected = std::iter::from_fn(|| client_events.dequeue())
+                .any(|event| matches!(event, NetworkEvent::Reconnected(_, id) if id == client_id));
+            if reconnected {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(reconnected);
+        assert!(client.is_connected());
+        assert_eq!(client.client_id(), Some(client_id));
+        assert_eq!(postoffice.client_count(), 1);
+        assert!(postoffice
+            .client_by_addr_mut(&client.addr().unwrap())
+            .is_some());
+        assert!(std::iter::from_fn(|| server_events.dequeue())
+            .any(|event| matches!(event, NetworkEvent::Reconnected(_, id) if id == client_id)));
+    }
+
+    #[test]
+    fn sending_to_client_without_stream_disconnects_it() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut server = TcpListenerResource::new(Some(listener));
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let client = TcpClientResource::new(server_addr).unwrap();
+        let mut events = NetworkEventQueue::new();
+
+        while postoffice.client_count() == 0 {
+            server.accept(&mut postoffice, &mut events);
+        }
+
+        server.drop_stream(client.addr().unwrap());
+        postoffice.broadcast(ServerToClientMessage::Message(1));
+        tcp_server_sent_system(&mut server, &mut postoffice, &mut events);
+
+        assert_eq!(postoffice.client_count(), 0);
+        let events = std::iter::from_fn(|| events.dequeue()).collect::<Vec<_>>();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            NetworkEvent::Error(_, ErrorKind::ConnectionNotFound(_))
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            NetworkEvent::Disconnected(_, _, DisconnectReason::ConnectionLost)
+        )));
+    }
+
+    #[test]
+    fn rejected_stream_is_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut server: TcpListenerResource = TcpListenerResource::with_handshake(
+            Some(listener),
+            HandshakeConfig::new("server"),
+            Packer::default(),
+        );
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+        let _client: TcpClientResource = TcpClientResource::with_handshake(
+            server_addr,
+            HandshakeConfig::new("client"),
+            Packer::default(),
+        )
+        .unwrap();
+
+        let mut rejected = false;
+        for _ in 0..100 {
+            tcp_connection_listener(&mut server, &mut postoffice, &mut events);
+            rejected = std::iter::from_fn(|| events.dequeue())
+                .any(|event| matches!(event, NetworkEvent::Rejected(_, _)));
+
+            if rejected {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(rejected);
+        assert_eq!(server.iter().count(), 0);
+        assert_eq!(postoffice.client_count(), 0);
+    }
+
+    #[test]
+    fn stream_without_hello_is_closed_after_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut server: TcpListenerResource = TcpListenerResource::with_handshake(
+            Some(listener),
+            HandshakeConfig {
+                timeout: Duration::from_millis(20),
+                ..HandshakeConfig::default()
+            },
+            Packer::default(),
+        );
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+        let _stream = TcpStream::connect(server_addr).unwrap();
+
+        while server.iter().count() == 0 {
+            tcp_connection_listener(&mut server, &mut postoffice, &mut events);
+        }
+
+        thread::sleep(Duration::from_millis(30));
+        tcp_connection_listener(&mut server, &mut postoffice, &mut events);
+
+        assert_eq!(server.iter().count(), 0);
+    }
+
+    #[test]
+    fn pending_authentication_is_rejected_after_timeout() {
+        struct Pending;
+
+        impl Authenticator for Pending {
+            fn authenticate(&mut self, _addr: SocketAddr, _payload: &[u8]) -> Authentication {
+                Authentication::Defer
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let handshake = HandshakeConfig {
+            timeout: Duration::from_millis(50),
+            ..HandshakeConfig::default()
+        };
+        let mut server: TcpListenerResource =
+            TcpListenerResource::with_handshake(Some(listener), handshake, Packer::default());
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        postoffice.set_authenticator(Pending);
+        let mut events = NetworkEventQueue::new();
+        let _client: TcpClientResource = TcpClientResource::new(server_addr).unwrap();
+
+        let mut rejected = false;
+        for _ in 0..100 {
+            tcp_connection_listener(&mut server, &mut postoffice, &mut events);
+            rejected = std::iter::from_fn(|| events.dequeue()).any(|event| {
+                matches!(
+                    event,
+                    NetworkEvent::Rejected(_, RejectReason::AuthenticationTimeout)
+                )
+            });
+
+            if rejected {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert!(rejected);
+        assert_eq!(server.iter().count(), 0);
+        assert_eq!(postoffice.client_count(), 0);
+    }
+
+    #[test]
+    fn disconnect_of_slow_peer_does_not_block() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let server_addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut server = TcpListenerResource::new(Some(listener));
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut events = NetworkEventQueue::new();
+        let mut client: TcpClientResource = TcpClientResource::new(server_addr).unwrap();
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        let mut client_events = NetworkEventQueue::new();
+
+        while postoffice.client_count() == 0 {
+            server.accept(&mut postoffice, &mut events);
+        }
+        let client_id = *postoffice.clients().next().unwrap().0;
+
+        // The client does not read, until the send buffers are full. The messages are random so
+        // that they are not compressed.
+        let mut value = 1u32;
+        for _ in 0..500 {
+            for _ in 0..20_000 {
+                value = value.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                postoffice.broadcast(ServerToClientMessage::Message(value));
+            }
+            server.send(&mut postoffice, &mut events);
+
+            if server
+                .iter()
+                .any(|(_, connection)| !connection.writer.is_empty())
+            {
+                break;
+            }
+        }
+
+        let started = Instant::now();
+        server.disconnect(
+            client_id,
+            DisconnectReason::Kicked,
+            &mut postoffice,
+            &mut events,
+        );
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert_eq!(server.closing_count(), 1);
+
+        let mut kicked = false;
+        for _ in 0..1000 {
+            ClientTransport::receive(&mut client, &mut postbox, &mut client_events);
+            postbox.drain_inbox(|_| true);
+            server.send(&mut postoffice, &mut events);
+
+            kicked |= std::iter::from_fn(|| client_events.dequeue()).any(|event| {
+                matches!(
+                    event,
+                    NetworkEvent::Disconnected(_, _, DisconnectReason::Kicked)
+                )
+            });
+
+            if kicked && server.closing_count() == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(kicked);
+        assert_eq!(server.closing_count(), 0);
+    }
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_maximum() {
+        let config = ReconnectConfig {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            ..ReconnectConfig::default()
+        };
+
+        assert_eq!(config.delay(0), Duration::from_millis(100));
+        assert_eq!(config.delay(2), Duration::from_millis(400));
+        assert_eq!(config.delay(3), Duration::from_millis(500));
+        assert_eq!(config.delay(40), Duration::from_millis(500));
+    }
+
+    /// Sends a message in both directions using only the transport traits.
+    fn exchange_messages<S: ServerTransport, C: ClientTransport>(server: &mut S, client: &mut C) {
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        let mut events = NetworkEventQueue::new();
+
+        while postoffice.client_count() == 0 {
+            server.accept(&mut postoffice, &mut events);
+        }
+
+        postbox.send(ClientToServerMessage::Message(5));
+        client.send(&mut postbox, &mut events);
+
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            server.receive(&mut postoffice, 0, &mut events);
+            let (_, remote_client) = postoffice.clients_mut().next().unwrap();
+            received.extend(remote_client.postbox_mut().drain_inbox(|_| true));
+
+            if !received.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(received, vec![5]);
+
+        postoffice.broadcast(ServerToClientMessage::Message(6));
+        server.send(&mut postoffice, &mut events);
+
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            client.receive(&mut postbox, &mut events);
+            received.extend(postbox.drain_inbox(|_| true));
+
+            if !received.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        match received.first() {
+            Some(ServerToClientMessage::Message(6)) => {}
+            _ => panic!("Expected the broadcasted message."),
+        }
+        assert!(client.is_connected());
     }
 }
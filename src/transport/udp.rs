@@ -0,0 +1,1453 @@
+//! UDP transport with reliable and unreliable-sequenced delivery.
+//!
+//! World state updates and their acknowledgements are sent unreliable-sequenced: they are never
+//! resent and an update is dropped by the receiver if a more recent one was already delivered.
+//! All other messages are sent reliably: they are resent until acknowledged and delivered once.
+//! Unlike TCP, a lost world state does not hold back the messages that follow it.
+//!
+//! UDP is connectionless, a client is registered with the `PostOffice` once its hello is received.
+//! The hello is sent reliably in the first datagram of the client. Messages that overtake it are
+//! buffered until the client is accepted.
+//!
+//! The server keeps the connection of a client it disconnects until its reliable messages are
+//! acknowledged, or the close timeout passes. The disconnect is then sent unreliably a few times.
+//! If all of them are lost, the remote notices the disconnect when its heartbeat timeout expires.
+
+use std::{
+    collections::{
+        hash_map::{Iter, IterMut},
+        HashMap, HashSet,
+    },
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error};
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use self::{
+    connection::UdpConnection,
+    packet::{PacketHeader, PacketKind, PACKET_HEADER_SIZE},
+};
+
+use crate::{
+    compression::{CompressionStrategy, DefaultCompression},
+    error::ErrorKind,
+    event::{NetworkEvent, NetworkEventQueue},
+    serialization::{DefaultSerialization, SerializationStrategy},
+    synchronisation::{CommandFrame, NetworkCommand, NetworkMessage},
+    transport,
+    transport::{
+        control, heartbeat, ClientId, ClientTransport, ControlPacket, DisconnectReason,
+        FrameReader, FrameWriter, HandshakeConfig, HandshakeReply, HeartbeatConfig, Packer,
+        Payload, PostBox, PostOffice, ServerTransport, DEFAULT_MAX_FRAME_SIZE, FRAME_HEADER_SIZE,
+    },
+};
+
+mod connection;
+mod packet;
+
+/// The maximum size of a datagram that can be received.
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// The maximum time the reliable messages of a disconnected client are resent for.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The number of times the disconnect is sent to a disconnected client.
+const DISCONNECT_SENDS: u32 = 3;
+
+/// The maximum number of payloads buffered for a remote whose hello is not yet received.
+const MAX_PENDING_PAYLOADS: usize = 64;
+
+/// The maximum number of bytes buffered for all remotes whose hello is not yet received.
+const MAX_PENDING_BYTES: usize = 1024 * 1024;
+
+/// Configuration of the UDP transport.
+#[derive(Clone, Debug)]
+pub struct UdpConfig {
+    /// The time after which a reliable payload that is not acknowledged is resent.
+    pub resend_timeout: Duration,
+    /// Messages are batched into datagrams until this size is reached.
+    /// A single message larger than this size is sent in its own datagram.
+    pub max_packet_size: usize,
+    /// The identity the client introduces itself with, and the server expects from its clients.
+    pub handshake: HandshakeConfig,
+    /// An empty datagram is sent when nothing was sent during the heartbeat interval.
+    pub heartbeat: HeartbeatConfig,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        UdpConfig {
+            resend_timeout: Duration::from_millis(100),
+            max_packet_size: 1200,
+            handshake: HandshakeConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+        }
+    }
+}
+
+pub struct UdpClientResource<
+    S: SerializationStrategy = DefaultSerialization,
+    C: CompressionStrategy = DefaultCompression,
+> {
+    socket: UdpSocket,
+    connection: UdpConnection,
+    connected: bool,
+    client_id: Option<ClientId>,
+    config: UdpConfig,
+    packer: Packer<S, C>,
+    recv_buffer: Vec<u8>,
+}
+
+impl UdpClientResource {
+    /// Returns a new `UdpClientResource` that sends its datagrams to the server at `addr`.
+    pub fn new(addr: SocketAddr) -> Result<UdpClientResource, ErrorKind> {
+        UdpClientResource::with_config(addr, UdpConfig::default(), Packer::default())
+    }
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> UdpClientResource<S, C> {
+    pub fn with_config(
+        addr: SocketAddr,
+        config: UdpConfig,
+        packer: Packer<S, C>,
+    ) -> Result<UdpClientResource<S, C>, ErrorKind> {
+        let local_addr: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+
+        let socket = UdpSocket::bind(local_addr)?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(UdpClientResource {
+            socket,
+            connection: UdpConnection::new(),
+            connected: true,
+            client_id: None,
+            config,
+            packer,
+            recv_buffer: vec![0; MAX_DATAGRAM_SIZE],
+        })
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+    }
+
+    /// Returns the id the server assigned to this client, once the handshake is accepted.
+    pub fn client_id(&self) -> Option<ClientId> {
+        self.client_id
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn connection(&self) -> &UdpConnection {
+        &self.connection
+    }
+
+    pub fn addr(&self) -> Result<SocketAddr, ErrorKind> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    pub fn packer(&self) -> &Packer<S, C> {
+        &self.packer
+    }
+
+    fn disconnected(&mut self, reason: DisconnectReason, network_events: &mut NetworkEventQueue) {
+        self.connected = false;
+
+        if let Ok(addr) = self.addr() {
+            network_events.enqueue(control::disconnected_event(addr, self.client_id, reason));
+        }
+    }
+}
+
+/// The connection of a client the server disconnected, its reliable messages are resent until
+/// they are acknowledged or the close timeout passes, followed by the disconnect.
+struct ClosingConnection {
+    connection: UdpConnection,
+    disconnect: Vec<u8>,
+    disconnects_left: u32,
+    next_disconnect: Instant,
+    deadline: Instant,
+}
+
+impl ClosingConnection {
+    /// Returns the datagrams that are due, and whether the connection is closed.
+    fn flush(&mut self, now: Instant, resend_timeout: Duration) -> (Vec<Vec<u8>>, bool) {
+        if self.connection.pending_reliable() > 0 && now < self.deadline {
+            return (self.connection.resend_due(now, resend_timeout), false);
+        }
+
+        if now < self.next_disconnect {
+            return (Vec::new(), false);
+        }
+
+        self.disconnects_left = self.disconnects_left.saturating_sub(1);
+        self.next_disconnect = now + resend_timeout;
+
+        (
+            vec![self.connection.send_unreliable(&self.disconnect)],
+            self.disconnects_left == 0,
+        )
+    }
+}
+
+pub struct UdpServerResource<
+    S: SerializationStrategy = DefaultSerialization,
+    C: CompressionStrategy = DefaultCompression,
+> {
+    socket: UdpSocket,
+    connections: HashMap<SocketAddr, UdpConnection>,
+    pending_payloads: HashMap<SocketAddr, Vec<Vec<u8>>>,
+    pending_bytes: usize,
+    unaccepted: HashMap<SocketAddr, Instant>,
+    authenticating: HashSet<SocketAddr>,
+    closing: HashMap<SocketAddr, ClosingConnection>,
+    config: UdpConfig,
+    packer: Packer<S, C>,
+    recv_buffer: Vec<u8>,
+}
+
+impl UdpServerResource {
+    /// Returns a new `UdpServerResource` that receives datagrams on the given socket.
+    pub fn new(socket: UdpSocket) -> Result<UdpServerResource, ErrorKind> {
+        UdpServerResource::with_config(socket, UdpConfig::default(), Packer::default())
+    }
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> UdpServerResource<S, C> {
+    pub fn with_config(
+        socket: UdpSocket,
+        config: UdpConfig,
+        packer: Packer<S, C>,
+    ) -> Result<UdpServerResource<S, C>, ErrorKind> {
+        socket.set_nonblocking(true)?;
+
+        Ok(UdpServerResource {
+            socket,
+            connections: HashMap::new(),
+            pending_payloads: HashMap::new(),
+            pending_bytes: 0,
+            unaccepted: HashMap::new(),
+            authenticating: HashSet::new(),
+            closing: HashMap::new(),
+            config,
+            packer,
+            recv_buffer: vec![0; MAX_DATAGRAM_SIZE],
+        })
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn packer(&self) -> &Packer<S, C> {
+        &self.packer
+    }
+
+    /// Returns the connection state of the given remote address.
+    pub fn get_connection(&mut self, addr: SocketAddr) -> Option<&mut UdpConnection> {
+        self.connections.get_mut(&addr)
+    }
+
+    /// Drops the connection state of the given remote address.
+    pub fn drop_connection(&mut self, addr: SocketAddr) -> Option<UdpConnection> {
+        self.take_pending(addr);
+        self.unaccepted.remove(&addr);
+        self.authenticating.remove(&addr);
+        self.connections.remove(&addr)
+    }
+
+    /// Returns an iterator over the connections of this server.
+    pub fn iter(&self) -> Iter<'_, SocketAddr, UdpConnection> {
+        self.connections.iter()
+    }
+
+    /// Returns a mutable iterator over the connections of this server.
+    pub fn iter_mut(&mut self) -> IterMut<'_, SocketAddr, UdpConnection> {
+        self.connections.iter_mut()
+    }
+
+    /// Returns the number of disconnected clients whose reliable messages or disconnect are still
+    /// being sent.
+    pub fn closing_count(&self) -> usize {
+        self.closing.len()
+    }
+
+    /// Resends the reliable messages and the disconnect of the disconnected clients, and drops
+    /// their connections once they are closed. This is called by `udp_server_sent_system`.
+    pub fn flush_closing(&mut self) {
+        let now = Instant::now();
+        let mut closed = Vec::new();
+
+        for (addr, closing) in self.closing.iter_mut() {
+            let (datagrams, is_closed) = closing.flush(now, self.config.resend_timeout);
+
+            for datagram in datagrams {
+                if let Err(e) = self.socket.send_to(&datagram, addr) {
+                    error!("Error occurred when sending UDP-packet. Reason: {:?}", e);
+                }
+            }
+
+            if is_closed {
+                closed.push(*addr);
+            }
+        }
+
+        for addr in closed {
+            self.closing.remove(&addr);
+        }
+    }
+
+    /// Buffers a payload of a remote whose hello is not yet received, returns false if the
+    /// payload is dropped because the buffers are full.
+    fn buffer_pending(&mut self, addr: SocketAddr, payload: Vec<u8>) -> bool {
+        let pending = self.pending_payloads.entry(addr).or_default();
+
+        if pending.len() >= MAX_PENDING_PAYLOADS
+            || self.pending_bytes + payload.len() > MAX_PENDING_BYTES
+        {
+            return false;
+        }
+
+        self.pending_bytes += payload.len();
+        pending.push(payload);
+        true
+    }
+
+    /// Removes the buffered payloads of the given remote.
+    fn take_pending(&mut self, addr: SocketAddr) -> Option<Vec<Vec<u8>>> {
+        let pending = self.pending_payloads.remove(&addr)?;
+        self.pending_bytes -= pending.iter().map(|payload| payload.len()).sum::<usize>();
+        Some(pending)
+    }
+
+    /// Drops the connections of the remotes whose hello was not received within the heartbeat
+    /// timeout after their first datagram, remotes that are authenticating are expired by
+    /// `poll_authentications`.
+    fn drop_unaccepted(&mut self, now: Instant) {
+        let timeout = self.config.heartbeat.timeout;
+        let authenticating = &self.authenticating;
+
+        let expired = self
+            .unaccepted
+            .iter()
+            .filter(|(addr, first_received)| {
+                !authenticating.contains(addr)
+                    && now.saturating_duration_since(**first_received) > timeout
+            })
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+
+        for addr in expired {
+            debug!("Dropped unaccepted UDP socket: {:?}.", addr);
+            self.drop_connection(addr);
+        }
+    }
+}
+
+pub fn udp_client_receive_system<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    udp: &mut UdpClientResource<S, C>,
+    postbox: &mut PostBox<
+        transport::ServerToClientMessage<ServerToClientMessage>,
+        transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+    >,
+    network_events: &mut NetworkEventQueue,
+) {
+    if !udp.connected {
+        return;
+    }
+
+    loop {
+        match udp.socket.recv(&mut udp.recv_buffer) {
+            Ok(recv_len) => {
+                let payload = match udp.connection.receive(&udp.recv_buffer[..recv_len]) {
+                    Ok(Some(payload)) => payload,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!("Error occurred when reading UDP-packet. Reason: {:?}", e);
+                        continue;
+                    }
+                };
+
+                debug!("Received {} bytes from server.", recv_len);
+
+                let unpacked = match udp.packer.unpack_payload(payload) {
+                    Ok(Payload::Messages(unpacked)) => unpacked,
+                    Ok(Payload::Control(ControlPacket::HandshakeReply(reply))) => {
+                        if let Ok(addr) = udp.addr() {
+                            udp.client_id =
+                                control::handle_reply(reply, addr, None, network_events)
+                                    .map(|(client_id, _)| client_id);
+
+                            if udp.client_id.is_none() {
+                                udp.connected = false;
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    Ok(Payload::Control(ControlPacket::Disconnect(reason))) => {
+                        udp.disconnected(reason, network_events);
+                        return;
+                    }
+                    Ok(Payload::Control(control)) => {
+                        debug!("Ignored control packet from server: {:?}", control);
+                        continue;
+                    }
+                    Err(e) => {
+                        if let Ok(addr) = udp.socket.local_addr() {
+                            network_events.enqueue(NetworkEvent::Error(addr, e));
+                        }
+                        continue;
+                    }
+                };
+
+                for message in unpack::<_, transport::ServerToClientMessage<ServerToClientMessage>>(
+                    udp.packer.serialization(),
+                    &unpacked,
+                ) {
+                    postbox.add_to_inbox(message);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(ref e)
+                if e.kind() == io::ErrorKind::ConnectionRefused
+                    || e.kind() == io::ErrorKind::ConnectionReset =>
+            {
+                udp.disconnected(DisconnectReason::ConnectionLost, network_events);
+                return;
+            }
+            Err(e) => {
+                error!("Error occurred when receiving UDP-packet {}", e);
+                break;
+            }
+        }
+    }
+
+    if udp
+        .config
+        .heartbeat
+        .is_timed_out(udp.connection.last_received(), Instant::now())
+    {
+        udp.disconnected(DisconnectReason::Timeout, network_events);
+    }
+}
+
+pub fn udp_client_sent_system<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    udp: &mut UdpClientResource<S, C>,
+    postbox: &mut PostBox<
+        transport::ServerToClientMessage<ServerToClientMessage>,
+        transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+    >,
+    _network_events: &mut NetworkEventQueue,
+) {
+    if !udp.connected {
+        return;
+    }
+
+    let now = Instant::now();
+    let (acknowledgements, packets): (Vec<_>, Vec<_>) = postbox
+        .drain_outgoing(|_| true)
+        .into_iter()
+        .partition(|message| {
+            matches!(message, transport::ClientToServerMessage::Acknowledge(_, _))
+        });
+
+    let mut datagrams = Vec::new();
+
+    // The server only knows about this client after receiving its hello.
+    if !udp.connection.has_sent() {
+        match udp
+            .packer
+            .pack_control(&ControlPacket::Hello(udp.config.handshake.hello()))
+        {
+            Ok(hello) => datagrams.push(udp.connection.send_reliable(hello, now)),
+            Err(e) => {
+                error!("Error occurred when packing UDP hello. Reason: {:?}", e);
+            }
+        }
+    }
+
+    for payload in pack(
+        udp.packer.serialization(),
+        &acknowledgements,
+        udp.config.max_packet_size,
+    ) {
+        datagrams.push(
+            udp.connection
+                .send_unreliable(&udp.packer.pack_messages(&payload)),
+        );
+    }
+
+    for payload in pack(
+        udp.packer.serialization(),
+        &packets,
+        udp.config.max_packet_size,
+    ) {
+        let packed = udp.packer.pack_messages(&payload);
+        datagrams.push(udp.connection.send_reliable(packed, now));
+    }
+
+    datagrams.extend(udp.connection.resend_due(now, udp.config.resend_timeout));
+
+    if datagrams.is_empty()
+        && (udp.connection.ack_required()
+            || udp
+                .config
+                .heartbeat
+                .is_heartbeat_due(udp.connection.last_sent(), now))
+    {
+        datagrams.push(udp.connection.send_ack());
+    }
+
+    if !packets.is_empty() {
+        debug!("Sending {} packets to host.", packets.len());
+    }
+
+    for datagram in datagrams {
+        if let Err(e) = udp.socket.send(&datagram) {
+            error!("Error occurred when sending UDP-packet. Reason: {:?}", e);
+        }
+    }
+}
+
+pub fn udp_server_receive_system<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    udp: &mut UdpServerResource<S, C>,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    command_frame: CommandFrame,
+    network_events: &mut NetworkEventQueue,
+) {
+    poll_authentications(udp, postoffice, command_frame, network_events);
+
+    loop {
+        let (recv_len, addr) = match udp.socket.recv_from(&mut udp.recv_buffer) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => {
+                // Errors of a single remote, such as an unreachable port, should not stop
+                // receiving datagrams from the others.
+                debug!("Error occurred when receiving UDP-packet {}", e);
+                continue;
+            }
+        };
+
+        // A disconnected client only acknowledges its last reliable messages.
+        if let Some(closing) = udp.closing.get_mut(&addr) {
+            if let Err(e) = closing.connection.receive(&udp.recv_buffer[..recv_len]) {
+                debug!("Error occurred when reading UDP-packet. Reason: {:?}", e);
+            }
+            continue;
+        }
+
+        let is_new = !udp.connections.contains_key(&addr);
+        let connection = udp.connections.entry(addr).or_default();
+
+        let payload = match connection.receive(&udp.recv_buffer[..recv_len]) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!(
+                    "Error occurred when reading UDP-packet from {:?}. Reason: {:?}",
+                    addr, e
+                );
+                if is_new {
+                    udp.connections.remove(&addr);
+                }
+                continue;
+            }
+        };
+
+        match postoffice.client_by_addr_mut(&addr) {
+            Some(client) => client.packet_received(),
+            None if is_new => {
+                debug!("Incoming UDP connection: {:?}", addr);
+                // The connection is dropped if the remote does not send a hello in time.
+                udp.unaccepted.insert(addr, Instant::now());
+            }
+            None => {}
+        }
+
+        let payload = match payload {
+            Some(payload) => payload,
+            None => continue,
+        };
+
+        debug!("Received {} bytes from UDP socket: {:?}.", recv_len, addr);
+
+        let payloads = match udp.packer.unpack_payload(payload) {
+            Ok(Payload::Messages(unpacked)) => {
+                if postoffice.client_by_addr_mut(&addr).is_none() {
+                    // Reliable payloads can overtake the hello of the client.
+                    if !udp.buffer_pending(addr, unpacked) {
+                        debug!("Dropped payload of unaccepted UDP socket: {:?}.", addr);
+                    }
+                    continue;
+                }
+                vec![unpacked]
+            }
+            Ok(Payload::Control(ControlPacket::Hello(hello))) => {
+                if udp.authenticating.contains(&addr) {
+                    continue;
+                }
+
+                let reply = match control::accept_hello(
+                    &udp.config.handshake,
+                    &hello,
+                    None,
+                    addr,
+                    postoffice,
+                    network_events,
+                ) {
+                    Some(reply) => reply,
+                    None => {
+                        udp.authenticating.insert(addr);
+                        continue;
+                    }
+                };
+
+                if !reply_to_hello(udp, addr, reply) {
+                    continue;
+                }
+
+                match udp.take_pending(addr) {
+                    Some(pending) => pending,
+                    None => continue,
+                }
+            }
+            Ok(Payload::Control(ControlPacket::Disconnect(reason))) => {
+                if udp.authenticating.contains(&addr) {
+                    control::cancel_authentication(
+                        addr,
+                        reason.clone(),
+                        postoffice,
+                        network_events,
+                    );
+                }
+
+                if let Some(client) = postoffice.client_by_addr_mut(&addr) {
+                    let client_id = client.client_id();
+                    network_events.enqueue(NetworkEvent::Disconnected(addr, client_id, reason));
+                    if let Err(e) = postoffice.remove_client(&client_id) {
+                        error!("Error occurred when removing UDP client. Reason: {:?}", e);
+                    }
+                }
+                udp.drop_connection(addr);
+                continue;
+            }
+            Ok(Payload::Control(control)) => {
+                debug!("Ignored control packet from {:?}: {:?}", addr, control);
+                continue;
+            }
+            Err(e) => {
+                network_events.enqueue(NetworkEvent::Error(addr, e));
+                continue;
+            }
+        };
+
+        deliver_payloads(
+            udp.packer.serialization(),
+            postoffice,
+            addr,
+            payloads,
+            command_frame,
+        );
+    }
+
+    udp.drop_unaccepted(Instant::now());
+
+    for addr in
+        heartbeat::disconnect_idle_clients(&udp.config.heartbeat, postoffice, network_events)
+    {
+        udp.drop_connection(addr);
+    }
+}
+
+/// Polls the deferred authentication of the remotes, and delivers the payloads that were
+/// buffered for the accepted ones.
+///
+/// Remotes that are not authenticated within the handshake timeout after their first datagram
+/// are rejected.
+fn poll_authentications<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    udp: &mut UdpServerResource<S, C>,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    command_frame: CommandFrame,
+    network_events: &mut NetworkEventQueue,
+) {
+    let now = Instant::now();
+    let authenticating = udp.authenticating.iter().copied().collect::<Vec<_>>();
+
+    for addr in authenticating {
+        let timed_out = matches!(udp.unaccepted.get(&addr), Some(first_received)
+            if now.saturating_duration_since(*first_received) > udp.config.handshake.timeout);
+
+        let reply = match control::poll_authentication(addr, postoffice, network_events) {
+            Some(reply) => reply,
+            None if timed_out => {
+                debug!("UDP socket {:?} was not authenticated in time.", addr);
+                control::expire_authentication(addr, postoffice, network_events)
+            }
+            None => continue,
+        };
+
+        udp.authenticating.remove(&addr);
+
+        if !reply_to_hello(udp, addr, reply) {
+            continue;
+        }
+
+        if let Some(pending) = udp.take_pending(addr) {
+            deliver_payloads(
+                udp.packer.serialization(),
+                postoffice,
+                addr,
+                pending,
+                command_frame,
+            );
+        }
+    }
+}
+
+fn deliver_payloads<
+    S: SerializationStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    serialization: &S,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    addr: SocketAddr,
+    payloads: Vec<Vec<u8>>,
+    command_frame: CommandFrame,
+) {
+    let client = match postoffice.client_by_addr_mut(&addr) {
+        Some(client) => client,
+        None => return,
+    };
+
+    for unpacked in payloads {
+        for message in unpack::<
+            _,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >(serialization, &unpacked)
+        {
+            client.add_received_message(message, command_frame);
+        }
+    }
+}
+
+/// Sends the handshake reply to the given remote, returns whether the remote was accepted.
+///
+/// An acceptance is sent reliably. A rejection is sent once, after which the connection is
+/// dropped.
+fn reply_to_hello<S: SerializationStrategy, C: CompressionStrategy>(
+    udp: &mut UdpServerResource<S, C>,
+    addr: SocketAddr,
+    reply: HandshakeReply,
+) -> bool {
+    let accepted = matches!(reply, HandshakeReply::Accepted(..));
+
+    let packet = match udp
+        .packer
+        .pack_control(&ControlPacket::HandshakeReply(reply))
+    {
+        Ok(packet) => packet,
+        Err(e) => {
+            error!(
+                "Error occurred when packing UDP handshake reply. Reason: {:?}",
+                e
+            );
+            return accepted;
+        }
+    };
+
+    if let Some(connection) = udp.connections.get_mut(&addr) {
+        let datagram = if accepted {
+            connection.send_reliable(packet, Instant::now())
+        } else {
+            connection.send_unreliable(&packet)
+        };
+
+        if let Err(e) = udp.socket.send_to(&datagram, addr) {
+            error!("Error occurred when sending UDP-packet. Reason: {:?}", e);
+        }
+    }
+
+    if accepted {
+        udp.unaccepted.remove(&addr);
+    } else {
+        udp.drop_connection(addr);
+    }
+
+    accepted
+}
+
+pub fn udp_server_sent_system<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    udp: &mut UdpServerResource<S, C>,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    _network_events: &mut NetworkEventQueue,
+) {
+    udp.flush_closing();
+
+    let now = Instant::now();
+
+    for (_, client) in postoffice.clients_mut() {
+        let addr = client.addr();
+
+        let connection = match udp.connections.get_mut(&addr) {
+            Some(connection) => connection,
+            None => continue,
+        };
+
+        let messages = client.drain_outgoing();
+
+        if !messages.is_empty() {
+            debug!(
+                "Sending {} packets to UDP socket: {:?}.",
+                messages.len(),
+                addr
+            );
+        }
+
+        let datagrams = server_datagrams(&udp.packer, &udp.config, connection, messages, now);
+
+        for datagram in datagrams {
+            if let Err(e) = udp.socket.send_to(&datagram, addr) {
+                error!("Error occurred when sending UDP-packet. Reason: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Returns the datagrams with the messages for a client, and the reliable payloads that are due
+/// for resending.
+fn server_datagrams<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+>(
+    packer: &Packer<S, C>,
+    config: &UdpConfig,
+    connection: &mut UdpConnection,
+    messages: Vec<transport::ServerToClientMessage<ServerToClientMessage>>,
+    now: Instant,
+) -> Vec<Vec<u8>> {
+    let (state_updates, packets): (Vec<_>, Vec<_>) = messages
+        .into_iter()
+        .partition(|message| matches!(message, transport::ServerToClientMessage::StateUpdate(_)));
+
+    let mut datagrams = Vec::new();
+
+    for payload in pack(
+        packer.serialization(),
+        &state_updates,
+        config.max_packet_size,
+    ) {
+        datagrams.push(connection.send_unreliable(&packer.pack_messages(&payload)));
+    }
+
+    for payload in pack(packer.serialization(), &packets, config.max_packet_size) {
+        datagrams.push(connection.send_reliable(packer.pack_messages(&payload), now));
+    }
+
+    datagrams.extend(connection.resend_due(now, config.resend_timeout));
+
+    if datagrams.is_empty()
+        && (connection.ack_required()
+            || config
+                .heartbeat
+                .is_heartbeat_due(connection.last_sent(), now))
+    {
+        datagrams.push(connection.send_ack());
+    }
+
+    datagrams
+}
+
+/// Sends the outgoing messages of the client followed by a disconnect with the given reason, and
+/// removes the client from the `PostOffice`.
+///
+/// The connection is kept until the reliable messages are acknowledged or the close timeout
+/// passes, after which the disconnect is sent a few times by the next calls to
+/// `udp_server_sent_system`.
+pub fn udp_server_disconnect<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    udp: &mut UdpServerResource<S, C>,
+    client_id: ClientId,
+    reason: DisconnectReason,
+    postoffice: &mut PostOffice<
+        ServerToClientMessage,
+        ClientToServerMessage,
+        ClientToServerCommand,
+    >,
+    network_events: &mut NetworkEventQueue,
+) {
+    let client = match postoffice.client_by_id_mut(&client_id) {
+        Some(client) => client,
+        None => return,
+    };
+
+    let addr = client.addr();
+    let messages = client.drain_outgoing();
+
+    if let Some(mut connection) = udp.drop_connection(addr) {
+        let now = Instant::now();
+        let datagrams = server_datagrams(&udp.packer, &udp.config, &mut connection, messages, now);
+
+        for datagram in datagrams {
+            if let Err(e) = udp.socket.send_to(&datagram, addr) {
+                error!("Error occurred when sending UDP-packet. Reason: {:?}", e);
+            }
+        }
+
+        match udp
+            .packer
+            .pack_control(&ControlPacket::Disconnect(reason.clone()))
+        {
+            Ok(disconnect) => {
+                udp.closing.insert(
+                    addr,
+                    ClosingConnection {
+                        connection,
+                        disconnect,
+                        disconnects_left: DISCONNECT_SENDS,
+                        next_disconnect: now,
+                        deadline: now + CLOSE_TIMEOUT,
+                    },
+                );
+                udp.flush_closing();
+            }
+            Err(e) => {
+                error!(
+                    "Error occurred when packing UDP disconnect. Reason: {:?}",
+                    e
+                );
+            }
+        }
+    }
+
+    if let Err(e) = postoffice.remove_client(&client_id) {
+        error!("Error occurred when removing UDP client. Reason: {:?}", e);
+    }
+    network_events.enqueue(NetworkEvent::Disconnected(addr, client_id, reason));
+}
+
+/// Sends the outgoing messages of the postbox followed by a disconnect with the given reason to
+/// the server.
+pub fn udp_client_disconnect<
+    S: SerializationStrategy,
+    C: CompressionStrategy,
+    ServerToClientMessage: NetworkMessage,
+    ClientToServerMessage: NetworkMessage,
+    ClientToServerCommand: NetworkCommand,
+>(
+    udp: &mut UdpClientResource<S, C>,
+    reason: DisconnectReason,
+    postbox: &mut PostBox<
+        transport::ServerToClientMessage<ServerToClientMessage>,
+        transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+    >,
+    network_events: &mut NetworkEventQueue,
+) {
+    if !udp.connected {
+        return;
+    }
+
+    udp_client_sent_system(udp, postbox, network_events);
+
+    match udp
+        .packer
+        .pack_control(&ControlPacket::Disconnect(reason.clone()))
+    {
+        Ok(packet) => {
+            let datagram = udp.connection.send_unreliable(&packet);
+
+            if let Err(e) = udp.socket.send(&datagram) {
+                error!("Error occurred when sending UDP-packet. Reason: {:?}", e);
+            }
+        }
+        Err(e) => {
+            error!(
+                "Error occurred when packing UDP disconnect. Reason: {:?}",
+                e
+            );
+        }
+    }
+
+    udp.disconnected(reason, network_events);
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> ServerTransport for UdpServerResource<S, C> {
+    /// UDP is connectionless, clients are registered when their hello is received.
+    fn accept<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        _postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        _network_events: &mut NetworkEventQueue,
+    ) {
+    }
+
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        command_frame: CommandFrame,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        udp_server_receive_system(self, postoffice, command_frame, network_events);
+    }
+
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        udp_server_sent_system(self, postoffice, network_events);
+    }
+
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        client_id: ClientId,
+        reason: DisconnectReason,
+        postoffice: &mut PostOffice<
+            ServerToClientMessage,
+            ClientToServerMessage,
+            ClientToServerCommand,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        udp_server_disconnect(self, client_id, reason, postoffice, network_events);
+    }
+}
+
+impl<S: SerializationStrategy, C: CompressionStrategy> ClientTransport for UdpClientResource<S, C> {
+    fn receive<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        udp_client_receive_system(self, postbox, network_events);
+    }
+
+    fn send<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        udp_client_sent_system(self, postbox, network_events);
+    }
+
+    fn disconnect<
+        ServerToClientMessage: NetworkMessage,
+        ClientToServerMessage: NetworkMessage,
+        ClientToServerCommand: NetworkCommand,
+    >(
+        &mut self,
+        reason: DisconnectReason,
+        postbox: &mut PostBox<
+            transport::ServerToClientMessage<ServerToClientMessage>,
+            transport::ClientToServerMessage<ClientToServerMessage, ClientToServerCommand>,
+        >,
+        network_events: &mut NetworkEventQueue,
+    ) {
+        udp_client_disconnect(self, reason, postbox, network_events);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+}
+
+/// Serializes the messages and packs them as length-prefixed frames into payloads of at most
+/// `max_packet_size` bytes.
+fn pack<S: SerializationStrategy, T: Serialize>(
+    serialization: &S,
+    messages: &[T],
+    max_packet_size: usize,
+) -> Vec<Vec<u8>> {
+    let mut payloads = Vec::new();
+    let mut writer = FrameWriter::new(MAX_DATAGRAM_SIZE);
+
+    for message in messages {
+        let serialized = match serialization.serialize(message) {
+            Ok(serialized) => serialized,
+            Err(e) => {
+                error!(
+                    "Error occurred when serializing UDP-packet. Reason: {:?}",
+                    e
+                );
+                continue;
+            }
+        };
+
+        if !writer.is_empty()
+            && writer.pending() + FRAME_HEADER_SIZE + serialized.len() > max_packet_size
+        {
+            payloads.push(take_payload(&mut writer));
+        }
+
+        if let Err(e) = writer.push_frame(&serialized) {
+            error!("Error occurred when packing UDP-packet. Reason: {:?}", e);
+        }
+    }
+
+    if !writer.is_empty() {
+        payloads.push(take_payload(&mut writer));
+    }
+
+    payloads
+}
+
+fn take_payload(writer: &mut FrameWriter) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(writer.pending());
+    writer
+        .flush(&mut payload)
+        .expect("Writing to a vector can not fail.");
+    payload
+}
+
+/// Deserializes the length-prefixed messages of a payload.
+fn unpack<S: SerializationStrategy, T: DeserializeOwned>(
+    serialization: &S,
+    payload: &[u8],
+) -> Vec<T> {
+    let mut reader = FrameReader::new(DEFAULT_MAX_FRAME_SIZE);
+    reader.extend(payload);
+
+    let mut messages = Vec::new();
+
+    loop {
+        match reader.next_frame() {
+            Ok(Some(frame)) => match serialization.deserialize::<T>(&frame) {
+                Ok(message) => messages.push(message),
+                Err(e) => {
+                    error!(
+                        "Error occurred when deserializing UDP-packet. Reason: {:?}",
+                        e
+                    );
+                }
+            },
+            Ok(None) => break,
+            Err(e) => {
+                error!("Error occurred when unpacking UDP-packet. Reason: {:?}", e);
+                break;
+            }
+        }
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, thread, time::Duration};
+
+    use crate::{
+        event::{NetworkEvent, NetworkEventQueue},
+        serialization::DefaultSerialization,
+        synchronisation::WorldState,
+        transport::{
+            udp::{pack, unpack, UdpClientResource, UdpConfig, UdpConnection, UdpServerResource},
+            ClientToServerMessage, ClientTransport, DisconnectReason, HeartbeatConfig, Packer,
+            PostBox, PostOffice, ServerToClientMessage, ServerTransport,
+        },
+    };
+
+    #[test]
+    fn pack_splits_messages_over_payloads() {
+        let messages = (0..100).collect::<Vec<u32>>();
+
+        let payloads = pack(&DefaultSerialization::default(), &messages, 64);
+        assert!(payloads.len() > 1);
+        assert!(payloads.iter().all(|payload| payload.len() <= 64));
+
+        let unpacked = payloads
+            .iter()
+            .flat_map(|payload| unpack::<_, u32>(&DefaultSerialization::default(), payload))
+            .collect::<Vec<u32>>();
+        assert_eq!(unpacked, messages);
+    }
+
+    #[test]
+    fn first_datagram_registers_client() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = socket.local_addr().unwrap();
+
+        let mut server = UdpServerResource::new(socket).unwrap();
+        let mut client = UdpClientResource::new(server_addr).unwrap();
+
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        let mut server_events = NetworkEventQueue::new();
+        let mut client_events = NetworkEventQueue::new();
+
+        postbox.send(ClientToServerMessage::Message(7));
+        client.send(&mut postbox, &mut client_events);
+
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            server.receive(&mut postoffice, 0, &mut server_events);
+
+            if let Some((_, remote_client)) = postoffice.clients_mut().next() {
+                received.extend(remote_client.postbox_mut().drain_inbox(|_| true));
+            }
+
+            if !received.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(received, vec![7]);
+        assert_eq!(postoffice.client_count(), 1);
+        match server_events.dequeue() {
+            Some(NetworkEvent::Connected(_)) => {}
+            _ => panic!("Expected a connected event."),
+        }
+
+        postoffice.broadcast(ServerToClientMessage::StateUpdate(WorldState::new(3)));
+        postoffice.broadcast(ServerToClientMessage::Message(8));
+        server.send(&mut postoffice, &mut server_events);
+
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            client.receive(&mut postbox, &mut client_events);
+            received.extend(postbox.drain_inbox(|_| true));
+
+            if received.len() == 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(received.len(), 2);
+        assert!(client.client_id().is_some());
+        assert!(received.iter().any(|message| match message {
+            ServerToClientMessage::StateUpdate(state) => state.command_frame == 3,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn kicked_client_receives_lost_messages_and_reason() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = socket.local_addr().unwrap();
+
+        let config = UdpConfig {
+            resend_timeout: Duration::from_millis(10),
+            ..UdpConfig::default()
+        };
+        let mut server: UdpServerResource =
+            UdpServerResource::with_config(socket, config.clone(), Packer::default()).unwrap();
+        let mut client: UdpClientResource =
+            UdpClientResource::with_config(server_addr, config, Packer::default()).unwrap();
+
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut postbox =
+            PostBox::<ServerToClientMessage<u32>, ClientToServerMessage<u32, u32>>::new();
+        let mut server_events = NetworkEventQueue::new();
+        let mut client_events = NetworkEventQueue::new();
+
+        client.send(&mut postbox, &mut client_events);
+        for _ in 0..100 {
+            server.receive(&mut postoffice, 0, &mut server_events);
+            server.send(&mut postoffice, &mut server_events);
+            client.receive(&mut postbox, &mut client_events);
+
+            if client.client_id().is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        let client_id = *postoffice.clients().next().unwrap().0;
+
+        postoffice.broadcast(ServerToClientMessage::Message(8));
+        server.disconnect(
+            client_id,
+            DisconnectReason::Kicked,
+            &mut postoffice,
+            &mut server_events,
+        );
+        assert_eq!(server.closing_count(), 1);
+
+        // The message and the first disconnect are lost.
+        thread::sleep(Duration::from_millis(5));
+        while client.socket.recv(&mut client.recv_buffer).is_ok() {}
+
+        let mut received = Vec::new();
+        let mut kicked = false;
+        for _ in 0..100 {
+            thread::sleep(Duration::from_millis(5));
+            server.receive(&mut postoffice, 0, &mut server_events);
+            server.send(&mut postoffice, &mut server_events);
+            client.receive(&mut postbox, &mut client_events);
+            client.send(&mut postbox, &mut client_events);
+
+            received.extend(postbox.drain_inbox(|_| true));
+            kicked |= std::iter::from_fn(|| client_events.dequeue()).any(|event| {
+                matches!(
+                    event,
+                    NetworkEvent::Disconnected(_, _, DisconnectReason::Kicked)
+                )
+            });
+
+            if kicked {
+                break;
+            }
+        }
+
+        assert!(kicked);
+        assert!(matches!(
+            received.as_slice(),
+            [ServerToClientMessage::Message(8)]
+        ));
+    }
+
+    #[test]
+    fn remote_without_hello_is_dropped_after_timeout() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = socket.local_addr().unwrap();
+
+        let config = UdpConfig {
+            heartbeat: HeartbeatConfig {
+                interval: Duration::from_millis(10),
+                timeout: Duration::from_millis(50),
+            },
+            ..UdpConfig::default()
+        };
+        let mut server: UdpServerResource =
+            UdpServerResource::with_config(socket, config, Packer::default()).unwrap();
+        let mut postoffice = PostOffice::<u32, u32, u32>::new();
+        let mut server_events = NetworkEventQueue::new();
+
+        // A remote that sends messages without ever introducing itself.
+        let remote = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut connection = UdpConnection::new();
+        let payload = pack(&DefaultSerialization::default(), &[7u32], 1200).remove(0);
+        let datagram = connection.send_unreliable(&server.packer().pack_messages(&payload));
+        remote.send_to(&datagram, server_addr).unwrap();
+
+        for _ in 0..100 {
+            server.receive(&mut postoffice, 0, &mut server_events);
+
+            if server.iter().count() == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(server.iter().count(), 1);
+        assert!(server.pending_bytes > 0);
+
+        thread::sleep(Duration::from_millis(60));
+        server.receive(&mut postoffice, 0, &mut server_events);
+
+        assert_eq!(server.iter().count(), 0);
+        assert!(server.pending_payloads.is_empty());
+        assert_eq!(server.pending_bytes, 0);
+        assert_eq!(postoffice.client_count(), 0);
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::ErrorKind,
+    transport::udp::packet::{sequence_greater_than, PacketHeader, PacketKind},
+};
+
+/// The number of sequence numbers remembered for acknowledgement and duplicate detection.
+const SEQUENCE_BUFFER_SIZE: usize = 1024;
+
+/// A fixed size buffer that stores values indexed by a wrapping sequence number.
+///
+/// An entry is overwritten when a sequence number `SEQUENCE_BUFFER_SIZE` further is inserted.
+struct SequenceBuffer<T> {
+    entries: Vec<Option<(u16, T)>>,
+}
+
+impl<T> SequenceBuffer<T> {
+    fn new() -> SequenceBuffer<T> {
+        SequenceBuffer {
+            entries: (0..SEQUENCE_BUFFER_SIZE).map(|_| None).collect(),
+        }
+    }
+
+    fn insert(&mut self, sequence: u16, value: T) {
+        self.entries[sequence as usize % SEQUENCE_BUFFER_SIZE] = Some((sequence, value));
+    }
+
+    fn contains(&self, sequence: u16) -> bool {
+        match &self.entries[sequence as usize % SEQUENCE_BUFFER_SIZE] {
+            Some((stored, _)) => *stored == sequence,
+            None => false,
+        }
+    }
+
+    fn remove(&mut self, sequence: u16) -> Option<T> {
+        let entry = &mut self.entries[sequence as usize % SEQUENCE_BUFFER_SIZE];
+
+        match entry {
+            Some((stored, _)) if *stored == sequence => entry.take().map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+/// A reliable payload that is not yet acknowledged by the remote.
+struct PendingReliable {
+    payload: Vec<u8>,
+    last_sent: Instant,
+}
+
+/// The reliability state of the connection with a single remote.
+///
+/// Every datagram gets a sequence number and acknowledges the datagrams received from the remote.
+/// Reliable payloads are resent with a new sequence number until one of the datagrams carrying
+/// them is acknowledged. Unreliable payloads are never resent and are dropped by the receiver if a
+/// more recent unreliable payload was already delivered.
+pub struct UdpConnection {
+    local_sequence: u16,
+    remote_sequence: u16,
+    has_received: bool,
+    received: SequenceBuffer<()>,
+    latest_sequenced: Option<u16>,
+    next_reliable_id: u16,
+    pending_reliable: HashMap<u16, PendingReliable>,
+    in_flight: SequenceBuffer<u16>,
+    received_reliable: SequenceBuffer<()>,
+    ack_required: bool,
+    has_sent: bool,
+    last_sent: Instant,
+    last_received: Instant,
+}
+
+impl UdpConnection {
+    pub fn new() -> UdpConnection {
+        UdpConnection {
+            local_sequence: 0,
+            // Acknowledging the sequence number before the first one acknowledges nothing.
+            remote_sequence: u16::MAX,
+            has_received: false,
+            received: SequenceBuffer::new(),
+            latest_sequenced: None,
+            next_reliable_id: 0,
+            pending_reliable: HashMap::new(),
+            in_flight: SequenceBuffer::new(),
+            received_reliable: SequenceBuffer::new(),
+            ack_required: false,
+            has_sent: false,
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+        }
+    }
+
+    /// Returns a datagram with the given payload that will not be resent.
+    pub fn send_unreliable(&mut self, payload: &[u8]) -> Vec<u8> {
+        self.build_datagram(PacketKind::UnreliableSequenced, payload)
+    }
+
+    /// Returns a datagram with the given payload that will be resent until acknowledged.
+    pub fn send_reliable(&mut self, payload: Vec<u8>, now: Instant) -> Vec<u8> {
+        let reliable_id = self.next_reliable_id;
+        self.next_reliable_id = self.next_reliable_id.wrapping_add(1);
+
+        let datagram = self.build_reliable_datagram(reliable_id, &payload);
+        self.pending_reliable.insert(
+            reliable_id,
+            PendingReliable {
+                payload,
+                last_sent: now,
+            },
+        );
+
+        datagram
+    }
+
+    /// Returns a datagram that only acknowledges the received datagrams.
+    pub fn send_ack(&mut self) -> Vec<u8> {
+        self.build_datagram(PacketKind::Ack, &[])
+    }
+
+    /// Returns the datagrams of reliable payloads that are not acknowledged within `timeout`.
+    pub fn resend_due(&mut self, now: Instant, timeout: Duration) -> Vec<Vec<u8>> {
+        let due = self
+            .pending_reliable
+            .iter()
+            .filter(|(_, pending)| now.duration_since(pending.last_sent) >= timeout)
+            .map(|(id, _)| *id)
+            .collect::<Vec<u16>>();
+
+        let mut datagrams = Vec::with_capacity(due.len());
+
+        for reliable_id in due {
+            let payload = match self.pending_reliable.get_mut(&reliable_id) {
+                Some(pending) => {
+                    pending.last_sent = now;
+                    pending.payload.clone()
+                }
+                None => continue,
+            };
+
+            datagrams.push(self.build_reliable_datagram(reliable_id, &payload));
+        }
+
+        datagrams
+    }
+
+    /// Processes a received datagram.
+    ///
+    /// Returns the payload if it should be delivered, `None` if the datagram carries no payload,
+    /// is a duplicate, or is older than an already delivered unreliable payload.
+    pub fn receive<'a>(&mut self, datagram: &'a [u8]) -> Result<Option<&'a [u8]>, ErrorKind> {
+        let (header, payload) = PacketHeader::read(datagram)?;
+
+        self.last_received = Instant::now();
+        self.process_acks(header.ack, header.ack_bits);
+
+        if header.kind != PacketKind::Ack {
+            self.ack_required = true;
+        }
+
+        if self.received.contains(header.sequence) {
+            // The network duplicated this datagram.
+            return Ok(None);
+        }
+
+        self.received.insert(header.sequence, ());
+        if !self.has_received || sequence_greater_than(header.sequence, self.remote_sequence) {
+            self.remote_sequence = header.sequence;
+            self.has_received = true;
+        }
+
+        match header.kind {
+            PacketKind::Ack => Ok(None),
+            PacketKind::UnreliableSequenced => {
+                if let Some(latest) = self.latest_sequenced {
+                    if !sequence_greater_than(header.sequence, latest) {
+                        return Ok(None);
+                    }
+                }
+
+                self.latest_sequenced = Some(header.sequence);
+                Ok(Some(payload))
+            }
+            PacketKind::Reliable(reliable_id) => {
+                if self.received_reliable.contains(reliable_id) {
+                    return Ok(None);
+                }
+
+                self.received_reliable.insert(reliable_id, ());
+                Ok(Some(payload))
+            }
+        }
+    }
+
+    /// Returns true if datagrams were received that have not been acknowledged yet.
+    pub fn ack_required(&self) -> bool {
+        self.ack_required
+    }
+
+    /// Returns true if at least one datagram was sent to the remote.
+    pub fn has_sent(&self) -> bool {
+        self.has_sent
+    }
+
+    /// Returns the time the last datagram was sent to the remote.
+    pub fn last_sent(&self) -> Instant {
+        self.last_sent
+    }
+
+    /// Returns the time the last valid datagram was received from the remote.
+    pub fn last_received(&self) -> Instant {
+        self.last_received
+    }
+
+    /// Returns the number of reliable payloads waiting for acknowledgement.
+    pub fn pending_reliable(&self) -> usize {
+        self.pending_reliable.len()
+    }
+
+    fn process_acks(&mut self, ack: u16, ack_bits: u32) {
+        self.acknowledge(ack);
+
+        for i in 0..32 {
+            if ack_bits & (1 << i) != 0 {
+                self.acknowledge(ack.wrapping_sub(i + 1));
+            }
+        }
+    }
+
+    fn acknowledge(&mut self, sequence: u16) {
+        if let Some(reliable_id) = self.in_flight.remove(sequence) {
+            self.pending_reliable.remove(&reliable_id);
+        }
+    }
+
+    fn ack_bits(&self) -> u32 {
+        let mut ack_bits = 0;
+
+        for i in 0..32 {
+            if self
+                .received
+                .contains(self.remote_sequence.wrapping_sub(i as u16 + 1))
+            {
+                ack_bits |= 1 << i;
+            }
+        }
+
+        ack_bits
+    }
+
+    fn build_reliable_datagram(&mut self, reliable_id: u16, payload: &[u8]) -> Vec<u8> {
+        self.in_flight.insert(self.local_sequence, reliable_id);
+        self.build_datagram(PacketKind::Reliable(reliable_id), payload)
+    }
+
+    fn build_datagram(&mut self, kind: PacketKind, payload: &[u8]) -> Vec<u8> {
+        let header = PacketHeader {
+            sequence: self.local_sequence,
+            ack: self.remote_sequence,
+            ack_bits: self.ack_bits(),
+            kind,
+        };
+
+        self.local_sequence = self.local_sequence.wrapping_add(1);
+        self.ack_required = false;
+        self.has_sent = true;
+        self.last_sent = Instant::now();
+
+        let mut datagram = Vec::with_capacity(payload.len() + 11);
+        header.write(&mut datagram);
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+}
+
+impl Default for UdpConnection {
+    fn default() -> Self {
+        UdpConnection::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::transport::udp::connection::UdpConnection;
+
+    #[test]
+    fn acknowledged_reliable_is_not_resent() {
+        let now = Instant::now();
+        let mut sender = UdpConnection::new();
+        let mut receiver = UdpConnection::new();
+
+        let datagram = sender.send_reliable(b"reliable".to_vec(), now);
+        assert_eq!(receiver.receive(&datagram).unwrap(), Some(&b"reliable"[..]));
+        assert!(receiver.ack_required());
+
+        let ack = receiver.send_ack();
+        assert_eq!(sender.receive(&ack).unwrap(), None);
+
+        assert_eq!(sender.pending_reliable(), 0);
+        assert!(sender
+            .resend_due(now + Duration::from_secs(1), Duration::from_millis(100))
+            .is_empty());
+    }
+
+    #[test]
+    fn lost_reliable_is_resent_and_delivered_once() {
+        let now = Instant::now();
+        let timeout = Duration::from_millis(100);
+        let mut sender = UdpConnection::new();
+        let mut receiver = UdpConnection::new();
+
+        let original = sender.send_reliable(b"reliable".to_vec(), now);
+        assert!(sender.resend_due(now, timeout).is_empty());
+
+        let resent = sender.resend_due(now + timeout, timeout);
+        assert_eq!(resent.len(), 1);
+
+        assert_eq!(
+            receiver.receive(&resent[0]).unwrap(),
+            Some(&b"reliable"[..])
+        );
+        // The original arrives late and should not be delivered a second time.
+        assert_eq!(receiver.receive(&original).unwrap(), None);
+
+        sender.receive(&receiver.send_ack()).unwrap();
+        assert_eq!(sender.pending_reliable(), 0);
+    }
+
+    #[test]
+    fn older_unreliable_is_dropped() {
+        let mut sender = UdpConnection::new();
+        let mut receiver = UdpConnection::new();
+
+        let first = sender.send_unreliable(b"first");
+        let second = sender.send_unreliable(b"second");
+
+        assert_eq!(receiver.receive(&second).unwrap(), Some(&b"second"[..]));
+        assert_eq!(receiver.receive(&first).unwrap(), None);
+    }
+
+    #[test]
+    fn duplicated_datagram_is_dropped() {
+        let mut sender = UdpConnection::new();
+        let mut receiver = UdpConnection::new();
+
+        let datagram = sender.send_unreliable(b"once");
+
+        assert_eq!(receiver.receive(&datagram).unwrap(), Some(&b"once"[..]));
+        assert_eq!(receiver.receive(&datagram).unwrap(), None);
+    }
+
+    #[test]
+    fn ack_bits_acknowledge_previous_datagrams() {
+        let now = Instant::now();
+        let mut sender = UdpConnection::new();
+        let mut receiver = UdpConnection::new();
+
+        let datagrams = (0..5)
+            .map(|i| sender.send_reliable(vec![i], now))
+            .collect::<Vec<Vec<u8>>>();
+
+        // Only the last datagram acknowledges them directly, the others by the bitfield.
+        for datagram in datagrams.iter() {
+            receiver.receive(datagram).unwrap();
+        }
+
+        sender.receive(&receiver.send_ack()).unwrap();
+        assert_eq!(sender.pending_reliable(), 0);
+    }
+}
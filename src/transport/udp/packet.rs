@@ -0,0 +1,147 @@
+use std::convert::TryInto;
+
+use crate::error::ErrorKind;
+
+/// The size of the header without the reliable message id.
+pub const PACKET_HEADER_SIZE: usize = 9;
+
+/// The kind of a datagram, this defines how the receiver treats its payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketKind {
+    /// The datagram only carries acknowledgements and has no payload.
+    Ack,
+    /// The payload is delivered only if it is newer than the last received unreliable payload.
+    UnreliableSequenced,
+    /// The payload is resent until acknowledged and delivered exactly once.
+    /// The value is the id of the reliable message, which stays the same when resent.
+    Reliable(u16),
+}
+
+/// The header that precedes the payload of each datagram.
+///
+/// | Bytes | Description |
+/// | :-- | :-- |
+/// | 0..2 | The sequence number of this datagram. |
+/// | 2..4 | The most recent sequence number received from the remote. |
+/// | 4..8 | A bitfield in which bit `n` acknowledges sequence number `ack - 1 - n`. |
+/// | 8 | The packet kind. |
+/// | 9..11 | The reliable message id, only present for reliable packets. |
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub sequence: u16,
+    pub ack: u16,
+    pub ack_bits: u32,
+    pub kind: PacketKind,
+}
+
+impl PacketHeader {
+    /// Appends the encoded header to the given buffer.
+    pub fn write(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.sequence.to_be_bytes());
+        buffer.extend_from_slice(&self.ack.to_be_bytes());
+        buffer.extend_from_slice(&self.ack_bits.to_be_bytes());
+
+        match self.kind {
+            PacketKind::Ack => buffer.push(0),
+            PacketKind::UnreliableSequenced => buffer.push(1),
+            PacketKind::Reliable(id) => {
+                buffer.push(2);
+                buffer.extend_from_slice(&id.to_be_bytes());
+            }
+        }
+    }
+
+    /// Decodes the header from the given datagram and returns it together with the payload.
+    pub fn read(datagram: &[u8]) -> Result<(PacketHeader, &[u8]), ErrorKind> {
+        if datagram.len() < PACKET_HEADER_SIZE {
+            return Err(ErrorKind::FramingError(format!(
+                "Datagram of {} bytes is smaller than the packet header.",
+                datagram.len()
+            )));
+        }
+
+        let sequence = u16::from_be_bytes(datagram[0..2].try_into().unwrap());
+        let ack = u16::from_be_bytes(datagram[2..4].try_into().unwrap());
+        let ack_bits = u32::from_be_bytes(datagram[4..8].try_into().unwrap());
+
+        let (kind, header_size) = match datagram[8] {
+            0 => (PacketKind::Ack, PACKET_HEADER_SIZE),
+            1 => (PacketKind::UnreliableSequenced, PACKET_HEADER_SIZE),
+            2 if datagram.len() >= PACKET_HEADER_SIZE + 2 => {
+                let id = u16::from_be_bytes(
+                    datagram[PACKET_HEADER_SIZE..PACKET_HEADER_SIZE + 2]
+                        .try_into()
+                        .unwrap(),
+                );
+                (PacketKind::Reliable(id), PACKET_HEADER_SIZE + 2)
+            }
+            kind => {
+                return Err(ErrorKind::FramingError(format!(
+                    "Datagram has an invalid packet kind {}.",
+                    kind
+                )))
+            }
+        };
+
+        Ok((
+            PacketHeader {
+                sequence,
+                ack,
+                ack_bits,
+                kind,
+            },
+            &datagram[header_size..],
+        ))
+    }
+}
+
+/// Returns true if sequence number `s1` is more recent than `s2`, taking wrap around into account.
+pub fn sequence_greater_than(s1: u16, s2: u16) -> bool {
+    ((s1 > s2) && (s1 - s2 <= 32768)) || ((s1 < s2) && (s2 - s1 > 32768))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::udp::packet::{sequence_greater_than, PacketHeader, PacketKind};
+
+    #[test]
+    fn header_round_trips() {
+        for kind in [
+            PacketKind::Ack,
+            PacketKind::UnreliableSequenced,
+            PacketKind::Reliable(513),
+        ]
+        .iter()
+        {
+            let header = PacketHeader {
+                sequence: 65535,
+                ack: 12,
+                ack_bits: 0b1011,
+                kind: *kind,
+            };
+
+            let mut buffer = Vec::new();
+            header.write(&mut buffer);
+            buffer.extend_from_slice(b"payload");
+
+            let (read, payload) = PacketHeader::read(&buffer).unwrap();
+            assert_eq!(read, header);
+            assert_eq!(payload, b"payload");
+        }
+    }
+
+    #[test]
+    fn should_reject_malformed_header() {
+        assert!(PacketHeader::read(&[0, 1, 2]).is_err());
+        assert!(PacketHeader::read(&[0, 0, 0, 0, 0, 0, 0, 0, 9]).is_err());
+        assert!(PacketHeader::read(&[0, 0, 0, 0, 0, 0, 0, 0, 2, 1]).is_err());
+    }
+
+    #[test]
+    fn sequence_comparison_wraps_around() {
+        assert!(sequence_greater_than(1, 0));
+        assert!(!sequence_greater_than(0, 1));
+        assert!(sequence_greater_than(0, 65535));
+        assert!(!sequence_greater_than(65535, 0));
+    }
+}
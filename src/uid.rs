@@ -1,75 +1,503 @@
 //! This module provides code for identifying entities.
 
-use std::{collections::HashMap, hash::Hash, u32};
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    ops::Range,
+    u32,
+};
 
+use serde::{Deserialize, Serialize};
+
+/// An identifier that consists of an index and the generation of that index.
+///
+/// The index is stored in the lower `INDEX_BITS` bits and the generation in the upper bits, so
+/// that an identifier is still sent as a single `u32`.
 pub type Uid = u32;
 
+/// The number of bits of an `Uid` used for the index.
+pub const INDEX_BITS: u32 = 24;
+/// The largest index an `Uid` can have.
+pub const MAX_INDEX: u32 = (1 << INDEX_BITS) - 1;
+/// The largest generation an `Uid` can have, after which the generation wraps around.
+pub const MAX_GENERATION: u32 = u32::MAX >> INDEX_BITS;
+
+/// Returns the `Uid` with the given index and generation.
+pub fn compose(index: u32, generation: u32) -> Uid {
+    debug_assert!(index <= MAX_INDEX && generation <= MAX_GENERATION);
+    (generation << INDEX_BITS) | index
+}
+
+/// Returns the index of the given `Uid`.
+pub fn index(uid: Uid) -> u32 {
+    uid & MAX_INDEX
+}
+
+/// Returns the generation of the given `Uid`.
+pub fn generation(uid: Uid) -> u32 {
+    uid >> INDEX_BITS
+}
+
+/// A range of consecutive identifiers, `start` inclusive and `end` exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct UidRange {
+    pub start: Uid,
+    pub end: Uid,
+}
+
+impl UidRange {
+    pub fn contains(&self, uid: Uid) -> bool {
+        self.start <= uid && uid < self.end
+    }
+
+    pub fn len(&self) -> u32 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Returns an iterator over the identifiers in this range.
+    pub fn iter(&self) -> Range<Uid> {
+        self.start..self.end
+    }
+}
+
+/// The generation of an index and whether an identifier with that index is in use.
+#[derive(Clone, Copy)]
+struct Slot {
+    generation: u32,
+    alive: bool,
+}
+
 /// This allocator can be used to generate identifiers for an given generic type.
-/// The allocator uses an underlying hashmap for quick look ups,
-/// were the `Key` is `T` and the `Value` is `u32`.
+/// The allocator uses two underlying hashmaps for quick look ups in both directions,
+/// were the `Key` is `T` and the `Value` is `Uid`.
 ///
-/// Identifiers are generated incremental. This might change in the future to reuse space.
-pub struct UidAllocator<T: Hash + Eq> {
-    // An incremental counter used for the identifier.
-    current_id: u32,
+/// The indices of deallocated identifiers are reused with the next generation, the oldest freed
+/// index first. An identifier of a previous generation is stale, see `is_stale`.
+pub struct UidAllocator<T: Hash + Eq + Clone> {
+    // The index of the next new identifier.
+    next_index: u32,
+    // The generation of every index that was handed out or seen, by index.
+    slots: Vec<Option<Slot>>,
+    // The indices that can be reused.
+    free: VecDeque<u32>,
     // A hasmap where the generic types and assigned identifiers are stored.
-    mapping: HashMap<T, u32>,
+    mapping: HashMap<T, Uid>,
+    // The reverse of `mapping`.
+    reverse: HashMap<Uid, T>,
 }
 
-impl<T: Hash + Eq> UidAllocator<T> {
-    /// Returns a new instance of the `UidAllocator`.n
+impl<T: Hash + Eq + Clone> UidAllocator<T> {
+    /// Returns a new instance of the `UidAllocator`.
     pub fn new() -> Self {
         Self {
-            current_id: 1,
+            next_index: 1,
+            slots: Vec::new(),
+            free: VecDeque::new(),
             mapping: HashMap::new(),
+            reverse: HashMap::new(),
         }
     }
 
     /// Returns the assigned `Uid` for the given key `T`.
     pub fn get(&self, key: &T) -> Uid {
-        *self.mapping.get(key).expect("Uid should exist!")
+        self.try_get(key).expect("Uid should exist!")
+    }
+
+    /// Returns the assigned `Uid` for the given key `T`, if there is one.
+    pub fn try_get(&self, key: &T) -> Option<Uid> {
+        self.mapping.get(key).copied()
     }
 
-    pub fn get_mut(&mut self, key: &T) -> Option<&mut Uid> {
-        self.mapping.get_mut(key)
+    /// Returns the key `T` the given `Uid` is assigned to.
+    pub fn get_by_val(&self, key: &Uid) -> &T {
+        self.try_get_by_val(key).expect("Uid should exist!")
+    }
+
+    /// Returns the key `T` the given `Uid` is assigned to, if there is one.
+    pub fn try_get_by_val(&self, key: &Uid) -> Option<&T> {
+        self.reverse.get(key)
+    }
+
+    /// Assigns the `new` identifier to the key of the `original` identifier.
+    pub fn replace_val(&mut self, original: Uid, new: Uid) {
+        self.try_replace_val(original, new)
+            .expect("Uid should exist!");
+    }
+
+    /// Assigns the `new` identifier to the key of the `original` identifier, and returns that key.
+    ///
+    /// Returns `None` if the `original` identifier is not assigned. A key that had the `new`
+    /// identifier loses it. The index of the `original` identifier is reused with the next
+    /// generation.
+    pub fn try_replace_val(&mut self, original: Uid, new: Uid) -> Option<&T> {
+        let key = self.reverse.remove(&original)?;
+
+        self.insert(key.clone(), new);
+        self.observe(new);
+        self.reverse.get(&new)
     }
 
-    pub fn get_by_val(&self, key: &u32) -> &T {
-        self.mapping
-            .iter()
-            .find(|(_k, v)| key == *v)
-            .expect("Uid should exist!")
-            .0
+    /// Returns the number of keys with an identifier.
+    pub fn len(&self) -> usize {
+        self.mapping.len()
     }
 
-    pub fn replace_val(&mut self, original: u32, new: u32) {
-        *self
-            .mapping
-            .iter_mut()
-            .find(|(_k, v)| original == **v)
-            .expect("Uid should exist!")
-            .1 = new;
+    pub fn is_empty(&self) -> bool {
+        self.mapping.is_empty()
     }
 
+    pub fn contains_key(&self, key: &T) -> bool {
+        self.mapping.contains_key(key)
+    }
+
+    pub fn contains_val(&self, uid: &Uid) -> bool {
+        self.reverse.contains_key(uid)
+    }
+
+    /// Returns an iterator over the keys and their identifiers, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&T, Uid)> {
+        self.mapping.iter().map(|(key, uid)| (key, *uid))
+    }
+
+    /// Returns an iterator over the keys with an identifier, in arbitrary order.
+    pub fn keys(&self) -> impl Iterator<Item = &T> {
+        self.mapping.keys()
+    }
+
+    /// Returns an iterator over the assigned identifiers, in arbitrary order.
+    pub fn vals(&self) -> impl Iterator<Item = Uid> + '_ {
+        self.reverse.keys().copied()
+    }
+
+    /// Removes the given key, the index of its identifier is reused with the next generation.
     // Useful for when a single entity is deleted because it doesn't reconstruct the
     // entire hashmap
-    pub fn deallocate(&mut self, id: T) -> Option<u32> {
-        self.mapping.remove(&id)
+    pub fn deallocate(&mut self, id: T) -> Option<Uid> {
+        let uid = self.mapping.remove(&id)?;
+        self.reverse.remove(&uid);
+        self.release(uid);
+
+        Some(uid)
+    }
+
+    /// Assigns an identifier to the given key, a new one if `id` is `None`.
+    ///
+    /// A given identifier, for example one assigned by the server, is never handed out by this
+    /// allocator while it is in use. Returns `None` if a new identifier is needed and all
+    /// `MAX_INDEX` indices are in use.
+    pub fn allocate(&mut self, entity: T, id: Option<Uid>) -> Option<Uid> {
+        let id = match id {
+            Some(id) => {
+                self.observe(id);
+                id
+            }
+            None => self.get_and_increment()?,
+        };
+
+        self.insert(entity, id);
+        Some(id)
+    }
+
+    /// Returns a new identifier, with a freed index if there is one.
+    ///
+    /// Returns `None` if all `MAX_INDEX` indices are in use.
+    pub fn get_and_increment(&mut self) -> Option<Uid> {
+        while let Some(index) = self.free.pop_front() {
+            // A freed index can be taken by an identifier that was given to `allocate`.
+            if let Some(slot) = self.slots[index as usize]
+                .as_mut()
+                .filter(|slot| !slot.alive)
+            {
+                slot.alive = true;
+                return Some(compose(index, slot.generation));
+            }
+        }
+
+        let index = self.next_index;
+        if index > MAX_INDEX {
+            return None;
+        }
+
+        self.observe(compose(index, 0));
+        Some(index)
+    }
+
+    /// Returns true if the given identifier was deallocated, or its index was reused since.
+    ///
+    /// An identifier that this allocator never saw is not stale.
+    pub fn is_stale(&self, uid: Uid) -> bool {
+        match self.slots.get(index(uid) as usize) {
+            Some(Some(slot)) => !slot.alive || slot.generation != generation(uid),
+            _ => false,
+        }
+    }
+
+    /// Reserves `count` new identifiers, for example for the entities a client spawns before the
+    /// server confirmed them.
+    ///
+    /// The identifiers are not handed out by this allocator, they are in use once they are given
+    /// to `allocate`. Returns `None` if there are less than `count` unused indices left.
+    pub fn reserve(&mut self, count: u32) -> Option<UidRange> {
+        let start = self.next_index;
+        if count > MAX_INDEX + 1 - start {
+            return None;
+        }
+
+        // Reserved indices are fresh, so their identifiers are of the first generation and equal
+        // to their index. The end is exclusive, it can be one past `MAX_INDEX`.
+        self.next_index += count;
+
+        Some(UidRange {
+            start,
+            end: start + count,
+        })
+    }
+
+    /// Hands the reserved identifiers that were not given to `allocate` back to this allocator,
+    /// their indices are reused with the next generation.
+    ///
+    /// Identifiers of the range that are in use, or that were deallocated already, are skipped.
+    pub fn release_reserved(&mut self, range: UidRange) {
+        for uid in range.iter() {
+            let index = index(uid);
+
+            if index as usize >= self.slots.len() {
+                self.slots.resize(index as usize + 1, None);
+            }
+
+            if self.slots[index as usize].is_none() {
+                self.slots[index as usize] = Some(Slot {
+                    generation: (generation(uid) + 1) & MAX_GENERATION,
+                    alive: false,
+                });
+                self.free.push_back(index);
+            }
+        }
     }
 
-    pub fn allocate(&mut self, entity: T, id: Option<u32>) -> Uid {
-        let id = id.unwrap_or_else(|| self.get_and_increment());
+    /// Assigns the identifier to the key, keys and identifiers that were assigned before are
+    /// unassigned so that both maps stay consistent.
+    ///
+    /// The index of the previous identifier of the key is reused with the next generation.
+    fn insert(&mut self, key: T, uid: Uid) {
+        if let Some(previous) = self.mapping.insert(key.clone(), uid) {
+            if previous != uid {
+                self.reverse.remove(&previous);
+                self.release(previous);
+            }
+        }
+
+        if let Some(previous) = self.reverse.insert(uid, key) {
+            if self.reverse[&uid] != previous {
+                self.mapping.remove(&previous);
+            }
+        }
+    }
 
-        self.mapping.insert(entity, id);
-        id
+    /// Marks the index of the given identifier as unused, and queues it for reuse with the next
+    /// generation.
+    fn release(&mut self, uid: Uid) {
+        if let Some(Some(slot)) = self.slots.get_mut(index(uid) as usize) {
+            if slot.alive && slot.generation == generation(uid) {
+                slot.alive = false;
+                slot.generation = (slot.generation + 1) & MAX_GENERATION;
+                self.free.push_back(index(uid));
+            }
+        }
     }
 
-    pub fn get_and_increment(&mut self) -> u32 {
-        let id = self.current_id;
-        self.current_id += 1;
-        id
+    /// Marks the index of the given identifier as in use with the generation of the identifier.
+    fn observe(&mut self, uid: Uid) {
+        let index = index(uid);
+
+        if index as usize >= self.slots.len() {
+            self.slots.resize(index as usize + 1, None);
+        }
+
+        // Skipped indices are never handed out, they may still be given to `allocate`.
+        if index >= self.next_index {
+            self.next_index = index + 1;
+        }
+
+        self.slots[index as usize] = Some(Slot {
+            generation: generation(uid),
+            alive: true,
+        });
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use crate::uid::{compose, generation, index, UidAllocator, MAX_GENERATION, MAX_INDEX};
+
+    #[test]
+    fn deallocated_index_is_reused_with_next_generation() {
+        let mut allocator = UidAllocator::new();
+
+        let first = allocator.allocate("first", None).unwrap();
+        let second = allocator.allocate("second", None).unwrap();
+        assert_eq!((first, second), (1, 2));
+
+        assert_eq!(allocator.deallocate("first"), Some(first));
+        let third = allocator.allocate("third", None).unwrap();
+
+        assert_eq!(index(third), index(first));
+        assert_eq!(generation(third), generation(first) + 1);
+        assert_eq!(allocator.allocate("fourth", None).unwrap(), 3);
+    }
+
+    #[test]
+    fn stale_identifiers_are_detected() {
+        let mut allocator = UidAllocator::new();
+
+        let first = allocator.allocate("first", None).unwrap();
+        assert!(!allocator.is_stale(first));
+
+        allocator.deallocate("first");
+        assert!(allocator.is_stale(first));
+
+        let reused = allocator.allocate("reused", None).unwrap();
+        assert!(allocator.is_stale(first));
+        assert!(!allocator.is_stale(reused));
+        assert!(!allocator.is_stale(100));
+    }
+
+    #[test]
+    fn reserved_identifiers_are_not_handed_out() {
+        let mut allocator = UidAllocator::new();
+        allocator.allocate("first", None);
+
+        let reserved = allocator.reserve(3).unwrap();
+        assert_eq!(reserved.iter().collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert!(!allocator.is_stale(3));
+
+        assert_eq!(allocator.allocate("second", None).unwrap(), 5);
+        assert_eq!(allocator.allocate("predicted", Some(3)).unwrap(), 3);
+        assert!(!allocator.is_stale(3));
+    }
+
+    #[test]
+    fn released_reserved_identifiers_are_reused() {
+        let mut allocator = UidAllocator::new();
+
+        let reserved = allocator.reserve(3).unwrap();
+        allocator.allocate("predicted", Some(reserved.start));
+        allocator.release_reserved(reserved);
+
+        assert!(!allocator.is_stale(reserved.start));
+        assert!(allocator.is_stale(reserved.start + 1));
+
+        let reused = allocator.allocate("reused", None).unwrap();
+        assert_eq!(index(reused), reserved.start + 1);
+        assert_eq!(generation(reused), 1);
+        assert_eq!(
+            index(allocator.allocate("next", None).unwrap()),
+            reserved.start + 2
+        );
+        assert_eq!(allocator.allocate("new", None), Some(reserved.end));
+    }
+
+    #[test]
+    fn given_identifiers_are_not_handed_out() {
+        let mut allocator = UidAllocator::new();
+
+        let given = compose(2, 5);
+        allocator.allocate("given", Some(given));
+        assert!(allocator.is_stale(compose(2, 4)));
+
+        let first = allocator.allocate("first", None).unwrap();
+        assert_eq!(first, 3);
+
+        allocator.deallocate("first");
+        // The freed index is taken by a given identifier before it is reused.
+        allocator.allocate("taken", Some(compose(index(first), 1)));
+
+        assert_eq!(allocator.allocate("new", None).unwrap(), 4);
+    }
+
+    #[test]
+    fn reverse_lookup_stays_consistent() {
+        let mut allocator = UidAllocator::new();
+
+        let first = allocator.allocate("first", None).unwrap();
+        let second = allocator.allocate("second", None).unwrap();
+        assert_eq!(allocator.get_by_val(&first), &"first");
+
+        allocator.replace_val(first, 10);
+        assert_eq!(allocator.try_get_by_val(&first), None);
+        assert_eq!(allocator.get_by_val(&10), &"first");
+        assert_eq!(allocator.get(&"first"), 10);
+
+        // A key that had the new identifier loses it.
+        assert_eq!(allocator.try_replace_val(10, second), Some(&"first"));
+        assert_eq!(allocator.try_get(&"second"), None);
+        assert_eq!(allocator.get_by_val(&second), &"first");
+        assert_eq!(allocator.len(), 1);
+
+        allocator.deallocate("first");
+        assert!(!allocator.contains_val(&second));
+        assert_eq!(allocator.try_replace_val(second, 11), None);
+        assert!(allocator.is_empty());
+    }
+
+    #[test]
+    fn replaced_identifier_is_reused_with_next_generation() {
+        let mut allocator = UidAllocator::new();
+
+        let first = allocator.allocate("first", None).unwrap();
+        allocator.replace_val(first, 10);
+        assert!(allocator.is_stale(first));
+
+        // A key that is allocated again gives up its previous identifier as well.
+        let second = allocator.allocate("second", None).unwrap();
+        assert_eq!(index(second), index(first));
+        allocator.allocate("second", Some(20));
+        assert!(allocator.is_stale(second));
+
+        let third = allocator.allocate("third", None).unwrap();
+        assert_eq!(index(third), index(first));
+        assert_eq!(generation(third), generation(first) + 2);
+    }
+
+    #[test]
+    fn iterates_keys_and_identifiers() {
+        let mut allocator = UidAllocator::new();
+        allocator.allocate("first", None);
+        allocator.allocate("second", None);
+
+        let mut pairs = allocator.iter().collect::<Vec<_>>();
+        pairs.sort();
+        assert_eq!(pairs, vec![(&"first", 1), (&"second", 2)]);
+
+        let mut vals = allocator.vals().collect::<Vec<_>>();
+        vals.sort();
+        assert_eq!(vals, vec![1, 2]);
+        assert_eq!(allocator.keys().count(), 2);
+    }
+
+    #[test]
+    fn generation_wraps_around() {
+        let mut allocator = UidAllocator::new();
+        allocator.allocate("last", Some(compose(1, MAX_GENERATION)));
+
+        allocator.deallocate("last");
+
+        assert_eq!(allocator.allocate("wrapped", None).unwrap(), compose(1, 0));
+    }
+
+    #[test]
+    fn exhausted_indices_are_reported() {
+        let mut allocator = UidAllocator::new();
+        allocator.allocate("last", Some(compose(MAX_INDEX - 1, 0)));
+
+        assert_eq!(allocator.reserve(2), None);
+        assert!(allocator.reserve(1).is_some());
+        assert_eq!(allocator.allocate("new", None), None);
+        assert!(!allocator.contains_key(&"new"));
+        assert_eq!(allocator.get_and_increment(), None);
+    }
+}